@@ -0,0 +1,13 @@
+-- 刷新凭证会话表，每个refresh_token对应一行，同一次登陆轮换出的凭证属于同一个family
+CREATE TABLE igame.session (
+    id TEXT PRIMARY KEY,
+    family_id TEXT NOT NULL,
+    user_id INT4 NOT NULL REFERENCES igame.user (id) ON DELETE CASCADE,
+    used_at TIMESTAMPTZ,
+    revoked_at TIMESTAMPTZ,
+    expire_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX session_family_id_idx ON igame.session (family_id);
+CREATE INDEX session_user_id_idx ON igame.session (user_id);
@@ -0,0 +1,5 @@
+-- 记录会话的设备信息，login_at为会话创建时间，created_at为该凭证签发（即最近一次使用）的时间
+ALTER TABLE igame.session
+    ADD COLUMN device TEXT NOT NULL DEFAULT '',
+    ADD COLUMN ip TEXT NOT NULL DEFAULT '',
+    ADD COLUMN login_at TIMESTAMPTZ NOT NULL DEFAULT now();
@@ -0,0 +1,3 @@
+-- 记录验证码输错的次数，达到上限后验证码失效
+ALTER TABLE igame.verify_email
+    ADD COLUMN attempts INT2 NOT NULL DEFAULT 0;
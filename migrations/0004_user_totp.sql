@@ -0,0 +1,21 @@
+-- 用户的两步验证密钥，确认绑定前enabled为false
+CREATE TABLE igame.user_totp (
+    user_id INT4 PRIMARY KEY REFERENCES igame.user (id) ON DELETE CASCADE,
+    secret TEXT NOT NULL,
+    enabled BOOL NOT NULL DEFAULT false,
+    -- 最近一次验证通过的时间步，防止同一个验证码被重复使用
+    last_used_step INT8,
+    enabled_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- 两步验证的恢复码，只保存哈希值，每个恢复码只能使用一次
+CREATE TABLE igame.user_recovery_code (
+    id SERIAL4 PRIMARY KEY,
+    user_id INT4 NOT NULL REFERENCES igame.user (id) ON DELETE CASCADE,
+    code_hash BYTEA NOT NULL,
+    used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX user_recovery_code_user_id_idx ON igame.user_recovery_code (user_id);
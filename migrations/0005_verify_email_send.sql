@@ -0,0 +1,11 @@
+-- 验证邮件的发送记录，重新发送同一个验证码时也会记录一行，用于限制发送频率
+CREATE TABLE igame.verify_email_send (
+    id SERIAL4 PRIMARY KEY,
+    verify_email_id INT4 NOT NULL REFERENCES igame.verify_email (id) ON DELETE CASCADE,
+    addr TEXT NOT NULL,
+    ip TEXT NOT NULL,
+    sent_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX verify_email_send_addr_idx ON igame.verify_email_send (addr, sent_at);
+CREATE INDEX verify_email_send_ip_idx ON igame.verify_email_send (ip, sent_at);
@@ -0,0 +1,3 @@
+-- 记录最近一次修改昵称的时间，用于限制修改频率
+ALTER TABLE igame.user
+    ADD COLUMN nick_name_changed_at TIMESTAMPTZ;
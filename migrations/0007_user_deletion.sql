@@ -0,0 +1,6 @@
+-- delete_at为申请注销后计划清除数据的时间，deleted_at为实际完成匿名化的时间
+ALTER TABLE igame.user
+    ADD COLUMN delete_at TIMESTAMPTZ,
+    ADD COLUMN deleted_at TIMESTAMPTZ;
+
+CREATE INDEX user_delete_at_idx ON igame.user (delete_at) WHERE delete_at IS NOT NULL;
@@ -0,0 +1,11 @@
+-- 绑定到用户的第三方身份，provider为配置文件中oidc提供者的id，subject为该提供者下的用户唯一标识
+CREATE TABLE igame.user_identity (
+    id SERIAL PRIMARY KEY,
+    user_id INT4 NOT NULL REFERENCES igame.user (id) ON DELETE CASCADE,
+    provider TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    email TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (provider, subject),
+    UNIQUE (user_id, provider)
+);
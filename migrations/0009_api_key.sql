@@ -0,0 +1,15 @@
+-- 用户创建的API密钥，只保存密钥的sha256摘要，scopes为允许使用的权限名，即igame.permission.key
+CREATE TABLE igame.api_key (
+    id SERIAL PRIMARY KEY,
+    user_id INT4 NOT NULL REFERENCES igame.user (id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    key_hash BYTEA NOT NULL UNIQUE,
+    key_prefix TEXT NOT NULL,
+    scopes TEXT[] NOT NULL DEFAULT '{}',
+    expire_at TIMESTAMPTZ,
+    used_at TIMESTAMPTZ,
+    revoked_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX api_key_user_id_idx ON igame.api_key (user_id);
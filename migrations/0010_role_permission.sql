@@ -0,0 +1,76 @@
+-- 权限改为数据行，角色通过igame.role_permission关联权限，新增权限或角色不再需要修改表结构
+-- permission.key与model::role::Permission一一对应，设置后不要修改
+CREATE TABLE igame.permission (
+    id SERIAL PRIMARY KEY,
+    key TEXT NOT NULL UNIQUE,
+    description TEXT NOT NULL DEFAULT ''
+);
+
+INSERT INTO igame.permission (key, description) VALUES
+    ('get_user', '获取任意用户的信息'),
+    ('create_user', '创建用户'),
+    ('send_email', '发送任意邮件'),
+    ('create_notice', '创建通知'),
+    ('free_download', '免费下载资源'),
+    ('free_install', '免费安装'),
+    ('ignore_exp', '无视等级限制'),
+    ('manage_role', '管理角色及其权限，授予与撤销用户的角色');
+
+CREATE TABLE igame.role_permission (
+    role_id INT4 NOT NULL REFERENCES igame.role (id) ON DELETE CASCADE,
+    permission_id INT4 NOT NULL REFERENCES igame.permission (id) ON DELETE CASCADE,
+    PRIMARY KEY (role_id, permission_id)
+);
+
+-- 迁移原有的权限列
+INSERT INTO igame.role_permission (role_id, permission_id)
+SELECT r.id, p.id
+FROM igame.role AS r
+INNER JOIN igame.permission AS p
+ON (p.key = 'get_user' AND r.get_user)
+OR (p.key = 'create_user' AND r.create_user)
+OR (p.key = 'send_email' AND r.send_email)
+OR (p.key = 'create_notice' AND r.create_notice)
+OR (p.key = 'free_download' AND r.free_download)
+OR (p.key = 'free_install' AND r.free_install)
+OR (p.key = 'ignore_exp' AND r.ignore_exp)
+-- 管理角色的权限默认授予管理员
+OR (p.key = 'manage_role' AND r.id = 1);
+
+ALTER TABLE igame.role
+    DROP COLUMN get_user,
+    DROP COLUMN create_user,
+    DROP COLUMN send_email,
+    DROP COLUMN create_notice,
+    DROP COLUMN free_download,
+    DROP COLUMN free_install,
+    DROP COLUMN ignore_exp;
+
+-- 角色使用key在代码中引用，内置角色为admin、user、vip，运行时创建的角色也需要唯一的key
+ALTER TABLE igame.role ADD COLUMN key TEXT;
+UPDATE igame.role SET key = CASE id
+    WHEN 1 THEN 'admin'
+    WHEN 2 THEN 'user'
+    WHEN 3 THEN 'vip'
+    ELSE 'role_' || id
+END;
+ALTER TABLE igame.role
+    ALTER COLUMN key SET NOT NULL,
+    ADD CONSTRAINT role_key_key UNIQUE (key);
+
+-- 运行时创建角色需要自动生成id
+DO $$
+BEGIN
+    IF NOT EXISTS (
+        SELECT 1 FROM information_schema.columns
+        WHERE table_schema = 'igame' AND table_name = 'role' AND column_name = 'id'
+        AND (column_default IS NOT NULL OR is_identity = 'YES')
+    ) THEN
+        ALTER TABLE igame.role ALTER COLUMN id ADD GENERATED BY DEFAULT AS IDENTITY;
+        PERFORM setval(
+            pg_get_serial_sequence('igame.role', 'id'),
+            (SELECT COALESCE(max(id), 0) + 1 FROM igame.role),
+            false
+        );
+    END IF;
+END $$;
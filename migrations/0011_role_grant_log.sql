@@ -0,0 +1,13 @@
+-- 用户角色的变更记录，action为grant、extend或revoke，operator_id为空表示由系统操作
+CREATE TABLE igame.role_grant_log (
+    id SERIAL PRIMARY KEY,
+    user_id INT4 NOT NULL REFERENCES igame.user (id) ON DELETE CASCADE,
+    role_id INT4 NOT NULL REFERENCES igame.role (id) ON DELETE CASCADE,
+    action TEXT NOT NULL,
+    previous_expire_at TIMESTAMPTZ,
+    expire_at TIMESTAMPTZ,
+    operator_id INT4 REFERENCES igame.user (id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX role_grant_log_user_id_idx ON igame.role_grant_log (user_id, created_at);
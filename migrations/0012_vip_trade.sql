@@ -0,0 +1,3 @@
+-- 购买会员的交易没有对应的资源，记录购买的套餐
+ALTER TABLE igame.trade ALTER COLUMN resource_id DROP NOT NULL;
+ALTER TABLE igame.trade ADD COLUMN vip_plan TEXT;
@@ -0,0 +1,2 @@
+-- 无限币余额不能为负数，NOT VALID只约束之后的写入，已有的负数余额需要人工处理后再执行VALIDATE CONSTRAINT
+ALTER TABLE igame.user ADD CONSTRAINT user_coin_non_negative CHECK (coin >= 0) NOT VALID;
@@ -0,0 +1,30 @@
+-- 无限币与经验值的流水，只能追加，每个用户每种currency的delta之和应等于igame.user中的余额
+-- currency为coin或exp，balance为本次变化后的余额，ref_id为关联记录的ID，例如igame.trade.id
+CREATE TABLE igame.ledger (
+    id BIGSERIAL PRIMARY KEY,
+    user_id INT4 NOT NULL REFERENCES igame.user (id),
+    currency TEXT NOT NULL,
+    delta INT4 NOT NULL,
+    balance INT4 NOT NULL,
+    reason TEXT NOT NULL,
+    ref_id INT4,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX ledger_user_id_idx ON igame.ledger (user_id, currency);
+
+CREATE FUNCTION igame.ledger_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'igame.ledger只能追加，不能修改或删除';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER ledger_append_only
+BEFORE UPDATE OR DELETE ON igame.ledger
+FOR EACH ROW EXECUTE FUNCTION igame.ledger_append_only();
+
+-- 已有的余额记为期初流水
+INSERT INTO igame.ledger (user_id, currency, delta, balance, reason)
+SELECT id, 'coin', coin, coin, 'opening' FROM igame.user WHERE coin <> 0
+UNION ALL
+SELECT id, 'exp', exp, exp, 'opening' FROM igame.user WHERE exp <> 0;
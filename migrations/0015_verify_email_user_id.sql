@@ -0,0 +1,3 @@
+-- 修改邮箱的验证邮件记录发起修改的用户，只有该用户可以使用其中的验证码
+ALTER TABLE igame.verify_email
+    ADD COLUMN user_id INT4 REFERENCES igame.user (id);
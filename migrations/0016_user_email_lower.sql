@@ -0,0 +1,16 @@
+-- 邮箱地址不区分大小写，代码中统一使用email::normalize_addr处理后的地址，并通过lower(email)查询
+-- 只有大小写不同的重复账号保留最近登陆的一个，其余账号的邮箱改为不可投递的占位地址，需要人工处理
+UPDATE igame.user AS u
+SET email = lower(btrim(u.email)) || '.dup' || u.id || '.invalid'
+FROM (
+    SELECT id, row_number() OVER (
+        PARTITION BY lower(btrim(email))
+        ORDER BY login_at DESC NULLS LAST, id
+    ) AS n
+    FROM igame.user
+) AS d
+WHERE u.id = d.id AND d.n > 1;
+
+UPDATE igame.user SET email = lower(btrim(email)) WHERE email <> lower(btrim(email));
+
+CREATE UNIQUE INDEX user_email_lower_key ON igame.user (lower(email));
@@ -0,0 +1,69 @@
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{web, Error, HttpMessage};
+use deadpool_postgres::{Client, Pool};
+use futures::future::{ok, Ready};
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+
+use crate::error::ResponseError;
+use crate::util::{api_key, req_parse::get_api_key};
+
+// 认证请求头中的API密钥，成功后将ApiKeyIdentity写入请求的extensions供req_parse读取
+// 没有携带API密钥的请求不做任何处理
+pub struct ApiKeyAuth;
+
+impl<S, B> Transform<S, ServiceRequest> for ApiKeyAuth
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = ApiKeyAuthMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(ApiKeyAuthMiddleware {
+            service: Rc::new(service),
+        })
+    }
+}
+
+#[doc(hidden)]
+pub struct ApiKeyAuthMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for ApiKeyAuthMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    actix_web::dev::forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let key = match get_api_key(req.parts_mut().0) {
+            Some(v) => v.to_string(),
+            None => return Box::pin(self.service.call(req)),
+        };
+        let service = self.service.clone();
+        Box::pin(async move {
+            let db_pool = req.app_data::<web::Data<Pool>>().cloned().ok_or_else(|| {
+                ResponseError::unexpected_err("认证API密钥失败", "找不到数据库连接池")
+            })?;
+            let client: Client = db_pool.get().await.map_err(ResponseError::from)?;
+            let identity = api_key::authenticate(&client, &key).await?;
+            drop(client);
+            req.extensions_mut().insert(identity);
+            service.call(req).await
+        })
+    }
+}
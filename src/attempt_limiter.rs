@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::config::{LoginLimitConfig, GLOBAL_CONFIG};
+use crate::error::ResponseError;
+
+// 超过该数量后清理过期的记录
+const PRUNE_THRESHOLD: usize = 10000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AttemptKind {
+    Account,
+    Ip,
+}
+
+#[derive(Clone)]
+pub struct AttemptLimiterShare {
+    limiter: Arc<Mutex<AttemptLimiter>>,
+}
+
+impl AttemptLimiterShare {
+    pub fn new() -> Self {
+        Self {
+            limiter: Arc::new(Mutex::new(AttemptLimiter::new(
+                GLOBAL_CONFIG.login_limit.clone(),
+            ))),
+        }
+    }
+
+    // 检查账号或ip是否处于锁定中
+    pub fn check(&self, account: Option<&str>, ip: &str) -> Result<(), ResponseError> {
+        let limiter = self.limiter.lock().unwrap();
+        let now = SystemTime::now();
+        let mut retry_after = limiter.locked_secs(AttemptKind::Ip, ip, now);
+        if let Some(account) = account {
+            retry_after = retry_after.max(limiter.locked_secs(AttemptKind::Account, account, now));
+        }
+        if retry_after > 0 {
+            return Err(ResponseError::too_many_attempts_err(
+                &format!("尝试次数过多，请在{}秒后重试", retry_after),
+                retry_after,
+                &format!("[账号: {:?}, ip: {}]处于锁定中", account, ip),
+            ));
+        }
+        Ok(())
+    }
+
+    // 记录一次失败
+    pub fn record_failure(&self, account: Option<&str>, ip: &str) {
+        let mut limiter = self.limiter.lock().unwrap();
+        let now = SystemTime::now();
+        limiter.record_failure(AttemptKind::Ip, ip, now);
+        if let Some(account) = account {
+            limiter.record_failure(AttemptKind::Account, account, now);
+        }
+    }
+
+    // 记录一次成功，清除账号的失败记录
+    pub fn record_success(&self, account: &str) {
+        self.limiter
+            .lock()
+            .unwrap()
+            .clear(AttemptKind::Account, account);
+    }
+}
+
+struct AttemptRecord {
+    failures: u32,
+    last_failure: SystemTime,
+    locked_until: SystemTime,
+}
+
+pub struct AttemptLimiter {
+    config: LoginLimitConfig,
+    records: HashMap<(AttemptKind, String), AttemptRecord>,
+}
+
+impl AttemptLimiter {
+    pub fn new(config: LoginLimitConfig) -> Self {
+        Self {
+            config,
+            records: HashMap::new(),
+        }
+    }
+
+    fn locked_secs(&self, kind: AttemptKind, key: &str, now: SystemTime) -> u64 {
+        match self.records.get(&(kind, key.to_string())) {
+            Some(record) => match record.locked_until.duration_since(now) {
+                // 向上取整，避免客户端过早重试
+                Ok(v) if !v.is_zero() => v.as_secs() + u64::from(v.subsec_nanos() > 0),
+                _ => 0,
+            },
+            None => 0,
+        }
+    }
+
+    fn record_failure(&mut self, kind: AttemptKind, key: &str, now: SystemTime) {
+        if self.records.len() > PRUNE_THRESHOLD {
+            self.prune(now);
+        }
+        let config = &self.config;
+
+        let record = self
+            .records
+            .entry((kind, key.to_string()))
+            .or_insert(AttemptRecord {
+                failures: 0,
+                last_failure: now,
+                locked_until: now,
+            });
+        // 距离上次失败已经很久，重新计数
+        if record.last_failure + Duration::from_secs(config.reset_after) < now {
+            record.failures = 0;
+        }
+        record.failures += 1;
+        record.last_failure = now;
+
+        let free_attempts = match kind {
+            AttemptKind::Account => config.account_free_attempts,
+            AttemptKind::Ip => config.ip_free_attempts,
+        };
+        // 超过允许的次数后，每多失败一次锁定时间翻倍
+        if record.failures > free_attempts {
+            let exponent = (record.failures - free_attempts - 1).min(16);
+            let lockout = config
+                .base_lockout
+                .saturating_mul(1 << exponent)
+                .min(config.max_lockout);
+            record.locked_until = now + Duration::from_secs(lockout);
+            tracing::warn!(
+                "[{:?}: {}]连续失败{}次，锁定{}秒",
+                kind,
+                key,
+                record.failures,
+                lockout
+            );
+        }
+    }
+
+    fn clear(&mut self, kind: AttemptKind, key: &str) {
+        self.records.remove(&(kind, key.to_string()));
+    }
+
+    fn prune(&mut self, now: SystemTime) {
+        let reset_after = Duration::from_secs(self.config.reset_after);
+        self.records.retain(|_, record| {
+            record.locked_until > now || record.last_failure + reset_after > now
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter() -> AttemptLimiter {
+        AttemptLimiter::new(LoginLimitConfig {
+            account_free_attempts: 3,
+            ip_free_attempts: 10,
+            base_lockout: 30,
+            max_lockout: 300,
+            reset_after: 3600,
+        })
+    }
+
+    fn secs(v: u64) -> Duration {
+        Duration::from_secs(v)
+    }
+
+    #[test]
+    fn free_attempts_do_not_lock() {
+        let mut limiter = limiter();
+        let now = SystemTime::now();
+        for _ in 0..3 {
+            limiter.record_failure(AttemptKind::Account, "a", now);
+        }
+        assert_eq!(limiter.locked_secs(AttemptKind::Account, "a", now), 0);
+        // ip的允许次数单独计算
+        for _ in 0..4 {
+            limiter.record_failure(AttemptKind::Ip, "1.1.1.1", now);
+        }
+        assert_eq!(limiter.locked_secs(AttemptKind::Ip, "1.1.1.1", now), 0);
+    }
+
+    #[test]
+    fn lockout_doubles_up_to_max() {
+        let mut limiter = limiter();
+        let now = SystemTime::now();
+        for _ in 0..3 {
+            limiter.record_failure(AttemptKind::Account, "a", now);
+        }
+        for lockout in [30, 60, 120, 240, 300, 300] {
+            limiter.record_failure(AttemptKind::Account, "a", now);
+            assert_eq!(limiter.locked_secs(AttemptKind::Account, "a", now), lockout);
+        }
+        // 剩余时间不足1秒时向上取整
+        assert_eq!(
+            limiter.locked_secs(
+                AttemptKind::Account,
+                "a",
+                now + Duration::from_millis(299_500)
+            ),
+            1
+        );
+        assert_eq!(
+            limiter.locked_secs(AttemptKind::Account, "a", now + secs(300)),
+            0
+        );
+    }
+
+    #[test]
+    fn failures_reset_after_quiet_period() {
+        let mut limiter = limiter();
+        let now = SystemTime::now();
+        for _ in 0..4 {
+            limiter.record_failure(AttemptKind::Account, "a", now);
+        }
+        assert!(limiter.locked_secs(AttemptKind::Account, "a", now) > 0);
+        let later = now + secs(3601);
+        limiter.record_failure(AttemptKind::Account, "a", later);
+        assert_eq!(limiter.locked_secs(AttemptKind::Account, "a", later), 0);
+    }
+
+    #[test]
+    fn success_clears_account_failures() {
+        let mut limiter = limiter();
+        let now = SystemTime::now();
+        for _ in 0..4 {
+            limiter.record_failure(AttemptKind::Account, "a", now);
+        }
+        limiter.clear(AttemptKind::Account, "a");
+        assert_eq!(limiter.locked_secs(AttemptKind::Account, "a", now), 0);
+        limiter.record_failure(AttemptKind::Account, "a", now);
+        assert_eq!(limiter.locked_secs(AttemptKind::Account, "a", now), 0);
+    }
+
+    #[test]
+    fn prune_keeps_locked_and_recent_records() {
+        let mut limiter = limiter();
+        let now = SystemTime::now();
+        limiter.record_failure(AttemptKind::Account, "old", now);
+        for _ in 0..4 {
+            limiter.record_failure(AttemptKind::Account, "locked", now + secs(3500));
+        }
+        limiter.record_failure(AttemptKind::Account, "recent", now + secs(3500));
+        limiter.prune(now + secs(3700));
+        let mut keys: Vec<_> = limiter
+            .records
+            .keys()
+            .map(|(_, key)| key.as_str())
+            .collect();
+        keys.sort_unstable();
+        assert_eq!(keys, ["locked", "recent"]);
+    }
+}
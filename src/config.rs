@@ -31,6 +31,36 @@ pub struct Config {
     pub jwt: JWTConfig,
     pub email: EmailConfig,
     pub pgsql: SQLConfig,
+    #[serde(default)]
+    pub password: PasswordConfig,
+    #[serde(default)]
+    pub login_limit: LoginLimitConfig,
+    #[serde(default)]
+    pub verify_email: VerifyEmailConfig,
+    #[serde(default)]
+    pub totp: TotpConfig,
+    #[serde(default)]
+    pub captcha: CaptchaConfig,
+    #[serde(default)]
+    pub profile: ProfileConfig,
+    #[serde(default)]
+    pub avatar: AvatarConfig,
+    #[serde(default)]
+    pub account: AccountConfig,
+    #[serde(default)]
+    pub magic_login: MagicLoginConfig,
+    #[serde(default)]
+    pub oidc: OidcConfig,
+    #[serde(default)]
+    pub api_key: ApiKeyConfig,
+    #[serde(default)]
+    pub permission: PermissionConfig,
+    #[serde(default)]
+    pub vip: VipConfig,
+    #[serde(default)]
+    pub ledger: LedgerConfig,
+    #[serde(default = "default_rate_limit")]
+    pub rate_limit: Vec<RateLimitRule>,
     pub msgraph: Vec<MSGraphConfig>,
     #[serde(skip)]
     file_path: String,
@@ -43,6 +73,10 @@ pub struct AppConfig {
     pub thread: usize,
     pub log_level: String,
     pub log_format: String,
+    // 可信的反向代理地址，例如"127.0.0.1"或"10.0.0.0/8"
+    // 只有直接连接的地址在列表中时才使用X-Forwarded-For头部中的客户端地址
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -62,6 +96,452 @@ pub struct JWTConfig {
     pub token_secret: String,
     pub access_token_expire: u64,
     pub refresh_token_expire: u64,
+    // 是否接受使用token_secret签名的凭证，未设置时只有没有配置signing_key才接受
+    // 迁移到非对称密钥时可以暂时开启，等旧凭证过期后应关闭
+    #[serde(default)]
+    pub accept_token_secret: Option<bool>,
+    // 从数据库同步已吊销会话的间隔，单位秒，多个实例部署时其他实例吊销的会话在该时间内生效
+    #[serde(default = "default_revoked_session_sync_interval")]
+    pub revoked_session_sync_interval: u64,
+    // 以下两项会序列化为表，必须放在其他字段之后，否则写回配置文件时toml序列化失败
+    // 当前用于签名的非对称密钥，未配置时使用token_secret以HS256签名
+    #[serde(default)]
+    pub signing_key: Option<JwtKeyConfig>,
+    // 轮换后仍然接受的旧密钥，只需要公钥，等旧凭证全部过期后再移除
+    #[serde(default)]
+    pub verification_keys: Vec<JwtKeyConfig>,
+}
+
+fn default_revoked_session_sync_interval() -> u64 {
+    10
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JwtKeyAlgorithm {
+    EdDSA,
+    RS256,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JwtKeyConfig {
+    // 写入凭证头部的kid，每个密钥必须不同
+    pub kid: String,
+    pub algorithm: JwtKeyAlgorithm,
+    // PEM格式的公钥，EdDSA与RS256都使用SPKI格式，RS256也可以使用PKCS#1格式
+    pub public_key_path: String,
+    // PEM格式的PKCS#8私钥，只有signing_key需要，RS256也可以使用PKCS#1格式
+    #[serde(default)]
+    pub private_key_path: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct PasswordConfig {
+    // argon2id的内存开销，单位KiB
+    pub argon2_memory_cost: u32,
+    // argon2id的迭代次数
+    pub argon2_time_cost: u32,
+    // argon2id的并行度
+    pub argon2_parallelism: u32,
+    // 密码的最短与最长字符数
+    pub min_len: usize,
+    pub max_len: usize,
+}
+
+impl Default for PasswordConfig {
+    fn default() -> Self {
+        Self {
+            argon2_memory_cost: 19456,
+            argon2_time_cost: 2,
+            argon2_parallelism: 1,
+            min_len: 8,
+            max_len: 128,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LoginLimitConfig {
+    // 同一账号允许连续失败的次数，超过后开始锁定
+    pub account_free_attempts: u32,
+    // 同一ip允许连续失败的次数，超过后开始锁定
+    pub ip_free_attempts: u32,
+    // 首次锁定的时长，之后每次失败翻倍，单位秒
+    pub base_lockout: u64,
+    // 最长锁定时长，单位秒
+    pub max_lockout: u64,
+    // 距离上次失败超过该时长后重新计数，单位秒
+    pub reset_after: u64,
+}
+
+impl Default for LoginLimitConfig {
+    fn default() -> Self {
+        Self {
+            account_free_attempts: 5,
+            ip_free_attempts: 20,
+            base_lockout: 30,
+            max_lockout: 15 * 60,
+            reset_after: 60 * 60,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct VerifyEmailConfig {
+    // 每个验证码允许输错的次数，达到后验证码失效
+    pub max_attempts: i16,
+    // 同一邮箱两次发送之间的最短间隔，单位秒
+    pub resend_cooldown: i64,
+    // 同一邮箱每天最多发送的次数
+    pub daily_limit_per_addr: i64,
+    // 同一ip每天最多发送的次数
+    pub daily_limit_per_ip: i64,
+    // 在该时长内生成且未使用的验证码会被重新发送，而不是生成新的验证码，单位秒
+    pub reuse_window: i64,
+    // 不允许使用的邮箱域名，同时匹配其子域名
+    pub deny_domains: Vec<String>,
+}
+
+impl Default for VerifyEmailConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            resend_cooldown: 60,
+            daily_limit_per_addr: 10,
+            daily_limit_per_ip: 30,
+            reuse_window: 30 * 60,
+            deny_domains: vec![
+                "10minutemail.com".to_string(),
+                "guerrillamail.com".to_string(),
+                "mailinator.com".to_string(),
+                "temp-mail.org".to_string(),
+                "yopmail.com".to_string(),
+            ],
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TotpConfig {
+    // 显示在验证器应用中的服务名称
+    pub issuer: String,
+    // 为true时，管理员未启用两步验证前不具备管理员权限
+    pub require_for_admin: bool,
+    // 登陆第二步所用凭证的有效期，单位秒
+    pub challenge_expire: u64,
+    // 每次生成的恢复码数量
+    pub recovery_code_count: usize,
+}
+
+impl Default for TotpConfig {
+    fn default() -> Self {
+        Self {
+            issuer: "iGame".to_string(),
+            require_for_admin: false,
+            challenge_expire: 5 * 60,
+            recovery_code_count: 10,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CaptchaConfig {
+    // 工作量证明的难度，即哈希值开头需要为0的比特数，每加1计算量翻倍
+    pub difficulty: u32,
+    // 题目的有效期，单位秒
+    pub expire: u64,
+}
+
+impl Default for CaptchaConfig {
+    fn default() -> Self {
+        Self {
+            difficulty: 20,
+            expire: 5 * 60,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ProfileConfig {
+    // 昵称的最短与最长字符数
+    pub nick_name_min_len: usize,
+    pub nick_name_max_len: usize,
+    // 两次修改昵称之间的最短间隔，单位秒
+    pub nick_name_cooldown: i64,
+    // 昵称中不允许出现的敏感词，忽略大小写与空白字符
+    pub sensitive_words: Vec<String>,
+    // 头像地址允许使用的域名，为空时允许任意https地址
+    pub avatar_hosts: Vec<String>,
+}
+
+impl Default for ProfileConfig {
+    fn default() -> Self {
+        Self {
+            nick_name_min_len: 2,
+            nick_name_max_len: 16,
+            nick_name_cooldown: 7 * 24 * 60 * 60,
+            sensitive_words: vec!["admin".to_string(), "管理员".to_string()],
+            avatar_hosts: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct OidcConfig {
+    // 发起授权到回调完成之间允许的最长时间，单位秒
+    pub state_expire: u64,
+    // 发现文档与JWKS的缓存时间，单位秒
+    pub metadata_cache: u64,
+    // 请求身份提供者的超时时间，单位秒
+    pub request_timeout: u64,
+    pub providers: Vec<OidcProviderConfig>,
+}
+
+impl Default for OidcConfig {
+    fn default() -> Self {
+        Self {
+            state_expire: 10 * 60,
+            metadata_cache: 60 * 60,
+            request_timeout: 10,
+            providers: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ApiKeyConfig {
+    // 每个用户最多可以持有的有效API密钥数量
+    pub max_per_user: i64,
+    // API密钥的最长有效期，单位秒，为0时允许创建永不过期的密钥
+    pub max_lifetime: i64,
+}
+
+impl Default for ApiKeyConfig {
+    fn default() -> Self {
+        Self {
+            max_per_user: 20,
+            max_lifetime: 365 * 24 * 60 * 60,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct PermissionConfig {
+    // 用户权限在进程内的缓存时间，单位秒，为0时每个请求都重新查询
+    // 缓存期间修改用户角色不会立即生效
+    pub cache_ttl: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct VipConfig {
+    // 可以使用无限币购买的会员套餐
+    pub plans: Vec<VipPlan>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VipPlan {
+    // 套餐的标识，购买时使用并记录在igame.trade.vip_plan中，设置后不要修改
+    pub id: String,
+    // 展示给用户的名称
+    pub name: String,
+    // 每次购买增加的会员时长，单位天，已是会员时在原过期时间上累加
+    pub days: i64,
+    // 价格，单位无限币
+    pub price: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LedgerConfig {
+    // 检查用户余额与流水是否一致的间隔，单位秒
+    pub reconcile_interval: u64,
+}
+
+impl Default for LedgerConfig {
+    fn default() -> Self {
+        Self {
+            reconcile_interval: 24 * 60 * 60,
+        }
+    }
+}
+
+impl Default for VipConfig {
+    fn default() -> Self {
+        Self {
+            plans: vec![
+                VipPlan {
+                    id: "month".to_string(),
+                    name: "月度会员".to_string(),
+                    days: 30,
+                    price: 300,
+                },
+                VipPlan {
+                    id: "quarter".to_string(),
+                    name: "季度会员".to_string(),
+                    days: 90,
+                    price: 800,
+                },
+                VipPlan {
+                    id: "year".to_string(),
+                    name: "年度会员".to_string(),
+                    days: 365,
+                    price: 3000,
+                },
+            ],
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OidcProviderConfig {
+    // 提供者的标识，出现在路由与igame.user_identity.provider中，设置后不要修改
+    pub id: String,
+    // 展示给用户的名称
+    pub name: String,
+    // 发现文档位于{issuer}/.well-known/openid-configuration，本地测试时可以使用http地址
+    pub issuer: String,
+    pub client_id: String,
+    // 公开客户端只使用PKCE，不需要client_secret
+    #[serde(default)]
+    pub client_secret: Option<String>,
+    pub redirect_uri: String,
+    #[serde(default = "default_oidc_scopes")]
+    pub scopes: Vec<String>,
+    // 第三方账号未绑定且邮箱未注册时，是否自动创建新用户
+    #[serde(default = "default_true")]
+    pub auto_register: bool,
+}
+
+fn default_oidc_scopes() -> Vec<String> {
+    vec![
+        "openid".to_string(),
+        "email".to_string(),
+        "profile".to_string(),
+    ]
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct MagicLoginConfig {
+    // 登陆码的有效期，单位秒
+    pub expire: i64,
+    // 邮件中一键登陆链接指向的前端地址，会附加email与code参数，为空时邮件中只有登陆码
+    pub link_url: String,
+}
+
+impl Default for MagicLoginConfig {
+    fn default() -> Self {
+        Self {
+            expire: 15 * 60,
+            link_url: String::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AccountConfig {
+    // 申请注销后到真正清除数据之间的冷静期，期间登陆会取消注销，单位秒
+    pub deletion_grace_period: i64,
+    // 检查并清除到期账号的间隔，单位秒
+    pub purge_interval: u64,
+}
+
+impl Default for AccountConfig {
+    fn default() -> Self {
+        Self {
+            deletion_grace_period: 14 * 24 * 60 * 60,
+            purge_interval: 60 * 60,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AvatarConfig {
+    // 头像的存储位置，"local"表示保存到本地目录，否则为msgraph中某个提供者的id
+    pub storage: String,
+    // 保存到本地时使用的目录
+    pub local_dir: String,
+    // 保存到提供者时使用的目录
+    pub remote_dir: String,
+    // 访问头像时使用的地址前缀，需要由前端服务器或CDN指向上面的目录
+    pub base_url: String,
+    // 上传文件的最大字节数
+    pub max_size: usize,
+    // 上传图片的最大宽高
+    pub max_dimension: u32,
+    // 生成的正方形头像的边长，最后一个尺寸会作为用户的avatar_url
+    pub sizes: Vec<u32>,
+}
+
+impl Default for AvatarConfig {
+    fn default() -> Self {
+        Self {
+            storage: "local".to_string(),
+            local_dir: "avatar".to_string(),
+            remote_dir: "/avatar".to_string(),
+            base_url: "/avatar".to_string(),
+            max_size: 2 * 1024 * 1024,
+            max_dimension: 4096,
+            sizes: vec![64, 256],
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RateLimitKey {
+    // 按客户端ip限制
+    #[serde(rename = "ip")]
+    Ip,
+    // 按登陆用户限制，未登陆时退化为按ip限制
+    // 限流在认证API密钥之前进行，使用API密钥的请求同样按ip限制
+    #[serde(rename = "user")]
+    User,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RateLimitRule {
+    // 路由的匹配模式，与路由宏中的写法一致，例如"/resource/{resource_id}/{client_group}/url"
+    pub pattern: String,
+    // 为空时对所有请求方法生效
+    #[serde(default)]
+    pub method: Option<String>,
+    pub key: RateLimitKey,
+    // 令牌桶的容量，即允许的突发请求数，为0时按1处理
+    pub capacity: u32,
+    // 令牌桶从空到满所需的时间，单位秒
+    pub period: u64,
+}
+
+fn default_rate_limit() -> Vec<RateLimitRule> {
+    vec![
+        RateLimitRule {
+            pattern: "/send_verify_email".to_string(),
+            method: None,
+            key: RateLimitKey::Ip,
+            capacity: 5,
+            period: 10 * 60,
+        },
+        RateLimitRule {
+            pattern: "/resource/{resource_id}/{client_group}/url".to_string(),
+            method: None,
+            key: RateLimitKey::User,
+            capacity: 30,
+            period: 60,
+        },
+    ]
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -116,3 +596,87 @@ impl Config {
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+[app]
+mode = "debug"
+addr = "127.0.0.1:8080"
+thread = 1
+log_level = "info"
+log_format = "full"
+
+[jwt]
+token_secret = "secret"
+access_token_expire = 600
+refresh_token_expire = 86400
+
+[jwt.signing_key]
+kid = "key-2"
+algorithm = "EdDSA"
+public_key_path = "key-2.pub.pem"
+private_key_path = "key-2.pem"
+
+[[jwt.verification_keys]]
+kid = "key-1"
+algorithm = "RS256"
+public_key_path = "key-1.pub.pem"
+
+[email]
+addr = "smtp.example.com"
+username = "user"
+password = "password"
+sender = "noreply@example.com"
+root_cert = ""
+idle_timeout = 60
+min_idle = 0
+max_size = 4
+
+[pgsql]
+mode = "debug"
+host = "127.0.0.1"
+port = 5432
+user = "postgres"
+password = "password"
+database_name = "igame"
+application_name = "igame_backend"
+ssl = false
+root_cert = ""
+connect_timeout = 10
+keepalives_idle = 60
+
+[[msgraph]]
+id = "od1"
+connect_timeout = 10
+whole_timeout = 60
+pool_idle_timeout = 60
+group = "normal"
+region = "global"
+client_id = "client"
+client_secret = "secret"
+drive_url = "https://graph.microsoft.com/v1.0/me/drive"
+redirect_url = "http://localhost"
+refresh_token = "old"
+"#;
+
+    #[test]
+    fn round_trip_with_jwt_keys() {
+        let mut config: Config = toml::from_str(CONFIG).unwrap();
+        config.msgraph[0].refresh_token = "new".to_string();
+        let s = toml::to_string_pretty(&config).unwrap();
+        let config: Config = toml::from_str(&s).unwrap();
+        assert_eq!(config.msgraph[0].refresh_token, "new");
+        let signing_key = config.jwt.signing_key.unwrap();
+        assert_eq!(signing_key.kid, "key-2");
+        assert_eq!(signing_key.algorithm, JwtKeyAlgorithm::EdDSA);
+        assert_eq!(signing_key.private_key_path.as_deref(), Some("key-2.pem"));
+        assert_eq!(config.jwt.verification_keys.len(), 1);
+        assert_eq!(config.jwt.verification_keys[0].kid, "key-1");
+        assert_eq!(config.jwt.revoked_session_sync_interval, 10);
+        assert_eq!(config.rate_limit.len(), 2);
+        assert_eq!(config.vip.plans.len(), 3);
+    }
+}
@@ -1,4 +1,4 @@
-use tokio_postgres::Error;
+use tokio_postgres::{error::SqlState, Error};
 
 pub fn is_db_zero_line_error(err: &Error) -> bool {
     if err
@@ -19,3 +19,11 @@ pub fn is_db_dup_unique_error(err: &Error) -> bool {
     }
     false
 }
+
+// 序列化失败或死锁，重新执行整个事务通常可以成功
+pub fn is_db_retryable_error(err: &Error) -> bool {
+    matches!(
+        err.code(),
+        Some(&SqlState::T_R_SERIALIZATION_FAILURE) | Some(&SqlState::T_R_DEADLOCK_DETECTED)
+    )
+}
@@ -2,5 +2,6 @@ mod dberror_check;
 mod response_error;
 
 pub use dberror_check::is_db_dup_unique_error;
+pub use dberror_check::is_db_retryable_error;
 pub use dberror_check::is_db_zero_line_error;
 pub use response_error::ResponseError;
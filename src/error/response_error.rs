@@ -1,4 +1,7 @@
-use actix_web::{http::StatusCode, HttpResponse, HttpResponseBuilder};
+use actix_web::{
+    http::{header, StatusCode},
+    HttpResponse, HttpResponseBuilder,
+};
 use deadpool_postgres::{tokio_postgres, PoolError};
 use derive_more::{Display, Error};
 use serde::Serialize;
@@ -25,12 +28,19 @@ pub struct ResponseError {
 }
 
 #[derive(Debug, Display, Error, Serialize)]
-#[display(fmt = "{{need_exp: {:?}, need_coin: {:?}}}", need_exp, need_coin)]
+#[display(
+    fmt = "{{need_exp: {:?}, need_coin: {:?}, retry_after: {:?}}}",
+    need_exp,
+    need_coin,
+    retry_after
+)]
 pub struct ExtraField {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub need_exp: Option<i32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub need_coin: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u64>,
 }
 
 impl ResponseError {
@@ -87,6 +97,7 @@ impl ResponseError {
             extra_field: Some(ExtraField {
                 need_exp: None,
                 need_coin: Some(need_coin),
+                retry_after: None,
             }),
             internal_message: internal_message.to_string(),
             status_code: StatusCode::FORBIDDEN,
@@ -101,6 +112,7 @@ impl ResponseError {
             extra_field: Some(ExtraField {
                 need_exp: Some(need_exp),
                 need_coin: None,
+                retry_after: None,
             }),
             internal_message: internal_message.to_string(),
             status_code: StatusCode::FORBIDDEN,
@@ -144,6 +156,51 @@ impl ResponseError {
         }
     }
 
+    pub fn too_many_attempts_err(
+        err_message: &str,
+        retry_after: u64,
+        internal_message: &str,
+    ) -> Self {
+        Self {
+            err_code: 10,
+            err_type: "尝试次数过多".to_string(),
+            err_message: err_message.to_string(),
+            extra_field: Some(ExtraField {
+                need_exp: None,
+                need_coin: None,
+                retry_after: Some(retry_after),
+            }),
+            internal_message: internal_message.to_string(),
+            status_code: StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+
+    pub fn rate_limited_err(err_message: &str, retry_after: u64, internal_message: &str) -> Self {
+        Self {
+            err_code: 11,
+            err_type: "请求过于频繁".to_string(),
+            err_message: err_message.to_string(),
+            extra_field: Some(ExtraField {
+                need_exp: None,
+                need_coin: None,
+                retry_after: Some(retry_after),
+            }),
+            internal_message: internal_message.to_string(),
+            status_code: StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+
+    pub fn identity_provider_err(err_message: &str, internal_message: &str) -> Self {
+        Self {
+            err_code: 12,
+            err_type: "第三方登陆失败".to_string(),
+            err_message: err_message.to_string(),
+            extra_field: None,
+            internal_message: internal_message.to_string(),
+            status_code: StatusCode::BAD_GATEWAY,
+        }
+    }
+
     pub fn unexpected_err(err_message: &str, internal_message: &str) -> Self {
         Self {
             err_code: 0,
@@ -162,7 +219,12 @@ impl actix_web::error::ResponseError for ResponseError {
     }
 
     fn error_response(&self) -> HttpResponse {
-        HttpResponseBuilder::new(self.status_code()).json(self)
+        let mut builder = HttpResponseBuilder::new(self.status_code());
+        // 告知客户端可以重试的时间
+        if let Some(retry_after) = self.extra_field.as_ref().and_then(|v| v.retry_after) {
+            builder.insert_header((header::RETRY_AFTER, retry_after.to_string()));
+        }
+        builder.json(self)
     }
 }
 
@@ -2,20 +2,29 @@
 #![feature(destructuring_assignment)]
 
 use actix_web::{middleware, web, App, HttpResponse, HttpServer};
+use deadpool_postgres::Pool;
 use std::time::Duration;
 use time::macros::format_description;
 use tokio::time::interval;
 use tracing_subscriber::{filter::LevelFilter, fmt::time::LocalTime, EnvFilter};
 
+use crate::api_key_middleware::ApiKeyAuth;
+use crate::attempt_limiter::AttemptLimiterShare;
 use crate::config::GLOBAL_CONFIG;
+use crate::oidc::OidcShare;
+use crate::rate_limit_middleware::RateLimiter;
 use crate::resource_provider::ResourceProviderShare;
 use crate::tracing_middleware::{CustomRootSpanBuilder, TracingLogger};
 
+mod api_key_middleware;
+mod attempt_limiter;
 mod config;
 mod db;
 mod email;
 mod error;
 mod model;
+mod oidc;
+mod rate_limit_middleware;
 mod resource_provider;
 mod router;
 mod tracing_middleware;
@@ -78,6 +87,14 @@ async fn run_server() -> std::io::Result<()> {
             db_pool.get().await.unwrap();
         }
     }
+    // 加载签名密钥
+    util::jwt_key::load_keys();
+    // 加载已吊销的会话
+    {
+        let client = db_pool.get().await.unwrap();
+        let count = util::session::load_revoked_sessions(&client).await.unwrap();
+        tracing::info!("加载了{}个已吊销的会话", count);
+    }
     // 初始化邮件服务器连接池
     let email_pool = email::new_email_pool();
     // 初始化资源服务器连接池
@@ -85,6 +102,12 @@ async fn run_server() -> std::io::Result<()> {
     {
         resource_provider.write_to_config_file().await;
     }
+    // 初始化登陆失败次数限制
+    let attempt_limiter = AttemptLimiterShare::new();
+    // 初始化第三方登陆
+    let oidc = OidcShare::new();
+    // 初始化请求频率限制
+    let rate_limiter = RateLimiter::new();
     // 初始化定时执行服务
     let resource_provider_clone = resource_provider.clone();
     tokio::spawn(async move {
@@ -96,13 +119,24 @@ async fn run_server() -> std::io::Result<()> {
             resource_provider_clone.write_to_config_file().await;
         }
     });
+    // 定时同步其他实例吊销的会话
+    spawn_revoked_session_sync(db_pool.clone());
+    // 定时清除冷静期已过的注销账号
+    spawn_purge_job(db_pool.clone());
+    // 定时检查用户余额与流水是否一致
+    spawn_reconcile_job(db_pool.clone());
 
     HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(db_pool.clone()))
             .app_data(web::Data::new(email_pool.clone()))
             .app_data(web::Data::new(resource_provider.clone()))
+            .app_data(web::Data::new(attempt_limiter.clone()))
+            .app_data(web::Data::new(oidc.clone()))
             .wrap(middleware::Compress::default())
+            // 后注册的中间件先执行，限流在认证API密钥之前，避免无效的密钥消耗数据库连接
+            .wrap(ApiKeyAuth)
+            .wrap(rate_limiter.clone())
             .wrap(TracingLogger::<CustomRootSpanBuilder>::new())
             .configure(router::register)
             .default_service(web::route().to(|| HttpResponse::NotFound()))
@@ -123,6 +157,14 @@ async fn run_server() -> std::io::Result<()> {
     {
         db_pool.get().await.unwrap();
     }
+    // 加载签名密钥
+    util::jwt_key::load_keys();
+    // 加载已吊销的会话
+    {
+        let client = db_pool.get().await.unwrap();
+        let count = util::session::load_revoked_sessions(&client).await.unwrap();
+        tracing::info!("加载了{}个已吊销的会话", count);
+    }
     // 初始化邮件服务器连接池
     let email_pool = email::new_email_pool();
     // 初始化资源服务器连接池
@@ -130,6 +172,12 @@ async fn run_server() -> std::io::Result<()> {
     {
         resource_provider.write_to_config_file().await;
     }
+    // 初始化登陆失败次数限制
+    let attempt_limiter = AttemptLimiterShare::new();
+    // 初始化第三方登陆
+    let oidc = OidcShare::new();
+    // 初始化请求频率限制
+    let rate_limiter = RateLimiter::new();
     // 初始化定时执行服务
     let resource_provider_clone = resource_provider.clone();
     tokio::spawn(async move {
@@ -141,13 +189,24 @@ async fn run_server() -> std::io::Result<()> {
             resource_provider_clone.write_to_config_file().await;
         }
     });
+    // 定时同步其他实例吊销的会话
+    spawn_revoked_session_sync(db_pool.clone());
+    // 定时清除冷静期已过的注销账号
+    spawn_purge_job(db_pool.clone());
+    // 定时检查用户余额与流水是否一致
+    spawn_reconcile_job(db_pool.clone());
 
     let temp_server = HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(db_pool.clone()))
             .app_data(web::Data::new(email_pool.clone()))
             .app_data(web::Data::new(resource_provider.clone()))
+            .app_data(web::Data::new(attempt_limiter.clone()))
+            .app_data(web::Data::new(oidc.clone()))
             .wrap(middleware::Compress::default())
+            // 后注册的中间件先执行，限流在认证API密钥之前，避免无效的密钥消耗数据库连接
+            .wrap(ApiKeyAuth)
+            .wrap(rate_limiter.clone())
             .wrap(TracingLogger::<CustomRootSpanBuilder>::new())
             .configure(router::register)
             .default_service(web::route().to(|| HttpResponse::NotFound()))
@@ -170,3 +229,74 @@ async fn run_server() -> std::io::Result<()> {
     }
     server.run().await
 }
+
+fn spawn_revoked_session_sync(db_pool: Pool) {
+    tokio::spawn(async move {
+        let period = GLOBAL_CONFIG.jwt.revoked_session_sync_interval.max(1);
+        let mut interval = interval(Duration::from_secs(period));
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let result = match db_pool.get().await {
+                Ok(client) => util::session::load_revoked_sessions(&client).await,
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = result {
+                tracing::error!("同步已吊销的会话失败: {:?}", e);
+            }
+        }
+    });
+}
+
+fn spawn_purge_job(db_pool: Pool) {
+    tokio::spawn(async move {
+        // interval的周期不能为0
+        let period = GLOBAL_CONFIG.account.purge_interval.max(1);
+        let mut interval = interval(Duration::from_secs(period));
+        loop {
+            interval.tick().await;
+            let result = match db_pool.get().await {
+                Ok(client) => util::account::purge_deleted_users(&client).await,
+                Err(e) => Err(e.into()),
+            };
+            match result {
+                Ok(0) => {}
+                Ok(count) => tracing::info!("清除了{}个已注销的账号", count),
+                Err(e) => tracing::error!("清除已注销的账号失败: {:?}", e),
+            }
+        }
+    });
+}
+
+fn spawn_reconcile_job(db_pool: Pool) {
+    tokio::spawn(async move {
+        // interval的周期不能为0
+        let period = GLOBAL_CONFIG.ledger.reconcile_interval.max(1);
+        let mut interval = interval(Duration::from_secs(period));
+        loop {
+            interval.tick().await;
+            let result = match db_pool.get().await {
+                Ok(client) => util::ledger::reconcile(&client).await,
+                Err(e) => Err(e.into()),
+            };
+            match result {
+                Ok(drifts) => {
+                    for drift in &drifts {
+                        tracing::error!(
+                            "[用户ID: {}]余额与流水不一致，无限币: {}，流水: {}，经验: {}，流水: {}",
+                            drift.user_id,
+                            drift.coin,
+                            drift.ledger_coin,
+                            drift.exp,
+                            drift.ledger_exp
+                        );
+                    }
+                    if !drifts.is_empty() {
+                        tracing::error!("共有{}个用户的余额与流水不一致", drifts.len());
+                    }
+                }
+                Err(e) => tracing::error!("检查余额与流水失败: {:?}", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,42 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::model::role::Permission;
+
+// scopes为该密钥允许使用的权限，不影响只需要登陆的接口
+#[derive(Debug, Deserialize)]
+pub struct PostMyApiKeyInput {
+    pub name: String,
+    #[serde(default)]
+    pub scopes: Vec<Permission>,
+    pub expire_at: Option<DateTime<Utc>>,
+}
+
+// api_key只在创建时返回一次
+#[derive(Debug, Serialize)]
+pub struct PostMyApiKeyOutput {
+    pub key_id: i32,
+    pub name: String,
+    pub api_key: String,
+    pub key_prefix: String,
+    pub scopes: Vec<String>,
+    pub expire_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetMyApiKeysOutputItem {
+    pub key_id: i32,
+    pub name: String,
+    pub key_prefix: String,
+    pub scopes: Vec<String>,
+    pub expire_at: Option<DateTime<Utc>>,
+    pub used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+pub type GetMyApiKeysOutput = Vec<GetMyApiKeysOutputItem>;
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteMyApiKeyPath {
+    pub key_id: i32,
+}
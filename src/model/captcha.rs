@@ -0,0 +1,16 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize)]
+pub struct GetCaptchaOutput {
+    pub challenge: String,
+    pub difficulty: u32,
+    pub expire_at: DateTime<Utc>,
+}
+
+// 需要人机验证的接口中附带的工作量证明答案
+#[derive(Debug, Deserialize)]
+pub struct CaptchaInput {
+    pub challenge: String,
+    pub solution: String,
+}
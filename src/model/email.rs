@@ -1,10 +1,14 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::config::GLOBAL_CONFIG;
+use crate::model::captcha::CaptchaInput;
+
 #[derive(Debug, Deserialize)]
 pub struct SendVerifyEmailInput {
     pub email_addr: String,
     pub email_type: VerifyEmailType,
+    pub captcha: CaptchaInput,
 }
 
 #[derive(Debug, Serialize)]
@@ -19,6 +23,10 @@ pub enum VerifyEmailType {
     UserRegister,
     #[serde(rename = "password_reset")]
     PasswordReset,
+    #[serde(rename = "email_change")]
+    EmailChange,
+    #[serde(rename = "magic_login")]
+    MagicLogin,
 }
 
 impl VerifyEmailType {
@@ -26,6 +34,8 @@ impl VerifyEmailType {
         match self {
             Self::UserRegister => 1,
             Self::PasswordReset => 2,
+            Self::EmailChange => 3,
+            Self::MagicLogin => 4,
         }
     }
 
@@ -33,11 +43,22 @@ impl VerifyEmailType {
         match self {
             Self::UserRegister => "注册验证邮件",
             Self::PasswordReset => "重置密码验证邮件",
+            Self::EmailChange => "修改邮箱验证邮件",
+            Self::MagicLogin => "登陆验证邮件",
         }
         .to_string()
     }
 
-    pub fn to_html(&self, verify_code: &str) -> String {
+    // 验证码的有效期，登陆验证码可以直接登陆，因此有效期更短
+    pub fn to_expire(&self) -> Duration {
+        match self {
+            Self::MagicLogin => Duration::seconds(GLOBAL_CONFIG.magic_login.expire),
+            _ => Duration::hours(2),
+        }
+    }
+
+    // link为登陆邮件中的一键登陆地址，其他类型的邮件不使用
+    pub fn to_html(&self, verify_code: &str, link: Option<&str>) -> String {
         match self {
             Self::UserRegister => format!(
                 r##"<!doctypehtml><html lang=zh-CN xmlns=http://www.w3.org/1999/xhtml xmlns:o=urn:schemas-microsoft-com:office:office xmlns:v=urn:schemas-microsoft-com:vml><meta charset=utf-8><meta content="width=device-width"name=viewport><meta content="IE=edge"http-equiv=X-UA-Compatible><meta name=x-apple-disable-message-reformatting><meta content="telephone=no,address=no,email=no,date=no,url=no"name=format-detection><meta content=light name=color-scheme><meta content=light name=supported-color-schemes><title>IGame注册邮件</title><!--[if gte mso 9]><xml><o:officedocumentsettings><o:allowpng><o:pixelsperinch>96</o:pixelsperinch></o:officedocumentsettings></xml><![endif]--><!--[if mso]><style>*{{font-family:sans-serif!important}}</style><![endif]--><!--[if !mso]><!--><!--<![endif]--><style>:root{{color-scheme:light;supported-color-schemes:light}}body,html{{margin:0 auto!important;padding:0!important;height:100%!important;width:100%!important}}*{{-ms-text-size-adjust:100%;-webkit-text-size-adjust:100%}}div[style*="margin: 16px 0"]{{margin:0!important}}#MessageViewBody,#MessageWebViewDiv{{width:100%!important}}table,td{{mso-table-lspace:0!important;mso-table-rspace:0!important}}table{{border-spacing:0!important;border-collapse:collapse!important;table-layout:fixed!important;margin:0 auto!important}}img{{-ms-interpolation-mode:bicubic}}a{{text-decoration:none}}.aBn,.unstyle-auto-detected-links a,a[x-apple-data-detectors]{{border-bottom:0!important;cursor:default!important;color:inherit!important;text-decoration:none!important;font-size:inherit!important;font-family:inherit!important;font-weight:inherit!important;line-height:inherit!important}}.a6S{{display:none!important;opacity:.01!important}}.im{{color:inherit!important}}img.g-img+div{{display:none!important}}@media only screen and (min-device-width:320px) and (max-device-width:374px){{u~div .email-container{{min-width:320px!important}}}}@media only screen and (min-device-width:375px) and (max-device-width:413px){{u~div .email-container{{min-width:375px!important}}}}@media only screen and (min-device-width:414px){{u~div .email-container{{min-width:414px!important}}}}</style><body style=margin:0;padding:0!important;mso-line-height-rule:exactly;background-color:#fff width=100%><center aria-roledescription=email lang=en role=article style=width:100%;background-color:#fff><!--[if mso | IE]><table border=0 cellpadding=0 cellspacing=0 role=presentation width=100% style=background-color:#fff><tr><td><![endif]--><div style=max-height:0;overflow:hidden;mso-hide:all aria-hidden=true>感谢您注册「IGame」账号, 验证码：{}</div><div style=display:none;font-size:1px;line-height:1px;max-height:0;max-width:0;opacity:0;overflow:hidden;mso-hide:all>‌</div><div style="max-width:600px;margin:0 auto;background-image:url(https://cdn.jsdelivr.net/gh/OmegaLo/images@main/email_backgroud.png);background-color:#e74777"class=email-container><!--[if mso]><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=background-image:url(https://cdn.jsdelivr.net/gh/OmegaLo/images@main/email_backgroud.png);background-color:#e74777 width=600><tr><td><![endif]--><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=margin:auto width=100%><tr><td style=padding-top:72px;text-align:center><img alt=Logo border=0 height=120 src=https://cdn.jsdelivr.net/gh/OmegaLo/images@main/email_logo.png width=120><tr><td><table border=0 cellpadding=0 cellspacing=0 role=presentation width=100%><tr><td style="padding:48px 24px 0 24px;text-align:center;font-size:32px;color:#fff;font-weight:700"><span>感谢您注册</span><span style=padding-top:8px;display:block>「IGame」账号</span><tr><td style="padding:48px 20px 0 20px"><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=margin:auto><tr><td style="background:#eec312;color:#000;font-size:18px;padding:8px 40px;font-weight:700"><span>验证码</span></table><tr><td style="padding:0 20px"><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=margin:auto><tr><td style="background-color:#fff;color:#000;font-size:56px;font-weight:700;padding:8px 16px;width:260px;text-align:center;border-radius:4px"><span>{}</span></table><tr><td style=padding-top:48px;font-family:sans-serif;font-size:15px;line-height:20px;color:#fff><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=margin:auto><tr><td><ul style="padding:0 16px 0 32px;list-style-type:disc"><li style=padding-bottom:8px class=list-item-first>该验证码2小时内有效，如果过期请重新申请验证<li style=padding-bottom:8px>每个邮箱只能成功注册一个账号<li style=padding-bottom:8px class=list-item-last>如果你并没有尝试注册「IGame」账号，请忽略该邮件</ul></table></table></table><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=margin:auto width=100%><tr><td style="padding:48px 20px 72px 20px"><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=margin:auto><tr><td style=color:#fff;font-size:24px;font-weight:700;padding:16px;width:160px;text-align:center;border-radius:4px;background-color:#843fa1><span>系统邮件</span></table></table><!--[if mso]><![endif]--></div><!--[if mso | IE]><![endif]--></center>"##,
@@ -47,10 +68,43 @@ impl VerifyEmailType {
                 r##"<!doctypehtml><html lang=zh-CN xmlns=http://www.w3.org/1999/xhtml xmlns:o=urn:schemas-microsoft-com:office:office xmlns:v=urn:schemas-microsoft-com:vml><meta charset=utf-8><meta content="width=device-width"name=viewport><meta content="IE=edge"http-equiv=X-UA-Compatible><meta name=x-apple-disable-message-reformatting><meta content="telephone=no,address=no,email=no,date=no,url=no"name=format-detection><meta content=light name=color-scheme><meta content=light name=supported-color-schemes><title>IGame重置密码邮件</title><!--[if gte mso 9]><xml><o:officedocumentsettings><o:allowpng><o:pixelsperinch>96</o:pixelsperinch></o:officedocumentsettings></xml><![endif]--><!--[if mso]><style>*{{font-family:sans-serif!important}}</style><![endif]--><!--[if !mso]><!--><!--<![endif]--><style>:root{{color-scheme:light;supported-color-schemes:light}}body,html{{margin:0 auto!important;padding:0!important;height:100%!important;width:100%!important}}*{{-ms-text-size-adjust:100%;-webkit-text-size-adjust:100%}}div[style*="margin: 16px 0"]{{margin:0!important}}#MessageViewBody,#MessageWebViewDiv{{width:100%!important}}table,td{{mso-table-lspace:0!important;mso-table-rspace:0!important}}table{{border-spacing:0!important;border-collapse:collapse!important;table-layout:fixed!important;margin:0 auto!important}}img{{-ms-interpolation-mode:bicubic}}a{{text-decoration:none}}.aBn,.unstyle-auto-detected-links a,a[x-apple-data-detectors]{{border-bottom:0!important;cursor:default!important;color:inherit!important;text-decoration:none!important;font-size:inherit!important;font-family:inherit!important;font-weight:inherit!important;line-height:inherit!important}}.a6S{{display:none!important;opacity:.01!important}}.im{{color:inherit!important}}img.g-img+div{{display:none!important}}@media only screen and (min-device-width:320px) and (max-device-width:374px){{u~div .email-container{{min-width:320px!important}}}}@media only screen and (min-device-width:375px) and (max-device-width:413px){{u~div .email-container{{min-width:375px!important}}}}@media only screen and (min-device-width:414px){{u~div .email-container{{min-width:414px!important}}}}</style><body style=margin:0;padding:0!important;mso-line-height-rule:exactly;background-color:#fff width=100%><center aria-roledescription=email lang=en role=article style=width:100%;background-color:#fff><!--[if mso | IE]><table border=0 cellpadding=0 cellspacing=0 role=presentation width=100% style=background-color:#fff><tr><td><![endif]--><div style=max-height:0;overflow:hidden;mso-hide:all aria-hidden=true>您正在尝试重置「IGame」账号密码, 验证码：{}</div><div style=display:none;font-size:1px;line-height:1px;max-height:0;max-width:0;opacity:0;overflow:hidden;mso-hide:all></div><div style="max-width:600px;margin:0 auto;background-image:url(https://cdn.jsdelivr.net/gh/OmegaLo/images@main/email_backgroud.png);background-color:#e74777"class=email-container><!--[if mso]><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=background-image:url(https://cdn.jsdelivr.net/gh/OmegaLo/images@main/email_backgroud.png);background-color:#e74777 width=600><tr><td><![endif]--><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=margin:auto width=100%><tr><td style=padding-top:72px;text-align:center><img alt=Logo border=0 height=120 src=https://cdn.jsdelivr.net/gh/OmegaLo/images@main/email_logo.png width=120><tr><td><table border=0 cellpadding=0 cellspacing=0 role=presentation width=100%><tr><td style="padding:48px 24px 0 24px;text-align:center;font-size:32px;color:#fff;font-weight:700"><span>您正在尝试重置</span><span style=padding-top:8px;display:block>「IGame」账号密码</span><tr><td style="padding:48px 20px 0 20px"><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=margin:auto><tr><td style="background:#eec312;color:#000;font-size:18px;padding:8px 40px;font-weight:700"><span>验证码</span></table><tr><td style="padding:0 20px"><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=margin:auto><tr><td style="background-color:#fff;color:#000;font-size:56px;font-weight:700;padding:8px 16px;width:260px;text-align:center;border-radius:4px"><span>{}</span></table><tr><td style=padding-top:48px;font-family:sans-serif;font-size:15px;line-height:20px;color:#fff><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=margin:auto><tr><td><ul style="padding:0 16px 0 32px;list-style-type:disc"><li class=list-item-first style=padding-bottom:8px>该验证码2小时内有效，如果过期请重新申请验证<li class=list-item-last style=padding-bottom:8px>如果你并没有尝试重置「IGame」账号密码，请忽略该邮件</ul></table></table></table><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=margin:auto width=100%><tr><td style="padding:48px 20px 72px 20px"><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=margin:auto><tr><td style=color:#fff;font-size:24px;font-weight:700;padding:16px;width:160px;text-align:center;border-radius:4px;background-color:#843fa1><span>系统邮件</span></table></table><!--[if mso]><![endif]--></div><!--[if mso | IE]><![endif]--></center>"##,
                 verify_code, verify_code
             ),
+            Self::EmailChange => format!(
+                r##"<!doctypehtml><html lang=zh-CN xmlns=http://www.w3.org/1999/xhtml xmlns:o=urn:schemas-microsoft-com:office:office xmlns:v=urn:schemas-microsoft-com:vml><meta charset=utf-8><meta content="width=device-width"name=viewport><meta content="IE=edge"http-equiv=X-UA-Compatible><meta name=x-apple-disable-message-reformatting><meta content="telephone=no,address=no,email=no,date=no,url=no"name=format-detection><meta content=light name=color-scheme><meta content=light name=supported-color-schemes><title>IGame修改邮箱邮件</title><!--[if gte mso 9]><xml><o:officedocumentsettings><o:allowpng><o:pixelsperinch>96</o:pixelsperinch></o:officedocumentsettings></xml><![endif]--><!--[if mso]><style>*{{font-family:sans-serif!important}}</style><![endif]--><!--[if !mso]><!--><!--<![endif]--><style>:root{{color-scheme:light;supported-color-schemes:light}}body,html{{margin:0 auto!important;padding:0!important;height:100%!important;width:100%!important}}*{{-ms-text-size-adjust:100%;-webkit-text-size-adjust:100%}}div[style*="margin: 16px 0"]{{margin:0!important}}#MessageViewBody,#MessageWebViewDiv{{width:100%!important}}table,td{{mso-table-lspace:0!important;mso-table-rspace:0!important}}table{{border-spacing:0!important;border-collapse:collapse!important;table-layout:fixed!important;margin:0 auto!important}}img{{-ms-interpolation-mode:bicubic}}a{{text-decoration:none}}.aBn,.unstyle-auto-detected-links a,a[x-apple-data-detectors]{{border-bottom:0!important;cursor:default!important;color:inherit!important;text-decoration:none!important;font-size:inherit!important;font-family:inherit!important;font-weight:inherit!important;line-height:inherit!important}}.a6S{{display:none!important;opacity:.01!important}}.im{{color:inherit!important}}img.g-img+div{{display:none!important}}@media only screen and (min-device-width:320px) and (max-device-width:374px){{u~div .email-container{{min-width:320px!important}}}}@media only screen and (min-device-width:375px) and (max-device-width:413px){{u~div .email-container{{min-width:375px!important}}}}@media only screen and (min-device-width:414px){{u~div .email-container{{min-width:414px!important}}}}</style><body style=margin:0;padding:0!important;mso-line-height-rule:exactly;background-color:#fff width=100%><center aria-roledescription=email lang=en role=article style=width:100%;background-color:#fff><!--[if mso | IE]><table border=0 cellpadding=0 cellspacing=0 role=presentation width=100% style=background-color:#fff><tr><td><![endif]--><div style=max-height:0;overflow:hidden;mso-hide:all aria-hidden=true>您正在将「IGame」账号绑定到该邮箱, 验证码：{}</div><div style=display:none;font-size:1px;line-height:1px;max-height:0;max-width:0;opacity:0;overflow:hidden;mso-hide:all></div><div style="max-width:600px;margin:0 auto;background-image:url(https://cdn.jsdelivr.net/gh/OmegaLo/images@main/email_backgroud.png);background-color:#e74777"class=email-container><!--[if mso]><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=background-image:url(https://cdn.jsdelivr.net/gh/OmegaLo/images@main/email_backgroud.png);background-color:#e74777 width=600><tr><td><![endif]--><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=margin:auto width=100%><tr><td style=padding-top:72px;text-align:center><img alt=Logo border=0 height=120 src=https://cdn.jsdelivr.net/gh/OmegaLo/images@main/email_logo.png width=120><tr><td><table border=0 cellpadding=0 cellspacing=0 role=presentation width=100%><tr><td style="padding:48px 24px 0 24px;text-align:center;font-size:32px;color:#fff;font-weight:700"><span>您正在将「IGame」账号</span><span style=padding-top:8px;display:block>绑定到该邮箱</span><tr><td style="padding:48px 20px 0 20px"><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=margin:auto><tr><td style="background:#eec312;color:#000;font-size:18px;padding:8px 40px;font-weight:700"><span>验证码</span></table><tr><td style="padding:0 20px"><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=margin:auto><tr><td style="background-color:#fff;color:#000;font-size:56px;font-weight:700;padding:8px 16px;width:260px;text-align:center;border-radius:4px"><span>{}</span></table><tr><td style=padding-top:48px;font-family:sans-serif;font-size:15px;line-height:20px;color:#fff><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=margin:auto><tr><td><ul style="padding:0 16px 0 32px;list-style-type:disc"><li class=list-item-first style=padding-bottom:8px>该验证码2小时内有效，如果过期请重新申请验证<li style=padding-bottom:8px>验证成功后，该邮箱将作为「IGame」账号的登录邮箱<li class=list-item-last style=padding-bottom:8px>如果你并没有尝试修改「IGame」账号邮箱，请忽略该邮件</ul></table></table></table><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=margin:auto width=100%><tr><td style="padding:48px 20px 72px 20px"><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=margin:auto><tr><td style=color:#fff;font-size:24px;font-weight:700;padding:16px;width:160px;text-align:center;border-radius:4px;background-color:#843fa1><span>系统邮件</span></table></table><!--[if mso]><![endif]--></div><!--[if mso | IE]><![endif]--></center>"##,
+                verify_code, verify_code
+            ),
+            Self::MagicLogin => {
+                let link_html = match link {
+                    Some(link) => format!(
+                        r##"<tr><td style="padding:32px 20px 0 20px"><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=margin:auto><tr><td style=background-color:#843fa1;border-radius:4px><a href="{}" style="display:block;color:#fff;font-size:20px;font-weight:700;padding:12px 40px">一键登陆</a></table>"##,
+                        link.replace('&', "&amp;").replace('"', "&quot;")
+                    ),
+                    None => String::new(),
+                };
+                format!(
+                    r##"<!doctypehtml><html lang=zh-CN xmlns=http://www.w3.org/1999/xhtml xmlns:o=urn:schemas-microsoft-com:office:office xmlns:v=urn:schemas-microsoft-com:vml><meta charset=utf-8><meta content="width=device-width"name=viewport><meta content="IE=edge"http-equiv=X-UA-Compatible><meta name=x-apple-disable-message-reformatting><meta content="telephone=no,address=no,email=no,date=no,url=no"name=format-detection><meta content=light name=color-scheme><meta content=light name=supported-color-schemes><title>IGame登陆邮件</title><!--[if gte mso 9]><xml><o:officedocumentsettings><o:allowpng><o:pixelsperinch>96</o:pixelsperinch></o:officedocumentsettings></xml><![endif]--><!--[if mso]><style>*{{font-family:sans-serif!important}}</style><![endif]--><!--[if !mso]><!--><!--<![endif]--><style>:root{{color-scheme:light;supported-color-schemes:light}}body,html{{margin:0 auto!important;padding:0!important;height:100%!important;width:100%!important}}*{{-ms-text-size-adjust:100%;-webkit-text-size-adjust:100%}}div[style*="margin: 16px 0"]{{margin:0!important}}#MessageViewBody,#MessageWebViewDiv{{width:100%!important}}table,td{{mso-table-lspace:0!important;mso-table-rspace:0!important}}table{{border-spacing:0!important;border-collapse:collapse!important;table-layout:fixed!important;margin:0 auto!important}}img{{-ms-interpolation-mode:bicubic}}a{{text-decoration:none}}.aBn,.unstyle-auto-detected-links a,a[x-apple-data-detectors]{{border-bottom:0!important;cursor:default!important;color:inherit!important;text-decoration:none!important;font-size:inherit!important;font-family:inherit!important;font-weight:inherit!important;line-height:inherit!important}}.a6S{{display:none!important;opacity:.01!important}}.im{{color:inherit!important}}img.g-img+div{{display:none!important}}@media only screen and (min-device-width:320px) and (max-device-width:374px){{u~div .email-container{{min-width:320px!important}}}}@media only screen and (min-device-width:375px) and (max-device-width:413px){{u~div .email-container{{min-width:375px!important}}}}@media only screen and (min-device-width:414px){{u~div .email-container{{min-width:414px!important}}}}</style><body style=margin:0;padding:0!important;mso-line-height-rule:exactly;background-color:#fff width=100%><center aria-roledescription=email lang=en role=article style=width:100%;background-color:#fff><!--[if mso | IE]><table border=0 cellpadding=0 cellspacing=0 role=presentation width=100% style=background-color:#fff><tr><td><![endif]--><div style=max-height:0;overflow:hidden;mso-hide:all aria-hidden=true>您正在登陆「IGame」账号, 登陆码：{}</div><div style=display:none;font-size:1px;line-height:1px;max-height:0;max-width:0;opacity:0;overflow:hidden;mso-hide:all></div><div style="max-width:600px;margin:0 auto;background-image:url(https://cdn.jsdelivr.net/gh/OmegaLo/images@main/email_backgroud.png);background-color:#e74777"class=email-container><!--[if mso]><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=background-image:url(https://cdn.jsdelivr.net/gh/OmegaLo/images@main/email_backgroud.png);background-color:#e74777 width=600><tr><td><![endif]--><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=margin:auto width=100%><tr><td style=padding-top:72px;text-align:center><img alt=Logo border=0 height=120 src=https://cdn.jsdelivr.net/gh/OmegaLo/images@main/email_logo.png width=120><tr><td><table border=0 cellpadding=0 cellspacing=0 role=presentation width=100%><tr><td style="padding:48px 24px 0 24px;text-align:center;font-size:32px;color:#fff;font-weight:700"><span>您正在登陆</span><span style=padding-top:8px;display:block>「IGame」账号</span><tr><td style="padding:48px 20px 0 20px"><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=margin:auto><tr><td style="background:#eec312;color:#000;font-size:18px;padding:8px 40px;font-weight:700"><span>登陆码</span></table><tr><td style="padding:0 20px"><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=margin:auto><tr><td style="background-color:#fff;color:#000;font-size:40px;font-weight:700;padding:8px 16px;width:300px;text-align:center;border-radius:4px;letter-spacing:4px"><span>{}</span></table>{}<tr><td style=padding-top:48px;font-family:sans-serif;font-size:15px;line-height:20px;color:#fff><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=margin:auto><tr><td><ul style="padding:0 16px 0 32px;list-style-type:disc"><li class=list-item-first style=padding-bottom:8px>该登陆码{}分钟内有效且只能使用一次<li style=padding-bottom:8px>请勿将登陆码或登陆链接转发给他人<li class=list-item-last style=padding-bottom:8px>如果你并没有尝试登陆「IGame」账号，请忽略该邮件</ul></table></table></table><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=margin:auto width=100%><tr><td style="padding:48px 20px 72px 20px"><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=margin:auto><tr><td style=color:#fff;font-size:24px;font-weight:700;padding:16px;width:160px;text-align:center;border-radius:4px;background-color:#843fa1><span>系统邮件</span></table></table><!--[if mso]><![endif]--></div><!--[if mso | IE]><![endif]--></center>"##,
+                    verify_code,
+                    verify_code,
+                    link_html,
+                    GLOBAL_CONFIG.magic_login.expire / 60
+                )
+            }
         }
     }
 }
 
+// 修改邮箱时发送给旧邮箱的通知
+pub fn email_change_notice_html(new_addr: &str) -> String {
+    let new_addr = new_addr
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;");
+    format!(
+        r##"<!doctypehtml><html lang=zh-CN xmlns=http://www.w3.org/1999/xhtml xmlns:o=urn:schemas-microsoft-com:office:office xmlns:v=urn:schemas-microsoft-com:vml><meta charset=utf-8><meta content="width=device-width"name=viewport><meta content="IE=edge"http-equiv=X-UA-Compatible><meta name=x-apple-disable-message-reformatting><meta content="telephone=no,address=no,email=no,date=no,url=no"name=format-detection><meta content=light name=color-scheme><meta content=light name=supported-color-schemes><title>IGame邮箱变更通知</title><!--[if gte mso 9]><xml><o:officedocumentsettings><o:allowpng><o:pixelsperinch>96</o:pixelsperinch></o:officedocumentsettings></xml><![endif]--><!--[if mso]><style>*{{font-family:sans-serif!important}}</style><![endif]--><!--[if !mso]><!--><!--<![endif]--><style>:root{{color-scheme:light;supported-color-schemes:light}}body,html{{margin:0 auto!important;padding:0!important;height:100%!important;width:100%!important}}*{{-ms-text-size-adjust:100%;-webkit-text-size-adjust:100%}}div[style*="margin: 16px 0"]{{margin:0!important}}#MessageViewBody,#MessageWebViewDiv{{width:100%!important}}table,td{{mso-table-lspace:0!important;mso-table-rspace:0!important}}table{{border-spacing:0!important;border-collapse:collapse!important;table-layout:fixed!important;margin:0 auto!important}}img{{-ms-interpolation-mode:bicubic}}a{{text-decoration:none}}.aBn,.unstyle-auto-detected-links a,a[x-apple-data-detectors]{{border-bottom:0!important;cursor:default!important;color:inherit!important;text-decoration:none!important;font-size:inherit!important;font-family:inherit!important;font-weight:inherit!important;line-height:inherit!important}}.a6S{{display:none!important;opacity:.01!important}}.im{{color:inherit!important}}img.g-img+div{{display:none!important}}@media only screen and (min-device-width:320px) and (max-device-width:374px){{u~div .email-container{{min-width:320px!important}}}}@media only screen and (min-device-width:375px) and (max-device-width:413px){{u~div .email-container{{min-width:375px!important}}}}@media only screen and (min-device-width:414px){{u~div .email-container{{min-width:414px!important}}}}</style><body style=margin:0;padding:0!important;mso-line-height-rule:exactly;background-color:#fff width=100%><center aria-roledescription=email lang=en role=article style=width:100%;background-color:#fff><!--[if mso | IE]><table border=0 cellpadding=0 cellspacing=0 role=presentation width=100% style=background-color:#fff><tr><td><![endif]--><div style=max-height:0;overflow:hidden;mso-hide:all aria-hidden=true>您的「IGame」账号正在修改邮箱，新的邮箱：{}</div><div style=display:none;font-size:1px;line-height:1px;max-height:0;max-width:0;opacity:0;overflow:hidden;mso-hide:all></div><div style="max-width:600px;margin:0 auto;background-image:url(https://cdn.jsdelivr.net/gh/OmegaLo/images@main/email_backgroud.png);background-color:#e74777"class=email-container><!--[if mso]><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=background-image:url(https://cdn.jsdelivr.net/gh/OmegaLo/images@main/email_backgroud.png);background-color:#e74777 width=600><tr><td><![endif]--><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=margin:auto width=100%><tr><td style=padding-top:72px;text-align:center><img alt=Logo border=0 height=120 src=https://cdn.jsdelivr.net/gh/OmegaLo/images@main/email_logo.png width=120><tr><td><table border=0 cellpadding=0 cellspacing=0 role=presentation width=100%><tr><td style="padding:48px 24px 0 24px;text-align:center;font-size:32px;color:#fff;font-weight:700"><span>您的「IGame」账号</span><span style=padding-top:8px;display:block>正在修改邮箱</span><tr><td style="padding:48px 20px 0 20px"><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=margin:auto><tr><td style="background:#eec312;color:#000;font-size:18px;padding:8px 40px;font-weight:700"><span>新的邮箱</span></table><tr><td style="padding:0 20px"><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=margin:auto><tr><td style="background-color:#fff;color:#000;font-size:20px;font-weight:700;padding:16px;width:360px;word-break:break-all;text-align:center;border-radius:4px"><span>{}</span></table><tr><td style=padding-top:48px;font-family:sans-serif;font-size:15px;line-height:20px;color:#fff><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=margin:auto><tr><td><ul style="padding:0 16px 0 32px;list-style-type:disc"><li class=list-item-first style=padding-bottom:8px>新的邮箱完成验证后，该邮箱将不能再用于登录<li class=list-item-last style=padding-bottom:8px>如果这不是你本人的操作，请立即修改密码并联系管理员</ul></table></table></table><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=margin:auto width=100%><tr><td style="padding:48px 20px 72px 20px"><table border=0 cellpadding=0 cellspacing=0 role=presentation align=center style=margin:auto><tr><td style=color:#fff;font-size:24px;font-weight:700;padding:16px;width:160px;text-align:center;border-radius:4px;background-color:#843fa1><span>系统邮件</span></table></table><!--[if mso]><![endif]--></div><!--[if mso | IE]><![endif]--></center>"##,
+        new_addr, new_addr
+    )
+}
+
 #[derive(Deserialize)]
 pub struct SendEmailInput {
     pub addr: String,
@@ -1,9 +1,16 @@
+pub mod api_key;
 pub mod app;
 pub mod app_subscribe;
 pub mod article;
+pub mod captcha;
 pub mod email;
 pub mod notice;
+pub mod oidc;
 pub mod resource;
 pub mod role;
+pub mod session;
 pub mod tag;
+pub mod totp;
+pub mod trade;
 pub mod user;
+pub mod vip;
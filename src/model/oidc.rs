@@ -0,0 +1,46 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize)]
+pub struct GetOidcProvidersOutputItem {
+    pub provider_id: String,
+    pub name: String,
+}
+
+pub type GetOidcProvidersOutput = Vec<GetOidcProvidersOutputItem>;
+
+#[derive(Debug, Deserialize)]
+pub struct OidcProviderPath {
+    pub provider_id: String,
+}
+
+// 客户端打开authorization_url，并保存state_token用于回调后完成登陆或绑定
+#[derive(Debug, Serialize)]
+pub struct GetOidcAuthorizeOutput {
+    pub authorization_url: String,
+    pub state: String,
+    pub state_token: String,
+}
+
+// state与code为提供者回调redirect_uri时附带的参数
+#[derive(Debug, Deserialize)]
+pub struct OidcCallbackInput {
+    pub state_token: String,
+    pub state: String,
+    pub code: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetMyIdentitiesOutputItem {
+    pub provider_id: String,
+    pub email: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+pub type GetMyIdentitiesOutput = Vec<GetMyIdentitiesOutputItem>;
+
+#[derive(Debug, Serialize)]
+pub struct PostMyIdentityOutput {
+    pub provider_id: String,
+    pub email: Option<String>,
+}
@@ -2,7 +2,8 @@ use chrono::{DateTime, Utc};
 use derive_more::Display;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Display)]
+#[derive(Debug, Display, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
 pub enum Permission {
     #[display(fmt = "get_user")]
     GetUser,
@@ -18,21 +19,56 @@ pub enum Permission {
     FreeInstall,
     #[display(fmt = "ignore_exp")]
     IgnoreExp,
+    #[display(fmt = "manage_role")]
+    ManageRole,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
-pub enum RoleID {
-    #[serde(rename = "admin")]
-    Admin = 1,
-    #[serde(rename = "user")]
-    User = 2,
-    #[serde(rename = "vip")]
-    Vip = 3,
+impl Permission {
+    // 代码中使用的所有权限，与igame.permission中的key一一对应
+    pub const ALL: [Permission; 8] = [
+        Permission::GetUser,
+        Permission::CreateUser,
+        Permission::SendEmail,
+        Permission::CreateNotice,
+        Permission::FreeDownload,
+        Permission::FreeInstall,
+        Permission::IgnoreExp,
+        Permission::ManageRole,
+    ];
+
+    // igame.permission中的key
+    pub fn key(&self) -> String {
+        self.to_string()
+    }
+
+    // 数据库中存在但代码中没有用到的权限返回None
+    pub fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.iter().find(|v| v.key() == key).copied()
+    }
+}
+
+// 代码中需要引用的内置角色，对应igame.role中的key
+#[derive(Debug, Clone, Copy)]
+pub enum RoleKey {
+    Admin,
+    User,
+    Vip,
 }
 
-impl RoleID {
-    pub fn to_i32(&self) -> i32 {
-        *self as i32
+impl RoleKey {
+    pub const ALL: [RoleKey; 3] = [RoleKey::Admin, RoleKey::User, RoleKey::Vip];
+
+    // 内置角色不能删除
+    pub fn is_builtin(key: &str) -> bool {
+        Self::ALL.iter().any(|v| v.as_str() == key)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RoleKey::Admin => "admin",
+            RoleKey::User => "user",
+            RoleKey::Vip => "vip",
+        }
     }
 }
 
@@ -42,3 +78,76 @@ pub struct Role {
     pub name: String,
     pub expire_at: Option<DateTime<Utc>>,
 }
+
+#[derive(Debug, Serialize)]
+pub struct RoleOutput {
+    pub role_id: i32,
+    pub key: String,
+    pub name: String,
+    pub permissions: Vec<String>,
+}
+
+pub type GetRolesOutput = Vec<RoleOutput>;
+
+// key只能包含小写字母、数字与下划线，创建后不能修改
+#[derive(Debug, Deserialize)]
+pub struct PostRoleInput {
+    pub key: String,
+    pub name: String,
+    #[serde(default)]
+    pub permissions: Vec<Permission>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RolePath {
+    pub role_key: String,
+}
+
+// 使用permissions替换角色原有的全部权限
+#[derive(Debug, Deserialize)]
+pub struct PutRolePermissionsInput {
+    pub permissions: Vec<Permission>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UserRolePath {
+    pub user_id: i32,
+    pub role_key: String,
+}
+
+// expire_at为空表示永久有效
+#[derive(Debug, Deserialize)]
+pub struct PostUserRoleInput {
+    pub expire_at: Option<DateTime<Utc>>,
+}
+
+// expire_at为空表示改为永久有效
+#[derive(Debug, Deserialize)]
+pub struct PatchUserRoleInput {
+    pub expire_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserRoleOutput {
+    pub user_id: i32,
+    pub role_key: String,
+    pub expire_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetUserRoleGrantsPath {
+    pub user_id: i32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetUserRoleGrantsOutputItem {
+    pub grant_id: i32,
+    pub role_key: String,
+    pub action: String,
+    pub previous_expire_at: Option<DateTime<Utc>>,
+    pub expire_at: Option<DateTime<Utc>>,
+    pub operator_id: Option<i32>,
+    pub created_at: DateTime<Utc>,
+}
+
+pub type GetUserRoleGrantsOutput = Vec<GetUserRoleGrantsOutputItem>;
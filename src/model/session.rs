@@ -0,0 +1,19 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize)]
+pub struct GetMySessionsOutputItem {
+    pub session_id: String,
+    pub device: String,
+    pub ip: String,
+    pub current: bool,
+    pub login_at: DateTime<Utc>,
+    pub last_used_at: DateTime<Utc>,
+}
+
+pub type GetMySessionsOutput = Vec<GetMySessionsOutputItem>;
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteMySessionPath {
+    pub session_id: String,
+}
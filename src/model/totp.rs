@@ -0,0 +1,32 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize)]
+pub struct PostMyTotpOutput {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PostMyTotpConfirmInput {
+    pub code: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PostMyTotpConfirmOutput {
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteMyTotpInput {
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PostMyRecoveryCodesInput {
+    pub code: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PostMyRecoveryCodesOutput {
+    pub recovery_codes: Vec<String>,
+}
@@ -0,0 +1,14 @@
+// igame.trade中的type
+#[derive(Debug, Clone, Copy)]
+pub enum TradeType {
+    // 下载资源
+    ResourceDownload = 1,
+    // 购买会员
+    VipPurchase = 2,
+}
+
+impl TradeType {
+    pub fn as_i16(&self) -> i16 {
+        *self as i16
+    }
+}
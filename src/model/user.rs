@@ -1,7 +1,7 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
-use crate::model::role::{Role, RoleID};
+use crate::model::{captcha::CaptchaInput, role::Role};
 
 #[derive(Debug, Serialize)]
 pub struct User {
@@ -25,7 +25,8 @@ pub struct PostUserInput {
     pub email: String,
     pub nick_name: String,
     pub password: String,
-    pub role: RoleID,
+    // 角色的key
+    pub role: String,
 }
 
 #[derive(Debug, Serialize)]
@@ -47,17 +48,84 @@ pub struct PostUserResetPasswordOutput {
     pub refresh_token: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct PostMyPasswordInput {
+    pub old_password: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PostMyPasswordOutput {
+    pub user_id: i32,
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PostMyEmailInput {
+    pub password: String,
+    pub new_email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PostMyEmailConfirmInput {
+    pub new_email: String,
+    pub verify_code: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PostMyEmailConfirmOutput {
+    pub user_id: i32,
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteMyselfInput {
+    pub password: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeleteMyselfOutput {
+    pub user_id: i32,
+    pub delete_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetMyExportOutput {
+    pub user_id: i32,
+    pub exported_at: DateTime<Utc>,
+    pub archive: serde_json::Value,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct PostUserLoginInput {
     pub email: String,
     pub password: String,
 }
 
+// 开启了两步验证时，只返回challenge_token，需要再调用/user/login/totp完成登陆
 #[derive(Debug, Serialize)]
 pub struct PostUserLoginOutput {
     pub user_id: i32,
-    pub access_token: String,
-    pub refresh_token: String,
+    pub totp_required: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub challenge_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PostUserLoginTotpInput {
+    pub challenge_token: String,
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PostUserLoginMagicInput {
+    pub email: String,
+    pub code: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -66,6 +134,7 @@ pub struct PostUserRegisterInput {
     pub password: String,
     pub nick_name: String,
     pub verify_code: String,
+    pub captcha: CaptchaInput,
 }
 
 #[derive(Debug, Serialize)]
@@ -87,6 +156,11 @@ pub struct PostNewTokenOutput {
     pub refresh_token: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct PostUserLogoutInput {
+    pub refresh_token: String,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct GetUserPath {
     pub user_id: i32,
@@ -105,6 +179,25 @@ pub struct GetUserOutput {
     pub created_at: DateTime<Utc>,
 }
 
+// 为空的字段保持不变
+#[derive(Debug, Deserialize)]
+pub struct PatchMyselfInput {
+    pub nick_name: Option<String>,
+    pub avatar_url: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AvatarThumbnail {
+    pub size: u32,
+    pub url: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PostMyAvatarOutput {
+    pub avatar_url: String,
+    pub thumbnails: Vec<AvatarThumbnail>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct GetMyselfOutput {
     pub user_id: i32,
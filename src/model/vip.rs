@@ -0,0 +1,25 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize)]
+pub struct GetVipPlansOutputItem {
+    pub plan_id: String,
+    pub name: String,
+    pub days: i64,
+    pub price: i32,
+}
+
+pub type GetVipPlansOutput = Vec<GetVipPlansOutputItem>;
+
+#[derive(Debug, Deserialize)]
+pub struct PostVipPurchaseInput {
+    pub plan_id: String,
+}
+
+// expire_at为购买后会员的过期时间
+#[derive(Debug, Serialize)]
+pub struct PostVipPurchaseOutput {
+    pub trade_id: i32,
+    pub remain_coin: i32,
+    pub expire_at: DateTime<Utc>,
+}
@@ -0,0 +1,273 @@
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::Error;
+use futures::future::{ok, Ready};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::config::{RateLimitKey, RateLimitRule, GLOBAL_CONFIG};
+use crate::error::ResponseError;
+use crate::util::req_parse::{authenticate, get_client_ip};
+
+// 超过该数量后清理已经回满的令牌桶
+const PRUNE_THRESHOLD: usize = 10000;
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl Bucket {
+    fn new(rule: &RateLimitRule, now: Instant) -> Self {
+        Self {
+            tokens: capacity(rule),
+            updated_at: now,
+        }
+    }
+
+    // 按经过的时间补充令牌后取走一个，令牌不足时返回需要等待的秒数
+    fn take(&mut self, rule: &RateLimitRule, now: Instant) -> Option<u64> {
+        let capacity = capacity(rule);
+        let refill_rate = capacity / rule.period.max(1) as f64;
+        self.tokens = (self.tokens
+            + now.duration_since(self.updated_at).as_secs_f64() * refill_rate)
+            .min(capacity);
+        self.updated_at = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return None;
+        }
+        // 向上取整，避免客户端过早重试
+        Some(((1.0 - self.tokens) / refill_rate).ceil() as u64)
+    }
+}
+
+// 容量为0时按1处理，否则令牌永远不会补充，等待时间会溢出
+fn capacity(rule: &RateLimitRule) -> f64 {
+    rule.capacity.max(1) as f64
+}
+
+// 按路由模式限制请求频率的中间件，规则来自配置文件中的rate_limit
+#[derive(Clone)]
+pub struct RateLimiter {
+    buckets: Arc<Mutex<HashMap<(usize, String), Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self {
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimiter
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RateLimiterMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RateLimiterMiddleware {
+            service,
+            buckets: self.buckets.clone(),
+        })
+    }
+}
+
+#[doc(hidden)]
+pub struct RateLimiterMiddleware<S> {
+    service: S,
+    buckets: Arc<Mutex<HashMap<(usize, String), Bucket>>>,
+}
+
+impl<S> RateLimiterMiddleware<S> {
+    // 从请求对应的所有令牌桶中各取走一个令牌，任意一个不足时返回需要等待的秒数
+    fn acquire(&self, req: &mut ServiceRequest) -> Option<(u64, String)> {
+        let pattern = req.match_pattern()?;
+        let rules: Vec<(usize, &RateLimitRule)> = GLOBAL_CONFIG
+            .rate_limit
+            .iter()
+            .enumerate()
+            .filter(|(_, rule)| rule.pattern == pattern)
+            .filter(|(_, rule)| match &rule.method {
+                Some(method) => method.eq_ignore_ascii_case(req.method().as_str()),
+                None => true,
+            })
+            .collect();
+        if rules.is_empty() {
+            return None;
+        }
+
+        let (http_req, _) = req.parts_mut();
+        let ip = get_client_ip(http_req);
+        let user_id = authenticate(http_req).ok().flatten().map(|v| v.user_id);
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > PRUNE_THRESHOLD {
+            prune(&mut buckets, &GLOBAL_CONFIG.rate_limit, now);
+        }
+
+        let mut retry_after = 0;
+        let mut limited_key = String::new();
+        for (index, rule) in rules {
+            let key = match (rule.key, user_id) {
+                (RateLimitKey::User, Some(user_id)) => format!("user:{}", user_id),
+                _ => format!("ip:{}", ip),
+            };
+            let bucket = buckets
+                .entry((index, key.clone()))
+                .or_insert_with(|| Bucket::new(rule, now));
+            if let Some(wait) = bucket.take(rule, now) {
+                if wait > retry_after {
+                    retry_after = wait;
+                    limited_key = key;
+                }
+            }
+        }
+        match retry_after {
+            0 => None,
+            _ => Some((retry_after, limited_key)),
+        }
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimiterMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    actix_web::dev::forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        if let Some((retry_after, key)) = self.acquire(&mut req) {
+            let err = ResponseError::rate_limited_err(
+                &format!("请求过于频繁，请在{}秒后重试", retry_after),
+                retry_after,
+                &format!(
+                    "[{}]请求{}超过频率限制",
+                    key,
+                    req.match_pattern().unwrap_or_default()
+                ),
+            );
+            return Box::pin(async move { Err(err.into()) });
+        }
+        let fut = self.service.call(req);
+        Box::pin(fut)
+    }
+}
+
+// 清理已经回满的令牌桶，以及规则已被删除的令牌桶
+fn prune(buckets: &mut HashMap<(usize, String), Bucket>, rules: &[RateLimitRule], now: Instant) {
+    buckets.retain(|(index, _), bucket| match rules.get(*index) {
+        Some(rule) => now.duration_since(bucket.updated_at).as_secs() < rule.period,
+        None => false,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RateLimitKey;
+    use std::time::Duration;
+
+    fn rule(capacity: u32, period: u64) -> RateLimitRule {
+        RateLimitRule {
+            pattern: "/test".to_string(),
+            method: None,
+            key: RateLimitKey::Ip,
+            capacity,
+            period,
+        }
+    }
+
+    #[test]
+    fn bucket_allows_burst_up_to_capacity() {
+        let rule = rule(3, 30);
+        let now = Instant::now();
+        let mut bucket = Bucket::new(&rule, now);
+        for _ in 0..3 {
+            assert_eq!(bucket.take(&rule, now), None);
+        }
+        // 每10秒补充一个令牌
+        assert_eq!(bucket.take(&rule, now), Some(10));
+    }
+
+    #[test]
+    fn bucket_refills_over_time() {
+        let rule = rule(2, 20);
+        let now = Instant::now();
+        let mut bucket = Bucket::new(&rule, now);
+        assert_eq!(bucket.take(&rule, now), None);
+        assert_eq!(bucket.take(&rule, now), None);
+        // 过了一半的补充时间，等待时间向上取整
+        let later = now + Duration::from_secs(5);
+        assert_eq!(bucket.take(&rule, later), Some(5));
+        let later = now + Duration::from_secs(10);
+        assert_eq!(bucket.take(&rule, later), None);
+        assert_eq!(bucket.take(&rule, later), Some(10));
+    }
+
+    #[test]
+    fn bucket_does_not_exceed_capacity() {
+        let rule = rule(2, 20);
+        let now = Instant::now();
+        let mut bucket = Bucket::new(&rule, now);
+        let later = now + Duration::from_secs(1000);
+        assert_eq!(bucket.take(&rule, later), None);
+        assert_eq!(bucket.take(&rule, later), None);
+        assert!(bucket.take(&rule, later).is_some());
+    }
+
+    #[test]
+    fn zero_period_does_not_divide_by_zero() {
+        let rule = rule(1, 0);
+        let now = Instant::now();
+        let mut bucket = Bucket::new(&rule, now);
+        assert_eq!(bucket.take(&rule, now), None);
+        assert_eq!(bucket.take(&rule, now), Some(1));
+    }
+
+    #[test]
+    fn zero_capacity_is_treated_as_one() {
+        let rule = rule(0, 10);
+        let now = Instant::now();
+        let mut bucket = Bucket::new(&rule, now);
+        assert_eq!(bucket.take(&rule, now), None);
+        assert_eq!(bucket.take(&rule, now), Some(10));
+    }
+
+    #[test]
+    fn prune_removes_full_and_orphaned_buckets() {
+        let rules = [rule(1, 10)];
+        let now = Instant::now();
+        let mut buckets = HashMap::new();
+        buckets.insert((0, "ip:a".to_string()), Bucket::new(&rules[0], now));
+        buckets.insert(
+            (0, "ip:b".to_string()),
+            Bucket::new(&rules[0], now + Duration::from_secs(15)),
+        );
+        // 规则已被删除
+        buckets.insert(
+            (1, "ip:c".to_string()),
+            Bucket::new(&rules[0], now + Duration::from_secs(15)),
+        );
+        prune(&mut buckets, &rules, now + Duration::from_secs(20));
+        let mut keys: Vec<_> = buckets.keys().map(|(_, key)| key.as_str()).collect();
+        keys.sort_unstable();
+        assert_eq!(keys, ["ip:b"]);
+    }
+}
@@ -80,6 +80,19 @@ impl ResourceProviderShare {
         return Err(default_err);
     }
 
+    pub async fn upload_file(
+        &self,
+        provider_id: &str,
+        resource_path: &str,
+        content: Vec<u8>,
+    ) -> Result<(), ResponseError> {
+        self.provider
+            .read()
+            .await
+            .upload_file(provider_id, resource_path, content)
+            .await
+    }
+
     pub async fn write_to_config_file(&self) {
         self.provider.read().await.write_to_config_file().await;
     }
@@ -186,6 +199,23 @@ impl ResourceProvider {
         }
     }
 
+    pub async fn upload_file(
+        &self,
+        provider_id: &str,
+        resource_path: &str,
+        content: Vec<u8>,
+    ) -> Result<(), ResponseError> {
+        match self.clients.get(provider_id) {
+            Some(client) if self.client_info_manager.is_available(provider_id) => {
+                client.upload_file(resource_path, content).await
+            }
+            _ => Err(ResponseError::resource_provider_unavailable_err(
+                "服务暂不可用，上传文件失败，请稍后重试",
+                &format!("[提供者{}]不存在或处于暂停中", provider_id),
+            )),
+        }
+    }
+
     pub async fn write_to_config_file(&self) {
         let mut msgraph_configs: Vec<MSGraphConfig> = Vec::new();
         for client in self.clients.values() {
@@ -441,6 +471,51 @@ impl MSGraphClient {
         Ok(response_de.download_url)
     }
 
+    // 使用简单上传接口，只支持4MB以内的文件
+    async fn upload_file(
+        &self,
+        resource_path: &str,
+        content: Vec<u8>,
+    ) -> Result<(), ResponseError> {
+        let response = self
+            .request_client
+            .put(format!(
+                "{}drives/{}/root:{}:/content",
+                self.graph_api, self.drive_id, resource_path
+            ))
+            .header("Authorization", format!("Bearer {}", self.access_token))
+            .body(content)
+            .send()
+            .await
+            .map_err(|e| {
+                tracing::error!(
+                    "[提供者{}]尝试发送upload_file请求失败: {}",
+                    self.config.id,
+                    e
+                );
+                e
+            })?;
+
+        if !response.status().is_success() {
+            return Err(ResponseError::resource_provider_unavailable_err(
+                "上传文件失败，请稍后重试",
+                &format!(
+                    "[提供者{}]发送upload_file的响应状态码不正确: {}, 内容: {}",
+                    self.config.id,
+                    response.status().as_str(),
+                    response.text().await.unwrap_or_default(),
+                ),
+            ));
+        }
+
+        tracing::info!(
+            "[提供者{}]上传文件成功, 资源路径: {}",
+            self.config.id,
+            resource_path
+        );
+        Ok(())
+    }
+
     async fn refresh_token(&mut self) -> Result<(), ResponseError> {
         #[derive(Deserialize)]
         struct Response {
@@ -0,0 +1,219 @@
+use actix_web::{body::Body, delete, get, post, web, HttpResponse};
+use chrono::{Duration, Utc};
+use deadpool_postgres::{Client, Pool};
+use futures::future::try_join3;
+
+use crate::config::GLOBAL_CONFIG;
+use crate::db::Type as DBType;
+use crate::error::ResponseError;
+use crate::model::api_key::{
+    DeleteMyApiKeyPath, GetMyApiKeysOutput, GetMyApiKeysOutputItem, PostMyApiKeyInput,
+    PostMyApiKeyOutput,
+};
+use crate::util::{
+    api_key,
+    req_parse::{AuthMethod, AuthUser},
+};
+
+// API密钥名称的最大长度
+const MAX_NAME_LEN: usize = 32;
+
+// 创建API密钥，只能使用access_token创建，防止泄露的密钥派生出新的密钥
+#[post("/myself/api_key")]
+pub async fn post_my_api_key(
+    auth_user: AuthUser,
+    db_pool: web::Data<Pool>,
+    input: web::Json<PostMyApiKeyInput>,
+) -> Result<HttpResponse, ResponseError> {
+    let mut client: Client = db_pool.get().await?;
+    let user_id = auth_user.access_token_claims()?.user_id;
+
+    let name = input.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+        return Err(ResponseError::input_err(
+            &format!("API密钥名称不能为空且不能超过{}个字符", MAX_NAME_LEN),
+            &format!("[用户ID: {}]API密钥名称[{}]不合法", user_id, name),
+        ));
+    }
+    let now = Utc::now();
+    let max_lifetime = GLOBAL_CONFIG.api_key.max_lifetime;
+    match input.expire_at {
+        Some(expire_at) if expire_at <= now => {
+            return Err(ResponseError::input_err(
+                "API密钥的过期时间必须晚于当前时间",
+                &format!("[用户ID: {}]API密钥过期时间[{}]已过去", user_id, expire_at),
+            ));
+        }
+        Some(expire_at)
+            if max_lifetime > 0 && expire_at > now + Duration::seconds(max_lifetime) =>
+        {
+            return Err(ResponseError::input_err(
+                &format!("API密钥的有效期不能超过{}天", max_lifetime / (24 * 60 * 60)),
+                &format!(
+                    "[用户ID: {}]API密钥过期时间[{}]超过上限",
+                    user_id, expire_at
+                ),
+            ));
+        }
+        None if max_lifetime > 0 => {
+            return Err(ResponseError::input_err(
+                "请设置API密钥的过期时间",
+                &format!("[用户ID: {}]API密钥未设置过期时间", user_id),
+            ));
+        }
+        _ => {}
+    }
+    let mut scopes: Vec<String> = input.scopes.iter().map(|v| v.key()).collect();
+    scopes.sort();
+    scopes.dedup();
+
+    // 锁定用户行，同一用户并发创建时依次统计数量并插入，不会超过上限
+    let transaction = client.transaction().await?;
+    let (s1, s2, s3) = try_join3(
+        transaction.prepare_typed_cached(
+            "SELECT id FROM igame.user WHERE id = $1 FOR UPDATE",
+            &[DBType::INT4],
+        ),
+        // 统计有效的API密钥数量
+        transaction.prepare_typed_cached(
+            "SELECT count(*) FROM igame.api_key
+            WHERE user_id = $1 AND revoked_at IS NULL AND (expire_at IS NULL OR expire_at > now())",
+            &[DBType::INT4],
+        ),
+        transaction.prepare_typed_cached(
+            "INSERT INTO igame.api_key(user_id, name, key_hash, key_prefix, scopes, expire_at)
+            VALUES($1, $2, $3, $4, $5, $6)
+            RETURNING id",
+            &[
+                DBType::INT4,
+                DBType::TEXT,
+                DBType::BYTEA,
+                DBType::TEXT,
+                DBType::TEXT_ARRAY,
+                DBType::TIMESTAMPTZ,
+            ],
+        ),
+    )
+    .await?;
+
+    transaction.execute(&s1, &[&user_id]).await?;
+    let r2 = transaction.query_one(&s2, &[&user_id]).await?;
+    let count: i64 = r2.get(0);
+    if count >= GLOBAL_CONFIG.api_key.max_per_user {
+        return Err(ResponseError::input_err(
+            &format!(
+                "最多只能持有{}个API密钥，请先吊销不再使用的密钥",
+                GLOBAL_CONFIG.api_key.max_per_user
+            ),
+            &format!("[用户ID: {}]API密钥数量已达上限", user_id),
+        ));
+    }
+
+    let key = api_key::generate_api_key();
+    let key_prefix = api_key::display_prefix(&key);
+    let r3 = transaction
+        .query_one(
+            &s3,
+            &[
+                &user_id,
+                &name,
+                &api_key::hash_api_key(&key),
+                &key_prefix,
+                &scopes,
+                &input.expire_at,
+            ],
+        )
+        .await?;
+    let key_id: i32 = r3.get("id");
+    transaction.commit().await?;
+    tracing::info!(
+        "[用户ID: {}]创建了API密钥[{}]，权限: {:?}",
+        user_id,
+        key_id,
+        scopes
+    );
+
+    Ok(HttpResponse::Ok().json(PostMyApiKeyOutput {
+        key_id,
+        name: name.to_string(),
+        api_key: key,
+        key_prefix,
+        scopes,
+        expire_at: input.expire_at,
+    }))
+}
+
+// 获取当前用户未吊销的API密钥
+#[get("/myself/api_keys")]
+pub async fn get_my_api_keys(
+    auth_user: AuthUser,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ResponseError> {
+    let client: Client = db_pool.get().await?;
+    let user_id = auth_user.user_id;
+
+    let s1 = client
+        .prepare_typed_cached(
+            "SELECT id, name, key_prefix, scopes, expire_at, used_at, created_at
+            FROM igame.api_key
+            WHERE user_id = $1 AND revoked_at IS NULL
+            ORDER BY created_at DESC",
+            &[DBType::INT4],
+        )
+        .await?;
+    let r1s = client.query(&s1, &[&user_id]).await?;
+
+    let output: GetMyApiKeysOutput = r1s
+        .iter()
+        .map(|r1| GetMyApiKeysOutputItem {
+            key_id: r1.get("id"),
+            name: r1.get("name"),
+            key_prefix: r1.get("key_prefix"),
+            scopes: r1.get("scopes"),
+            expire_at: r1.get("expire_at"),
+            used_at: r1.get("used_at"),
+            created_at: r1.get("created_at"),
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(output))
+}
+
+// 吊销API密钥，使用API密钥时只能吊销自身，防止泄露的密钥吊销用户的其他密钥
+#[delete("/myself/api_key/{key_id}")]
+pub async fn delete_my_api_key(
+    auth_user: AuthUser,
+    db_pool: web::Data<Pool>,
+    path: web::Path<DeleteMyApiKeyPath>,
+) -> Result<HttpResponse, ResponseError> {
+    let client: Client = db_pool.get().await?;
+    let user_id = auth_user.user_id;
+    if let AuthMethod::ApiKey(identity) = &auth_user.method {
+        if identity.key_id != path.key_id {
+            return Err(ResponseError::permission_err(
+                "API密钥只能吊销自身，吊销其他密钥请登陆后进行",
+                &format!(
+                    "[用户ID: {}]使用API密钥[{}]尝试吊销API密钥[{}]",
+                    user_id, identity.key_id, path.key_id
+                ),
+            ));
+        }
+    }
+
+    let s1 = client
+        .prepare_typed_cached(
+            "UPDATE igame.api_key
+            SET revoked_at = now()
+            WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL",
+            &[DBType::INT4, DBType::INT4],
+        )
+        .await?;
+    if client.execute(&s1, &[&path.key_id, &user_id]).await? == 0 {
+        return Err(ResponseError::input_err(
+            "该API密钥不存在或已吊销",
+            &format!("[用户ID: {}]API密钥[{}]不存在", user_id, path.key_id),
+        ));
+    }
+    tracing::info!("[用户ID: {}]吊销了API密钥[{}]", user_id, path.key_id);
+
+    Ok(HttpResponse::Ok().body(Body::Empty))
+}
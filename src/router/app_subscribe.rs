@@ -1,20 +1,20 @@
-use actix_web::{body::Body, get, post, web, HttpRequest, HttpResponse};
+use actix_web::{body::Body, get, post, web, HttpResponse};
 use deadpool_postgres::{Client, Pool};
 
 use crate::db::Type as DBType;
 use crate::error::{is_db_dup_unique_error, is_db_zero_line_error, ResponseError};
 use crate::model::app_subscribe::{AppSubscribePath, GetAppSubscribeStatusOutput};
-use crate::util::req_parse::get_user_id;
+use crate::util::req_parse::AuthUser;
 
 // 获取app订阅状态
 #[get("/app/{app_id}/subscribe_status")]
 pub async fn get_app_subscribe_status(
-    req: HttpRequest,
+    auth_user: AuthUser,
     db_pool: web::Data<Pool>,
     path: web::Path<AppSubscribePath>,
 ) -> Result<HttpResponse, ResponseError> {
     let client: Client = db_pool.get().await?;
-    let user_id = get_user_id(&req)?;
+    let user_id = auth_user.user_id;
     let app_id = path.app_id;
 
     let s1 = client
@@ -43,12 +43,12 @@ pub async fn get_app_subscribe_status(
 // 订阅app
 #[post("/app/{app_id}/subscribe")]
 pub async fn post_app_subscribe(
-    req: HttpRequest,
+    auth_user: AuthUser,
     db_pool: web::Data<Pool>,
     path: web::Path<AppSubscribePath>,
 ) -> Result<HttpResponse, ResponseError> {
     let client: Client = db_pool.get().await?;
-    let user_id = get_user_id(&req)?;
+    let user_id = auth_user.user_id;
     let app_id = path.app_id;
 
     let s1 = client
@@ -72,12 +72,12 @@ pub async fn post_app_subscribe(
 // 取消订阅文章
 #[post("/app/{app_id}/unsubscribe")]
 pub async fn post_app_unsubscribe(
-    req: HttpRequest,
+    auth_user: AuthUser,
     db_pool: web::Data<Pool>,
     path: web::Path<AppSubscribePath>,
 ) -> Result<HttpResponse, ResponseError> {
     let client: Client = db_pool.get().await?;
-    let user_id = get_user_id(&req)?;
+    let user_id = auth_user.user_id;
     let app_id = path.app_id;
 
     let s1 = client
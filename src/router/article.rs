@@ -1,6 +1,6 @@
 use actix_web::{get, web, HttpRequest, HttpResponse};
 use deadpool_postgres::{Client, Pool};
-use futures::future::{try_join, try_join4};
+use futures::future::try_join3;
 use serde_json::json;
 
 use crate::db::Type as DBType;
@@ -13,7 +13,7 @@ use crate::model::{
     role::Permission,
     tag::Tag,
 };
-use crate::util::req_parse::{get_access_token, get_user_id};
+use crate::util::{permission, req_parse::OptionalUser};
 
 // 获取文章的封面
 #[get("/article/covers")]
@@ -144,11 +144,12 @@ pub async fn get_article_amount(db_pool: web::Data<Pool>) -> Result<HttpResponse
 #[get("/article/{article_id}")]
 pub async fn get_article(
     req: HttpRequest,
+    optional_user: OptionalUser,
     db_pool: web::Data<Pool>,
     path: web::Path<GetArticlePath>,
 ) -> Result<HttpResponse, ResponseError> {
     let client: Client = db_pool.get().await?;
-    let (s1, s2, s3, s4) = try_join4(
+    let (s1, s2, s3) = try_join3(
         // 返回完整的文章信息
         client.prepare_typed_cached(
             "WITH temp AS (
@@ -175,45 +176,25 @@ pub async fn get_article(
             "SELECT exp FROM igame.user WHERE id = $1",
             &[DBType::INT4],
         ),
-        // 检验相关权限
-        client.prepare_typed_cached(
-            &format!(
-                "SELECT bool_or({})
-                FROM igame.role 
-                WHERE id IN (
-                    SELECT role_id 
-                    FROM igame.user_role 
-                    WHERE user_id = $1
-                    AND (expire_at IS NULL OR (expire_at IS NOT NULL AND expire_at > now()))
-                )",
-                Permission::IgnoreExp.to_string()
-            ),
-            &[DBType::INT4],
-        ),
     ).await?;
 
     let r1 = client.query_one(&s1, &[&path.article_id]).await?;
     let article_id: i32 = r1.get("id");
     let allowed_exp: i32 = r1.get("allowed_exp");
-    let is_login = get_access_token(&req).is_some();
     let mut can_view = true;
     // 如果该文章的allowed_exp大于0，那么检验用户的exp
     if allowed_exp > 0 {
-        if !is_login {
-            can_view = false;
-        } else {
-            let user_id = get_user_id(&req)?;
-            let (r3, r4) = try_join(
-                client.query_one(&s3, &[&user_id]),
-                client.query_one(&s4, &[&user_id]),
-            )
-            .await?;
+        if let Some(auth_user) = optional_user.0 {
+            let r3 = client.query_one(&s3, &[&auth_user.user_id]).await?;
+            let permissions = permission::get_permissions(&req, &client, &auth_user).await?;
             let exp: i32 = r3.get("exp");
-            let can_ignore_exp: bool = r4.get("ignore_exp");
+            let can_ignore_exp = permissions.contains(Permission::IgnoreExp);
             // 如果用户没有ignore_exp权限，且exp小于文章的allowed_exp
             if !can_ignore_exp && exp < allowed_exp {
                 can_view = false;
             }
+        } else {
+            can_view = false;
         }
     }
     // 如果不能浏览，返回错误
@@ -0,0 +1,18 @@
+use actix_web::{get, HttpResponse};
+use chrono::{TimeZone, Utc};
+
+use crate::error::ResponseError;
+use crate::model::captcha::GetCaptchaOutput;
+use crate::util::captcha;
+
+// 获取一道工作量证明题目，用于注册与发送验证邮件前的人机验证
+#[get("/captcha")]
+pub async fn get_captcha() -> Result<HttpResponse, ResponseError> {
+    let (challenge, difficulty, expire_at) = captcha::generate_challenge()?;
+
+    Ok(HttpResponse::Ok().json(GetCaptchaOutput {
+        challenge,
+        difficulty,
+        expire_at: Utc.timestamp(expire_at as i64, 0),
+    }))
+}
@@ -0,0 +1,10 @@
+use actix_web::{get, HttpResponse};
+
+use crate::error::ResponseError;
+use crate::util::jwt_key;
+
+// 获取校验凭证使用的公钥，其他服务可以据此校验凭证而不需要token_secret
+#[get("/.well-known/jwks.json")]
+pub async fn get_jwks() -> Result<HttpResponse, ResponseError> {
+    Ok(HttpResponse::Ok().json(jwt_key::jwk_set()))
+}
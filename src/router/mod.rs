@@ -1,13 +1,26 @@
+mod api_key;
 mod app;
 mod app_subscribe;
 mod article;
+mod captcha;
 mod email;
+mod jwks;
 mod notice;
+mod oidc;
 mod resource;
+mod role;
+mod session;
 mod tag;
+mod totp;
 mod user;
+mod vip;
 
 pub fn register(cfg: &mut actix_web::web::ServiceConfig) {
+    cfg.service((
+        api_key::post_my_api_key,
+        api_key::get_my_api_keys,
+        api_key::delete_my_api_key,
+    ));
     cfg.service(app::get_app);
     cfg.service((
         app_subscribe::get_app_subscribe_status,
@@ -19,22 +32,63 @@ pub fn register(cfg: &mut actix_web::web::ServiceConfig) {
         article::get_article_amount,
         article::get_article,
     ));
+    cfg.service(captcha::get_captcha);
     cfg.service((email::post_send_verify_email, email::post_send_email));
+    cfg.service(jwks::get_jwks);
     cfg.service((notice::get_notices, notice::get_notice, notice::post_notice));
     cfg.service((
         resource::get_brief_resources,
         resource::get_resource,
         resource::get_resource_url,
     ));
+    cfg.service((
+        oidc::get_oidc_providers,
+        oidc::get_oidc_authorize,
+        oidc::post_oidc_login,
+        oidc::get_my_identities,
+        oidc::post_my_identity,
+        oidc::delete_my_identity,
+    ));
+    cfg.service((
+        role::get_roles,
+        role::post_role,
+        role::put_role_permissions,
+        role::delete_role,
+        role::post_user_role,
+        role::patch_user_role,
+        role::delete_user_role,
+        role::get_user_role_grants,
+    ));
+    cfg.service((session::get_my_sessions, session::delete_my_session));
     cfg.service(tag::get_tags);
     cfg.service((
-        user::get_user,
+        totp::post_my_totp,
+        totp::post_my_totp_confirm,
+        totp::delete_my_totp,
+        totp::post_my_recovery_codes,
+    ));
+    cfg.service((
         user::get_myself,
+        user::patch_myself,
+        user::post_my_avatar,
+        user::post_my_password,
+        user::post_my_email,
+        user::post_my_email_confirm,
+        user::delete_myself,
+        user::get_my_export,
+    ));
+    cfg.service((
+        user::get_user,
         user::post_user_login,
+        user::post_user_login_totp,
+        user::post_user_login_magic,
         user::post_user_register,
         user::post_user_new_token,
+        user::post_user_logout,
+        user::post_user_logout_all,
         user::post_user_reset_password,
         user::post_user,
         user::post_user_daily_bonus,
     ));
+    cfg.service((vip::get_vip_plans, vip::post_vip_purchase));
 }
@@ -1,6 +1,6 @@
-use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use actix_web::{get, post, web, HttpResponse};
 use deadpool_postgres::{Client, Pool};
-use futures::future::{try_join, try_join3};
+use futures::future::try_join;
 use serde_json::json;
 
 use crate::db::Type as DBType;
@@ -8,17 +8,19 @@ use crate::error::ResponseError;
 use crate::model::notice::{
     GetNoticeOutput, GetNoticePath, GetNoticesOutput, GetNoticesOutputItem, PostNoticeInput,
 };
-use crate::model::role::Permission;
-use crate::util::req_parse::get_user_id;
+use crate::util::{
+    permission::{require, RequirePermission},
+    req_parse::AuthUser,
+};
 
 // 获取全部通知
 #[get("/notices")]
 pub async fn get_notices(
-    req: HttpRequest,
+    auth_user: AuthUser,
     db_pool: web::Data<Pool>,
 ) -> Result<HttpResponse, ResponseError> {
     let client: Client = db_pool.get().await?;
-    let user_id = get_user_id(&req)?;
+    let user_id = auth_user.user_id;
 
     let s1 = client
         .prepare_typed_cached(
@@ -48,12 +50,12 @@ pub async fn get_notices(
 // 获取单个详细通知
 #[get("/notice/{notice_id}")]
 pub async fn get_notice(
-    req: HttpRequest,
+    auth_user: AuthUser,
     db_pool: web::Data<Pool>,
     path: web::Path<GetNoticePath>,
 ) -> Result<HttpResponse, ResponseError> {
     let client: Client = db_pool.get().await?;
-    let user_id = get_user_id(&req)?;
+    let user_id = auth_user.user_id;
     let notice_id = path.notice_id;
 
     let (s1, s2) = try_join(
@@ -96,29 +98,13 @@ pub async fn get_notice(
 // 创建通知
 #[post("/notice")]
 pub async fn post_notice(
-    req: HttpRequest,
+    _: RequirePermission<require::CreateNotice>,
     db_pool: web::Data<Pool>,
     input: web::Json<PostNoticeInput>,
 ) -> Result<HttpResponse, ResponseError> {
     let client: Client = db_pool.get().await?;
-    let user_id = get_user_id(&req)?;
 
-    let (s1, s2, s3) = try_join3(
-        // 检查是否有创建通知的权限
-        client.prepare_typed_cached(
-            &format!(
-                "SELECT bool_or({}) 
-                FROM igame.role 
-                WHERE id IN (
-                    SELECT role_id 
-                    FROM igame.user_role 
-                    WHERE user_id = $1
-                    AND (expire_at IS NULL OR (expire_at IS NOT NULL AND expire_at > now()))
-                )",
-                Permission::CreateNotice.to_string()
-            ),
-            &[DBType::INT4],
-        ),
+    let (s2, s3) = try_join(
         // 创建notice并分发给所有人
         client.prepare_typed_cached(
             "WITH n AS (
@@ -150,15 +136,6 @@ pub async fn post_notice(
     )
     .await?;
 
-    let r1 = client.query_one(&s1, &[&user_id]).await?;
-    let has_permission: bool = r1.get(0);
-    if !has_permission {
-        return Err(ResponseError::permission_err(
-            "创建通知失败，没有对应权限",
-            &format!("[用户ID: {}]没有create_notice权限", user_id),
-        ));
-    }
-
     let notice_id: i32;
     if let Some(user_ids) = &input.user_ids {
         let r3 = client
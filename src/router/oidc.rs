@@ -0,0 +1,335 @@
+use actix_web::{body::Body, delete, get, post, web, HttpRequest, HttpResponse};
+use deadpool_postgres::{Client, Pool};
+use futures::future::{try_join, try_join3};
+
+use crate::config::{OidcProviderConfig, GLOBAL_CONFIG};
+use crate::db::Type as DBType;
+use crate::error::ResponseError;
+use crate::model::{
+    oidc::{
+        GetMyIdentitiesOutput, GetMyIdentitiesOutputItem, GetOidcAuthorizeOutput,
+        GetOidcProvidersOutput, GetOidcProvidersOutputItem, OidcCallbackInput, OidcProviderPath,
+        PostMyIdentityOutput,
+    },
+    role::RoleKey,
+};
+use crate::oidc::{generate_pkce, OidcIdentity, OidcShare};
+use crate::util::{email, hash, jwt, profile, req_parse::AuthUser, role, session, totp};
+
+// 获取可用的第三方登陆方式
+#[get("/oidc/providers")]
+pub async fn get_oidc_providers() -> Result<HttpResponse, ResponseError> {
+    let output: GetOidcProvidersOutput = GLOBAL_CONFIG
+        .oidc
+        .providers
+        .iter()
+        .map(|v| GetOidcProvidersOutputItem {
+            provider_id: v.id.clone(),
+            name: v.name.clone(),
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(output))
+}
+
+// 发起第三方登陆或绑定，返回需要在浏览器中打开的授权地址
+#[get("/oidc/{provider_id}/authorize")]
+pub async fn get_oidc_authorize(
+    oidc: web::Data<OidcShare>,
+    path: web::Path<OidcProviderPath>,
+) -> Result<HttpResponse, ResponseError> {
+    let provider = OidcShare::get_provider(&path.provider_id)?;
+    let (code_verifier, code_challenge) = generate_pkce();
+    let claims = jwt::OidcStateClaims::new(&provider.id, &code_verifier);
+    let authorization_url = oidc
+        .authorization_url(provider, &claims.jti, &claims.nonce, &code_challenge)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(GetOidcAuthorizeOutput {
+        authorization_url,
+        state_token: jwt::generate_oidc_state_token(&claims)?,
+        state: claims.jti,
+    }))
+}
+
+// 使用提供者回调的授权码登陆，未绑定的第三方账号会按配置自动注册
+#[post("/oidc/{provider_id}/login")]
+pub async fn post_oidc_login(
+    req: HttpRequest,
+    db_pool: web::Data<Pool>,
+    oidc: web::Data<OidcShare>,
+    path: web::Path<OidcProviderPath>,
+    input: web::Json<OidcCallbackInput>,
+) -> Result<HttpResponse, ResponseError> {
+    let (provider, identity) = authenticate_callback(&oidc, &path.provider_id, &input).await?;
+    let mut client: Client = db_pool.get().await?;
+
+    let (s1, s2, s3) = try_join3(
+        // 获取已绑定的用户，已注销的用户不能再登陆
+        client.prepare_typed_cached(
+            "SELECT i.user_id
+            FROM igame.user_identity AS i
+            INNER JOIN igame.user AS u
+            ON i.user_id = u.id
+            WHERE i.provider = $1 AND i.subject = $2 AND u.deleted_at IS NULL",
+            &[DBType::TEXT, DBType::TEXT],
+        ),
+        // 判断用户的邮箱是否存在
+        client.prepare_typed_cached(
+            "SELECT EXISTS(SELECT 1 FROM igame.user WHERE lower(email) = $1)",
+            &[DBType::TEXT],
+        ),
+        // 添加记录到igame.user,igame.user_notice, igame.user_identity表中，角色通过role::grant_initial_role授予
+        client.prepare_typed_cached(
+            "WITH
+            u AS (
+                INSERT INTO igame.user(email, nick_name, password)
+                VALUES($1, $2, $3) RETURNING id
+            ),
+            n AS (
+                INSERT INTO igame.user_notice(user_id, notice_id)
+                SELECT (SELECT id FROM u), id FROM igame.notice
+                WHERE send_new_user = true
+            ),
+            i AS (
+                INSERT INTO igame.user_identity(user_id, provider, subject, email)
+                SELECT id, $4, $5, $1 FROM u
+            )
+            SELECT id AS user_id FROM u",
+            &[
+                DBType::TEXT,
+                DBType::TEXT,
+                DBType::BYTEA,
+                DBType::TEXT,
+                DBType::TEXT,
+            ],
+        ),
+    )
+    .await?;
+
+    if let Some(r1) = client
+        .query_opt(&s1, &[&provider.id, &identity.subject])
+        .await?
+    {
+        let user_id: i32 = r1.get("user_id");
+        // 开启了两步验证，先签发challenge_token，验证通过后再创建会话
+        if totp::is_enabled(&client, user_id).await? {
+            return Ok(HttpResponse::Ok().json(session::totp_challenge(user_id)?));
+        }
+        return Ok(HttpResponse::Ok().json(session::finish_login(&client, &req, user_id).await?));
+    }
+
+    if !provider.auto_register {
+        return Err(ResponseError::input_err(
+            "该第三方账号尚未绑定，请使用邮箱登陆后在账号设置中绑定",
+            &format!(
+                "oidc提供者[{}]的账号[{}]未绑定且不允许自动注册",
+                provider.id, identity.subject
+            ),
+        ));
+    }
+    let email = identity
+        .email
+        .as_deref()
+        .map(email::normalize_addr)
+        .ok_or_else(|| {
+            ResponseError::input_err(
+                "无法获取该第三方账号已验证的邮箱，请使用邮箱注册",
+                &format!(
+                    "oidc提供者[{}]的账号[{}]没有已验证的邮箱",
+                    provider.id, identity.subject
+                ),
+            )
+        })?;
+    // 邮箱已注册时不自动绑定，防止通过第三方账号接管已有账号
+    let r2 = client.query_one(&s2, &[&email]).await?;
+    let exist: bool = r2.get(0);
+    if exist {
+        return Err(ResponseError::input_err(
+            "该邮箱已注册，请使用邮箱登陆后在账号设置中绑定该第三方账号",
+            &format!(
+                "oidc提供者[{}]的账号[{}]的邮箱[{}]已存在",
+                provider.id, identity.subject, email
+            ),
+        ));
+    }
+
+    // 自动注册的用户没有可用的密码，需要时可以通过邮箱重置密码
+    let hased_password = hash::hash_password(&jwt::generate_token_id()).await?;
+    let transaction = client.transaction().await?;
+    let r3 = transaction
+        .query_one(
+            &s3,
+            &[
+                &email,
+                &nick_name_from_identity(&identity),
+                &hased_password,
+                &provider.id,
+                &identity.subject,
+            ],
+        )
+        .await?;
+    let user_id: i32 = r3.get("user_id");
+    role::grant_initial_role(&transaction, user_id, RoleKey::User.as_str(), None).await?;
+    transaction.commit().await?;
+    tracing::info!(
+        "[用户ID: {}]通过oidc提供者[{}]自动注册",
+        user_id,
+        provider.id
+    );
+
+    Ok(HttpResponse::Ok().json(session::finish_login(&client, &req, user_id).await?))
+}
+
+// 获取已绑定的第三方账号
+#[get("/myself/identities")]
+pub async fn get_my_identities(
+    auth_user: AuthUser,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ResponseError> {
+    let client: Client = db_pool.get().await?;
+    let user_id = auth_user.user_id;
+
+    let s1 = client
+        .prepare_typed_cached(
+            "SELECT provider, email, created_at
+            FROM igame.user_identity
+            WHERE user_id = $1
+            ORDER BY created_at",
+            &[DBType::INT4],
+        )
+        .await?;
+    let r1s = client.query(&s1, &[&user_id]).await?;
+
+    let output: GetMyIdentitiesOutput = r1s
+        .iter()
+        .map(|r1| GetMyIdentitiesOutputItem {
+            provider_id: r1.get("provider"),
+            email: r1.get("email"),
+            created_at: r1.get("created_at"),
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(output))
+}
+
+// 使用提供者回调的授权码将第三方账号绑定到当前用户
+#[post("/myself/identity/{provider_id}")]
+pub async fn post_my_identity(
+    auth_user: AuthUser,
+    db_pool: web::Data<Pool>,
+    oidc: web::Data<OidcShare>,
+    path: web::Path<OidcProviderPath>,
+    input: web::Json<OidcCallbackInput>,
+) -> Result<HttpResponse, ResponseError> {
+    let user_id = auth_user.access_token_claims()?.user_id;
+    let (provider, identity) = authenticate_callback(&oidc, &path.provider_id, &input).await?;
+    let client: Client = db_pool.get().await?;
+
+    let (s1, s2) = try_join(
+        client.prepare_typed_cached(
+            "SELECT user_id FROM igame.user_identity
+            WHERE provider = $1 AND (subject = $2 OR user_id = $3)",
+            &[DBType::TEXT, DBType::TEXT, DBType::INT4],
+        ),
+        client.prepare_typed_cached(
+            "INSERT INTO igame.user_identity(user_id, provider, subject, email)
+            VALUES($1, $2, $3, $4)",
+            &[DBType::INT4, DBType::TEXT, DBType::TEXT, DBType::TEXT],
+        ),
+    )
+    .await?;
+
+    let r1s = client
+        .query(&s1, &[&provider.id, &identity.subject, &user_id])
+        .await?;
+    if r1s.iter().any(|r1| r1.get::<_, i32>("user_id") == user_id) {
+        return Err(ResponseError::already_done_err(
+            "已经绑定过该登陆方式，请先解除绑定",
+            &format!("[用户ID: {}]已绑定oidc提供者[{}]", user_id, provider.id),
+        ));
+    }
+    if !r1s.is_empty() {
+        return Err(ResponseError::input_err(
+            "该第三方账号已绑定到其他用户",
+            &format!(
+                "oidc提供者[{}]的账号[{}]已绑定到其他用户",
+                provider.id, identity.subject
+            ),
+        ));
+    }
+
+    client
+        .execute(
+            &s2,
+            &[&user_id, &provider.id, &identity.subject, &identity.email],
+        )
+        .await?;
+    tracing::info!("[用户ID: {}]绑定了oidc提供者[{}]", user_id, provider.id);
+
+    Ok(HttpResponse::Ok().json(PostMyIdentityOutput {
+        provider_id: provider.id.clone(),
+        email: identity.email,
+    }))
+}
+
+// 解除绑定第三方账号
+#[delete("/myself/identity/{provider_id}")]
+pub async fn delete_my_identity(
+    auth_user: AuthUser,
+    db_pool: web::Data<Pool>,
+    path: web::Path<OidcProviderPath>,
+) -> Result<HttpResponse, ResponseError> {
+    let client: Client = db_pool.get().await?;
+    let user_id = auth_user.access_token_claims()?.user_id;
+
+    let s1 = client
+        .prepare_typed_cached(
+            "DELETE FROM igame.user_identity WHERE user_id = $1 AND provider = $2",
+            &[DBType::INT4, DBType::TEXT],
+        )
+        .await?;
+    if client.execute(&s1, &[&user_id, &path.provider_id]).await? == 0 {
+        return Err(ResponseError::input_err(
+            "尚未绑定该登陆方式",
+            &format!(
+                "[用户ID: {}]未绑定oidc提供者[{}]",
+                user_id, path.provider_id
+            ),
+        ));
+    }
+    tracing::info!(
+        "[用户ID: {}]解除绑定了oidc提供者[{}]",
+        user_id,
+        path.provider_id
+    );
+
+    Ok(HttpResponse::Ok().body(Body::Empty))
+}
+
+// 校验回调参数并向提供者换取第三方身份
+async fn authenticate_callback(
+    oidc: &OidcShare,
+    provider_id: &str,
+    input: &OidcCallbackInput,
+) -> Result<(&'static OidcProviderConfig, OidcIdentity), ResponseError> {
+    let provider = OidcShare::get_provider(provider_id)?;
+    let claims = jwt::parse_oidc_state_token(&input.state_token)?;
+    if claims.provider != provider.id || claims.jti != input.state {
+        return Err(ResponseError::input_err(
+            "第三方登陆已失效，请重新登陆",
+            &format!("oidc提供者[{}]回调的state与state_token不匹配", provider.id),
+        ));
+    }
+    let identity = oidc
+        .authenticate(provider, &input.code, &claims.code_verifier, &claims.nonce)
+        .await?;
+    Ok((provider, identity))
+}
+
+// 使用第三方账号的昵称，不符合要求时生成一个随机昵称
+fn nick_name_from_identity(identity: &OidcIdentity) -> String {
+    identity
+        .name
+        .as_deref()
+        .and_then(|v| profile::check_nick_name(v).ok())
+        .unwrap_or_else(|| format!("用户{}", &jwt::generate_token_id()[..8]))
+}
@@ -1,9 +1,10 @@
 use actix_web::{get, web, HttpRequest, HttpResponse};
 use deadpool_postgres::{Client, Pool};
-use futures::future::{try_join, try_join3, try_join4, try_join_all};
+use futures::future::{try_join, try_join_all};
+use tokio_postgres::Statement;
 
 use crate::db::Type as DBType;
-use crate::error::ResponseError;
+use crate::error::{is_db_retryable_error, ResponseError};
 use crate::model::{
     resource::{
         GetBriefResourcesOutput, GetBriefResourcesOutputItem, GetBriefResourcesPath,
@@ -11,9 +12,17 @@ use crate::model::{
         GetResourceUrlPath,
     },
     role::Permission,
+    trade::TradeType,
 };
 use crate::resource_provider::ResourceProviderShare;
-use crate::util::{jwt::parse_access_token, req_parse::get_access_token};
+use crate::util::{
+    ledger::{self, LedgerReason},
+    permission,
+    req_parse::OptionalUser,
+};
+
+// 扣除无限币的事务最多执行的次数
+const MAX_SPEND_ATTEMPTS: u32 = 3;
 
 // 获取指定app的多个简短资源信息
 #[get("/app/{app_id}/brief_resources")]
@@ -95,6 +104,7 @@ pub async fn get_resource(
 #[get("/resource/{resource_id}/{client_group}/url")]
 pub async fn get_resource_url(
     req: HttpRequest,
+    optional_user: OptionalUser,
     db_pool: web::Data<Pool>,
     resource_provider: web::Data<ResourceProviderShare>,
     path: web::Path<GetResourceUrlPath>,
@@ -117,51 +127,32 @@ pub async fn get_resource_url(
             "INSERT INTO igame.trade (user_id, type, cost, resource_id) VALUES ($1, $2, $3, $4) RETURNING id",
             &[DBType::INT4, DBType::INT2, DBType::INT4, DBType::INT4],
         ),
-        // s3:减少用户的无限币数量
-        client.prepare_typed_cached(
-            "UPDATE igame.user SET coin = coin - $1 WHERE id = $2 RETURNING coin",
-            &[DBType::INT4, DBType::INT4],
-        ),
-        // s4:资源下载量+1
+        // s3:资源下载量+1
         client.prepare_typed_cached(
             "UPDATE igame.resource SET downloaded = downloaded + 1 WHERE id = $1 RETURNING downloaded",
             &[DBType::INT4],
         ),
-        // s5:文章下载量+1
+        // s4:文章下载量+1
         client.prepare_typed_cached(
             "UPDATE igame.article SET downloaded = downloaded + 1 WHERE app_id = $1",
             &[DBType::INT4],
         ),
-        // s6:检查用户是否可以免费下载，或者无视等级限制
-        client.prepare_typed_cached(
-            &format!(
-                "SELECT bool_or({}) as free_download, bool_or({}) as ignore_exp
-                FROM igame.role 
-                WHERE id IN (
-                    SELECT role_id 
-                    FROM igame.user_role 
-                    WHERE user_id = $1
-                    AND (expire_at IS NULL OR (expire_at IS NOT NULL AND expire_at > now()))
-                )",
-                Permission::FreeDownload.to_string(), Permission::IgnoreExp.to_string()
-            ),
-            &[DBType::INT4],
-        ),
     ]).await?;
 
     let download_url: String;
     let trade_id: i32;
     let remain_coin: i32;
     let downloaded: i32;
-    if let Some(access_token) = get_access_token(&req) {
+    if let Some(auth_user) = optional_user.0 {
         // 如果是登陆用户
-        let user_id = parse_access_token(&access_token)?.user_id;
-        let (r0, r1, r6) = try_join3(
+        let user_id = auth_user.user_id;
+        let (r0, r1) = try_join(
             client.query_one(&vec_s[0], &[&path.resource_id]),
             client.query_one(&vec_s[1], &[&user_id]),
-            client.query_one(&vec_s[6], &[&user_id]),
         )
         .await?;
+        // 检查用户是否可以免费下载，或者无视等级限制
+        let permissions = permission::get_permissions(&req, &client, &auth_user).await?;
         let app_id: i32 = r0.get("app_id");
         let allowed_exp: i32 = r0.get("allowed_exp");
         let mut cost: i32 = r0.get(format!("{}_download_cost", &path.provider_group).as_str());
@@ -179,8 +170,8 @@ pub async fn get_resource_url(
         let resource_path: String = r0.get("file_path");
         let user_coin: i32 = r1.get("coin");
         let user_exp: i32 = r1.get("exp");
-        let can_free_download: bool = r6.get("free_download");
-        let can_ignore_exp: bool = r6.get("ignore_exp");
+        let can_free_download = permissions.contains(Permission::FreeDownload);
+        let can_ignore_exp = permissions.contains(Permission::IgnoreExp);
         // 如果没有无视等级的权限，且用户等级小于要求等级
         if !can_ignore_exp && user_exp < allowed_exp {
             return Err(ResponseError::lack_exp_err(
@@ -201,35 +192,23 @@ pub async fn get_resource_url(
         if can_free_download {
             cost = 0;
         }
-        let transaction: deadpool_postgres::Transaction;
-        (
-            download_url,
-            (transaction, trade_id, remain_coin, downloaded),
-        ) = try_join(
-            resource_provider.get_download_url(&resource_path, &path.provider_group, provider_ids),
-            async {
-                let trade_type: i16 = 1;
-                let transaction = client.transaction().await?;
-                let (r2, r3, r4, _) = try_join4(
-                    transaction.query_one(
-                        &vec_s[2],
-                        &[&user_id, &trade_type, &cost, &path.resource_id],
-                    ),
-                    transaction.query_one(&vec_s[3], &[&cost, &user_id]),
-                    transaction.query_one(&vec_s[4], &[&path.resource_id]),
-                    transaction.execute(&vec_s[5], &[&app_id]),
-                )
-                .await?;
-                return Ok((
-                    transaction,
-                    r2.get("id"),
-                    r3.get("coin"),
-                    r4.get("downloaded"),
-                ));
-            },
-        )
-        .await?;
-        transaction.commit().await?;
+        // 先获取下载链接，获取失败时不会扣除无限币
+        download_url = resource_provider
+            .get_download_url(&resource_path, &path.provider_group, provider_ids)
+            .await?;
+        (trade_id, remain_coin, downloaded) =
+            spend_coin(&mut client, &vec_s, user_id, cost, path.resource_id, app_id)
+                .await?
+                .ok_or_else(|| {
+                    ResponseError::lack_coin_err(
+                        "用户无限币不足，无法获取资源下载链接",
+                        cost,
+                        &format!(
+                            "用户ID: {},资源ID: {},扣除时余额不足",
+                            user_id, path.resource_id
+                        ),
+                    )
+                })?;
 
         return Ok(HttpResponse::Ok().json(GetResourceUrlOutput {
             download_url,
@@ -275,8 +254,8 @@ pub async fn get_resource_url(
             resource_provider.get_download_url(&resource_path, &path.provider_group, provider_ids),
             async {
                 let (r4, _) = try_join(
-                    client.query_one(&vec_s[4], &[&path.resource_id]),
-                    client.execute(&vec_s[5], &[&app_id]),
+                    client.query_one(&vec_s[3], &[&path.resource_id]),
+                    client.execute(&vec_s[4], &[&app_id]),
                 )
                 .await?;
                 return Ok(r4.get("downloaded"));
@@ -292,3 +271,67 @@ pub async fn get_resource_url(
         }));
     }
 }
+
+// 扣除无限币并记录交易，余额不足时返回None，遇到序列化失败或死锁时重试
+async fn spend_coin(
+    client: &mut Client,
+    vec_s: &[Statement],
+    user_id: i32,
+    cost: i32,
+    resource_id: i32,
+    app_id: i32,
+) -> Result<Option<(i32, i32, i32)>, ResponseError> {
+    let mut attempt = 1;
+    loop {
+        match try_spend_coin(client, vec_s, user_id, cost, resource_id, app_id).await {
+            Err(e) if attempt < MAX_SPEND_ATTEMPTS && is_db_retryable_error(&e) => {
+                tracing::warn!(
+                    "用户ID: {},资源ID: {},第{}次扣除无限币失败，重试: {}",
+                    user_id,
+                    resource_id,
+                    attempt,
+                    e
+                );
+                attempt += 1;
+            }
+            result => return Ok(result?),
+        }
+    }
+}
+
+async fn try_spend_coin(
+    client: &mut Client,
+    vec_s: &[Statement],
+    user_id: i32,
+    cost: i32,
+    resource_id: i32,
+    app_id: i32,
+) -> Result<Option<(i32, i32, i32)>, tokio_postgres::Error> {
+    let trade_type = TradeType::ResourceDownload.as_i16();
+    let transaction = client.transaction().await?;
+    let r2 = transaction
+        .query_one(&vec_s[2], &[&user_id, &trade_type, &cost, &resource_id])
+        .await?;
+    let trade_id: i32 = r2.get("id");
+    // 扣除与检查余额在同一条语句中完成，并发请求不会使余额变为负数
+    let balance = match ledger::apply(
+        &transaction,
+        user_id,
+        -cost,
+        0,
+        LedgerReason::ResourceDownload,
+        Some(trade_id),
+    )
+    .await?
+    {
+        Some(balance) => balance,
+        None => return Ok(None),
+    };
+    let (r3, _) = try_join(
+        transaction.query_one(&vec_s[3], &[&resource_id]),
+        transaction.execute(&vec_s[4], &[&app_id]),
+    )
+    .await?;
+    transaction.commit().await?;
+    Ok(Some((trade_id, balance.coin, r3.get("downloaded"))))
+}
@@ -0,0 +1,517 @@
+use actix_web::{delete, get, patch, post, put, web, HttpRequest, HttpResponse};
+use chrono::Utc;
+use deadpool_postgres::{Client, Pool, Transaction};
+use futures::future::try_join;
+use serde_json::json;
+
+use crate::db::Type as DBType;
+use crate::error::{is_db_dup_unique_error, ResponseError};
+use crate::model::role::{
+    GetRolesOutput, GetUserRoleGrantsOutput, GetUserRoleGrantsOutputItem, GetUserRoleGrantsPath,
+    PatchUserRoleInput, Permission, PostRoleInput, PostUserRoleInput, PutRolePermissionsInput,
+    RoleKey, RoleOutput, RolePath, UserRoleOutput, UserRolePath,
+};
+use crate::util::{
+    permission::{self, require, RequirePermission},
+    req_parse::AuthUser,
+    role::{self, GrantAction},
+};
+
+// 角色key与名称的最大长度
+const MAX_KEY_LEN: usize = 32;
+const MAX_NAME_LEN: usize = 32;
+
+// 获取所有角色及其拥有的权限
+#[get("/roles")]
+pub async fn get_roles(
+    _: RequirePermission<require::ManageRole>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ResponseError> {
+    let client: Client = db_pool.get().await?;
+    let s1 = client
+        .prepare_typed_cached(
+            "SELECT r.id, r.key, r.name,
+                COALESCE(array_agg(p.key ORDER BY p.id) FILTER (WHERE p.key IS NOT NULL), '{}') AS permissions
+            FROM igame.role AS r
+            LEFT JOIN igame.role_permission AS rp
+            ON r.id = rp.role_id
+            LEFT JOIN igame.permission AS p
+            ON rp.permission_id = p.id
+            GROUP BY r.id, r.key, r.name
+            ORDER BY r.id",
+            &[],
+        )
+        .await?;
+    let r1s = client.query(&s1, &[]).await?;
+    let mut output: GetRolesOutput = Vec::new();
+    for r1 in r1s {
+        output.push(RoleOutput {
+            role_id: r1.get("id"),
+            key: r1.get("key"),
+            name: r1.get("name"),
+            permissions: r1.get("permissions"),
+        });
+    }
+
+    Ok(HttpResponse::Ok().json(output))
+}
+
+// 创建角色，只能赋予操作者自己拥有的权限
+#[post("/role")]
+pub async fn post_role(
+    req: HttpRequest,
+    guard: RequirePermission<require::ManageRole>,
+    db_pool: web::Data<Pool>,
+    input: web::Json<PostRoleInput>,
+) -> Result<HttpResponse, ResponseError> {
+    let mut client: Client = db_pool.get().await?;
+    let operator_id = guard.auth_user.user_id;
+    let key = input.key.trim();
+    let name = input.name.trim();
+    if key.is_empty()
+        || key.len() > MAX_KEY_LEN
+        || !key
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+    {
+        return Err(ResponseError::input_err(
+            &format!(
+                "角色key只能包含小写字母、数字与下划线，且不能超过{}个字符",
+                MAX_KEY_LEN
+            ),
+            &format!("[用户ID: {}]角色key[{}]不合法", operator_id, key),
+        ));
+    }
+    if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+        return Err(ResponseError::input_err(
+            &format!("角色名称不能为空且不能超过{}个字符", MAX_NAME_LEN),
+            &format!("[用户ID: {}]角色名称[{}]不合法", operator_id, name),
+        ));
+    }
+    check_grantable(&req, &client, &guard.auth_user, &input.permissions).await?;
+
+    let transaction = client.transaction().await?;
+    if input.permissions.contains(&Permission::ManageRole) {
+        role::check_admin(&transaction, operator_id, key).await?;
+    }
+    let s1 = transaction
+        .prepare_typed_cached(
+            "INSERT INTO igame.role (key, name) VALUES ($1, $2) RETURNING id",
+            &[DBType::TEXT, DBType::TEXT],
+        )
+        .await?;
+    let role_id: i32 = match transaction.query_one(&s1, &[&key, &name]).await {
+        Ok(r1) => r1.get("id"),
+        Err(e) if is_db_dup_unique_error(&e) => {
+            return Err(ResponseError::already_done_err(
+                "角色key已存在",
+                &format!("[用户ID: {}]角色key[{}]已存在", operator_id, key),
+            ));
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let permissions = set_role_permissions(&transaction, role_id, &input.permissions).await?;
+    transaction.commit().await?;
+    tracing::info!(
+        "[用户ID: {}]创建了角色[{}]，权限: {:?}",
+        operator_id,
+        key,
+        permissions
+    );
+
+    Ok(HttpResponse::Ok().json(RoleOutput {
+        role_id,
+        key: key.to_string(),
+        name: name.to_string(),
+        permissions,
+    }))
+}
+
+// 替换角色的全部权限，不能修改管理员角色，只能赋予操作者自己拥有的权限
+#[put("/role/{role_key}/permissions")]
+pub async fn put_role_permissions(
+    req: HttpRequest,
+    guard: RequirePermission<require::ManageRole>,
+    db_pool: web::Data<Pool>,
+    path: web::Path<RolePath>,
+    input: web::Json<PutRolePermissionsInput>,
+) -> Result<HttpResponse, ResponseError> {
+    let mut client: Client = db_pool.get().await?;
+    let operator_id = guard.auth_user.user_id;
+    if path.role_key == RoleKey::Admin.as_str() {
+        return Err(ResponseError::permission_err(
+            "不能修改管理员角色的权限",
+            &format!("[用户ID: {}]尝试修改管理员角色的权限", operator_id),
+        ));
+    }
+    check_grantable(&req, &client, &guard.auth_user, &input.permissions).await?;
+
+    let transaction = client.transaction().await?;
+    let (role_id, name) = lock_role(&transaction, &path.role_key).await?;
+    role::check_manageable(&transaction, operator_id, role_id, &path.role_key).await?;
+    // 赋予管理角色的权限同样只有管理员可以操作
+    if input.permissions.contains(&Permission::ManageRole) {
+        role::check_admin(&transaction, operator_id, &path.role_key).await?;
+    }
+    let permissions = set_role_permissions(&transaction, role_id, &input.permissions).await?;
+    transaction.commit().await?;
+    permission::invalidate_all();
+    tracing::info!(
+        "[用户ID: {}]将角色[{}]的权限修改为: {:?}",
+        operator_id,
+        path.role_key,
+        permissions
+    );
+
+    Ok(HttpResponse::Ok().json(RoleOutput {
+        role_id,
+        key: path.role_key.clone(),
+        name,
+        permissions,
+    }))
+}
+
+// 删除角色，内置角色与已经授予过用户的角色不能删除
+#[delete("/role/{role_key}")]
+pub async fn delete_role(
+    guard: RequirePermission<require::ManageRole>,
+    db_pool: web::Data<Pool>,
+    path: web::Path<RolePath>,
+) -> Result<HttpResponse, ResponseError> {
+    let mut client: Client = db_pool.get().await?;
+    let operator_id = guard.auth_user.user_id;
+    if RoleKey::is_builtin(&path.role_key) {
+        return Err(ResponseError::permission_err(
+            "不能删除内置角色",
+            &format!(
+                "[用户ID: {}]尝试删除内置角色[{}]",
+                operator_id, path.role_key
+            ),
+        ));
+    }
+
+    let transaction = client.transaction().await?;
+    let (role_id, _) = lock_role(&transaction, &path.role_key).await?;
+    let s1 = transaction
+        .prepare_typed_cached(
+            "SELECT EXISTS(SELECT 1 FROM igame.user_role WHERE role_id = $1)
+            OR EXISTS(SELECT 1 FROM igame.role_grant_log WHERE role_id = $1)",
+            &[DBType::INT4],
+        )
+        .await?;
+    let granted: bool = transaction.query_one(&s1, &[&role_id]).await?.get(0);
+    if granted {
+        return Err(ResponseError::input_err(
+            "该角色已经授予过用户，不能删除",
+            &format!(
+                "[用户ID: {}]尝试删除已授予过的角色[{}]",
+                operator_id, path.role_key
+            ),
+        ));
+    }
+    let s2 = transaction
+        .prepare_typed_cached("DELETE FROM igame.role WHERE id = $1", &[DBType::INT4])
+        .await?;
+    transaction.execute(&s2, &[&role_id]).await?;
+    transaction.commit().await?;
+    tracing::info!("[用户ID: {}]删除了角色[{}]", operator_id, path.role_key);
+
+    Ok(HttpResponse::Ok().json(json!({ "result": "ok" })))
+}
+
+// 授予用户角色，expire_at为空时永久有效
+#[post("/user/{user_id}/role/{role_key}")]
+pub async fn post_user_role(
+    guard: RequirePermission<require::ManageRole>,
+    db_pool: web::Data<Pool>,
+    path: web::Path<UserRolePath>,
+    input: web::Json<PostUserRoleInput>,
+) -> Result<HttpResponse, ResponseError> {
+    let mut client: Client = db_pool.get().await?;
+    let operator_id = guard.auth_user.user_id;
+    check_target(operator_id, &path)?;
+    if let Some(expire_at) = input.expire_at {
+        if expire_at <= Utc::now() {
+            return Err(ResponseError::input_err(
+                "过期时间必须晚于当前时间",
+                &format!(
+                    "[用户ID: {}]角色过期时间[{}]已过去",
+                    path.user_id, expire_at
+                ),
+            ));
+        }
+    }
+
+    let transaction = client.transaction().await?;
+    lock_user(&transaction, path.user_id).await?;
+    let role_id = role::get_role_id(&transaction, &path.role_key).await?;
+    role::check_manageable(&transaction, operator_id, role_id, &path.role_key).await?;
+    let previous = role::lock_grant(&transaction, path.user_id, role_id).await?;
+    if previous.is_some_and(|v| v.is_active()) {
+        return Err(ResponseError::already_done_err(
+            "该用户已拥有该角色，如需修改有效期请使用延长",
+            &format!("[用户ID: {}]已拥有角色[{}]", path.user_id, path.role_key),
+        ));
+    }
+    role::set_grant(
+        &transaction,
+        path.user_id,
+        role_id,
+        previous,
+        input.expire_at,
+        GrantAction::Grant,
+        Some(operator_id),
+    )
+    .await?;
+    transaction.commit().await?;
+    permission::invalidate(path.user_id);
+
+    Ok(HttpResponse::Ok().json(UserRoleOutput {
+        user_id: path.user_id,
+        role_key: path.role_key.clone(),
+        expire_at: input.expire_at,
+    }))
+}
+
+// 延长用户未过期的角色，expire_at为空时改为永久有效
+#[patch("/user/{user_id}/role/{role_key}")]
+pub async fn patch_user_role(
+    guard: RequirePermission<require::ManageRole>,
+    db_pool: web::Data<Pool>,
+    path: web::Path<UserRolePath>,
+    input: web::Json<PatchUserRoleInput>,
+) -> Result<HttpResponse, ResponseError> {
+    let mut client: Client = db_pool.get().await?;
+    let operator_id = guard.auth_user.user_id;
+    check_target(operator_id, &path)?;
+
+    let transaction = client.transaction().await?;
+    lock_user(&transaction, path.user_id).await?;
+    let role_id = role::get_role_id(&transaction, &path.role_key).await?;
+    role::check_manageable(&transaction, operator_id, role_id, &path.role_key).await?;
+    let previous = match role::lock_grant(&transaction, path.user_id, role_id).await? {
+        Some(previous) if previous.is_active() => previous,
+        _ => {
+            return Err(ResponseError::input_err(
+                "该用户没有该角色或角色已过期，请重新授予",
+                &format!(
+                    "[用户ID: {}]没有有效的角色[{}]",
+                    path.user_id, path.role_key
+                ),
+            ));
+        }
+    };
+    match (previous.expire_at, input.expire_at) {
+        (None, _) => {
+            return Err(ResponseError::already_done_err(
+                "该角色已经永久有效",
+                &format!(
+                    "[用户ID: {}]的角色[{}]已经永久有效",
+                    path.user_id, path.role_key
+                ),
+            ));
+        }
+        (Some(current), Some(expire_at)) if expire_at <= current => {
+            return Err(ResponseError::input_err(
+                "新的过期时间必须晚于当前的过期时间",
+                &format!(
+                    "[用户ID: {}]角色[{}]新的过期时间[{}]早于[{}]",
+                    path.user_id, path.role_key, expire_at, current
+                ),
+            ));
+        }
+        _ => {}
+    }
+    role::set_grant(
+        &transaction,
+        path.user_id,
+        role_id,
+        Some(previous),
+        input.expire_at,
+        GrantAction::Extend,
+        Some(operator_id),
+    )
+    .await?;
+    transaction.commit().await?;
+    permission::invalidate(path.user_id);
+
+    Ok(HttpResponse::Ok().json(UserRoleOutput {
+        user_id: path.user_id,
+        role_key: path.role_key.clone(),
+        expire_at: input.expire_at,
+    }))
+}
+
+// 撤销用户的角色，已过期的记录也会被删除
+#[delete("/user/{user_id}/role/{role_key}")]
+pub async fn delete_user_role(
+    guard: RequirePermission<require::ManageRole>,
+    db_pool: web::Data<Pool>,
+    path: web::Path<UserRolePath>,
+) -> Result<HttpResponse, ResponseError> {
+    let mut client: Client = db_pool.get().await?;
+    let operator_id = guard.auth_user.user_id;
+    check_target(operator_id, &path)?;
+
+    let transaction = client.transaction().await?;
+    lock_user(&transaction, path.user_id).await?;
+    let role_id = role::get_role_id(&transaction, &path.role_key).await?;
+    role::check_manageable(&transaction, operator_id, role_id, &path.role_key).await?;
+    let previous = match role::lock_grant(&transaction, path.user_id, role_id).await? {
+        Some(previous) => previous,
+        None => {
+            return Err(ResponseError::already_done_err(
+                "该用户没有该角色",
+                &format!("[用户ID: {}]没有角色[{}]", path.user_id, path.role_key),
+            ));
+        }
+    };
+    role::delete_grant(
+        &transaction,
+        path.user_id,
+        role_id,
+        previous,
+        Some(operator_id),
+    )
+    .await?;
+    transaction.commit().await?;
+    permission::invalidate(path.user_id);
+
+    Ok(HttpResponse::Ok().json(json!({ "result": "ok" })))
+}
+
+// 获取用户的角色变更记录，按时间倒序
+#[get("/user/{user_id}/role_grants")]
+pub async fn get_user_role_grants(
+    _: RequirePermission<require::ManageRole>,
+    db_pool: web::Data<Pool>,
+    path: web::Path<GetUserRoleGrantsPath>,
+) -> Result<HttpResponse, ResponseError> {
+    let client: Client = db_pool.get().await?;
+    let s1 = client
+        .prepare_typed_cached(
+            "SELECT l.id, r.key, l.action, l.previous_expire_at, l.expire_at, l.operator_id, l.created_at
+            FROM igame.role_grant_log AS l
+            INNER JOIN igame.role AS r
+            ON l.role_id = r.id
+            WHERE l.user_id = $1
+            ORDER BY l.id DESC",
+            &[DBType::INT4],
+        )
+        .await?;
+    let r1s = client.query(&s1, &[&path.user_id]).await?;
+    let mut output: GetUserRoleGrantsOutput = Vec::new();
+    for r1 in r1s {
+        output.push(GetUserRoleGrantsOutputItem {
+            grant_id: r1.get("id"),
+            role_key: r1.get("key"),
+            action: r1.get("action"),
+            previous_expire_at: r1.get("previous_expire_at"),
+            expire_at: r1.get("expire_at"),
+            operator_id: r1.get("operator_id"),
+            created_at: r1.get("created_at"),
+        });
+    }
+
+    Ok(HttpResponse::Ok().json(output))
+}
+
+// 不能修改自己的角色，防止管理员误操作撤销自己的权限
+fn check_target(operator_id: i32, path: &UserRolePath) -> Result<(), ResponseError> {
+    if operator_id == path.user_id {
+        return Err(ResponseError::permission_err(
+            "不能修改自己的角色",
+            &format!(
+                "[用户ID: {}]尝试修改自己的角色[{}]",
+                operator_id, path.role_key
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// 锁定目标用户，已注销的用户不能再修改角色
+async fn lock_user(transaction: &Transaction<'_>, user_id: i32) -> Result<(), ResponseError> {
+    let s1 = transaction
+        .prepare_typed_cached(
+            "SELECT id FROM igame.user WHERE id = $1 AND deleted_at IS NULL FOR UPDATE",
+            &[DBType::INT4],
+        )
+        .await?;
+    if transaction.query_opt(&s1, &[&user_id]).await?.is_none() {
+        return Err(ResponseError::input_err(
+            "用户不存在",
+            &format!("[用户ID: {}]不存在或已注销", user_id),
+        ));
+    }
+    Ok(())
+}
+
+// 只能赋予操作者自己当前拥有的权限，防止通过角色提升自己的权限
+async fn check_grantable(
+    req: &HttpRequest,
+    client: &Client,
+    auth_user: &AuthUser,
+    permissions: &[Permission],
+) -> Result<(), ResponseError> {
+    let own_permissions = permission::get_permissions(req, client, auth_user).await?;
+    if let Some(v) = permissions.iter().find(|v| !own_permissions.contains(**v)) {
+        return Err(ResponseError::permission_err(
+            "不能赋予自己没有的权限",
+            &format!("[用户ID: {}]尝试赋予自己没有的权限{}", auth_user.user_id, v),
+        ));
+    }
+    Ok(())
+}
+
+// 锁定角色，返回角色ID与名称
+async fn lock_role(
+    transaction: &Transaction<'_>,
+    role_key: &str,
+) -> Result<(i32, String), ResponseError> {
+    let s1 = transaction
+        .prepare_typed_cached(
+            "SELECT id, name FROM igame.role WHERE key = $1 FOR UPDATE",
+            &[DBType::TEXT],
+        )
+        .await?;
+    match transaction.query_opt(&s1, &[&role_key]).await? {
+        Some(r1) => Ok((r1.get("id"), r1.get("name"))),
+        None => Err(ResponseError::input_err(
+            "角色不存在",
+            &format!("角色[{}]不存在", role_key),
+        )),
+    }
+}
+
+// 替换角色的全部权限，返回替换后的权限key
+async fn set_role_permissions(
+    transaction: &Transaction<'_>,
+    role_id: i32,
+    permissions: &[Permission],
+) -> Result<Vec<String>, ResponseError> {
+    let (s1, s2) = try_join(
+        transaction.prepare_typed_cached(
+            "DELETE FROM igame.role_permission WHERE role_id = $1",
+            &[DBType::INT4],
+        ),
+        transaction.prepare_typed_cached(
+            "WITH i AS (
+                INSERT INTO igame.role_permission (role_id, permission_id)
+                SELECT $1, id FROM igame.permission WHERE key = ANY($2)
+                RETURNING permission_id
+            )
+            SELECT p.key
+            FROM i
+            INNER JOIN igame.permission AS p
+            ON i.permission_id = p.id
+            ORDER BY p.id",
+            &[DBType::INT4, DBType::TEXT_ARRAY],
+        ),
+    )
+    .await?;
+    let keys: Vec<String> = permissions.iter().map(|v| v.key()).collect();
+    transaction.execute(&s1, &[&role_id]).await?;
+    let r2s = transaction.query(&s2, &[&role_id, &keys]).await?;
+    Ok(r2s.iter().map(|r2| r2.get("key")).collect())
+}
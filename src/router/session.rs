@@ -0,0 +1,69 @@
+use actix_web::{body::Body, delete, get, web, HttpResponse};
+use deadpool_postgres::{Client, Pool};
+
+use crate::db::Type as DBType;
+use crate::error::ResponseError;
+use crate::model::session::{DeleteMySessionPath, GetMySessionsOutput, GetMySessionsOutputItem};
+use crate::util::{req_parse::AuthUser, session};
+
+// 获取当前用户所有有效的会话
+#[get("/myself/sessions")]
+pub async fn get_my_sessions(
+    auth_user: AuthUser,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ResponseError> {
+    let client: Client = db_pool.get().await?;
+    let claims = auth_user.access_token_claims()?;
+
+    // 每个有效会话只有最新签发的那个凭证未被使用
+    let s1 = client
+        .prepare_typed_cached(
+            "SELECT family_id, device, ip, login_at, created_at
+            FROM igame.session
+            WHERE user_id = $1
+            AND used_at IS NULL AND revoked_at IS NULL AND expire_at > now()
+            ORDER BY created_at DESC",
+            &[DBType::INT4],
+        )
+        .await?;
+    let r1s = client.query(&s1, &[&claims.user_id]).await?;
+
+    let mut output: GetMySessionsOutput = Vec::new();
+    for r1 in r1s {
+        let session_id: String = r1.get("family_id");
+        output.push(GetMySessionsOutputItem {
+            current: session_id == claims.sid,
+            session_id,
+            device: r1.get("device"),
+            ip: r1.get("ip"),
+            login_at: r1.get("login_at"),
+            last_used_at: r1.get("created_at"),
+        })
+    }
+
+    Ok(HttpResponse::Ok().json(output))
+}
+
+// 吊销当前用户的某个会话
+#[delete("/myself/session/{session_id}")]
+pub async fn delete_my_session(
+    auth_user: AuthUser,
+    db_pool: web::Data<Pool>,
+    path: web::Path<DeleteMySessionPath>,
+) -> Result<HttpResponse, ResponseError> {
+    let client: Client = db_pool.get().await?;
+    let claims = auth_user.access_token_claims()?;
+
+    let exist = session::revoke_session(&client, claims.user_id, &path.session_id).await?;
+    if !exist {
+        return Err(ResponseError::input_err(
+            "该会话不存在或已失效",
+            &format!(
+                "[用户ID: {}]会话[{}]不存在",
+                claims.user_id, path.session_id
+            ),
+        ));
+    }
+
+    Ok(HttpResponse::Ok().body(Body::Empty))
+}
@@ -0,0 +1,224 @@
+use actix_web::{body::Body, delete, post, web, HttpRequest, HttpResponse};
+use deadpool_postgres::{Client, Pool};
+use futures::future::try_join;
+
+use crate::attempt_limiter::AttemptLimiterShare;
+use crate::config::GLOBAL_CONFIG;
+use crate::db::Type as DBType;
+use crate::error::ResponseError;
+use crate::model::{
+    role::RoleKey,
+    totp::{
+        DeleteMyTotpInput, PostMyRecoveryCodesInput, PostMyRecoveryCodesOutput,
+        PostMyTotpConfirmInput, PostMyTotpConfirmOutput, PostMyTotpOutput,
+    },
+};
+use crate::util::{
+    permission,
+    req_parse::{get_client_ip, AuthUser},
+    totp,
+};
+
+// 生成新的两步验证密钥，需要再调用/myself/totp/confirm确认后才会生效
+#[post("/myself/totp")]
+pub async fn post_my_totp(
+    auth_user: AuthUser,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ResponseError> {
+    let client: Client = db_pool.get().await?;
+    let user_id = auth_user.access_token_claims()?.user_id;
+
+    let (s1, s2) = try_join(
+        // 获取用户的邮箱，作为验证器应用中显示的账号名
+        client.prepare_typed_cached(
+            "SELECT email FROM igame.user WHERE id = $1",
+            &[DBType::INT4],
+        ),
+        // 保存新密钥，已经启用的两步验证不会被覆盖
+        client.prepare_typed_cached(
+            "INSERT INTO igame.user_totp (user_id, secret)
+            VALUES ($1, $2)
+            ON CONFLICT (user_id) DO UPDATE
+            SET secret = $2, last_used_step = NULL, created_at = now()
+            WHERE igame.user_totp.enabled = false",
+            &[DBType::INT4, DBType::TEXT],
+        ),
+    )
+    .await?;
+
+    let secret = totp::generate_secret();
+    let (r1, count) = try_join(
+        client.query_one(&s1, &[&user_id]),
+        client.execute(&s2, &[&user_id, &secret]),
+    )
+    .await?;
+    if count == 0 {
+        return Err(ResponseError::already_done_err(
+            "已经启用了两步验证",
+            &format!("[用户ID: {}]重复启用两步验证", user_id),
+        ));
+    }
+    let email: String = r1.get("email");
+
+    Ok(HttpResponse::Ok().json(PostMyTotpOutput {
+        otpauth_uri: totp::otpauth_uri(&email, &secret),
+        secret,
+    }))
+}
+
+// 使用验证器应用生成的验证码确认绑定，成功后返回恢复码
+#[post("/myself/totp/confirm")]
+pub async fn post_my_totp_confirm(
+    req: HttpRequest,
+    auth_user: AuthUser,
+    db_pool: web::Data<Pool>,
+    attempt_limiter: web::Data<AttemptLimiterShare>,
+    input: web::Json<PostMyTotpConfirmInput>,
+) -> Result<HttpResponse, ResponseError> {
+    let client: Client = db_pool.get().await?;
+    let user_id = auth_user.access_token_claims()?.user_id;
+    let ip = get_client_ip(&req);
+    attempt_limiter.check(None, &ip)?;
+
+    let (s1, s2) = try_join(
+        client.prepare_typed_cached(
+            "SELECT secret, enabled FROM igame.user_totp WHERE user_id = $1",
+            &[DBType::INT4],
+        ),
+        client.prepare_typed_cached(
+            "UPDATE igame.user_totp
+            SET enabled = true, enabled_at = now(), last_used_step = $2
+            WHERE user_id = $1 AND enabled = false",
+            &[DBType::INT4, DBType::INT8],
+        ),
+    )
+    .await?;
+
+    let r1 = match client.query_opt(&s1, &[&user_id]).await? {
+        Some(v) => v,
+        None => {
+            return Err(ResponseError::input_err(
+                "请先生成两步验证密钥",
+                &format!("[用户ID: {}]没有待确认的两步验证密钥", user_id),
+            ))
+        }
+    };
+    let secret: String = r1.get("secret");
+    let enabled: bool = r1.get("enabled");
+    if enabled {
+        return Err(ResponseError::already_done_err(
+            "已经启用了两步验证",
+            &format!("[用户ID: {}]重复确认两步验证", user_id),
+        ));
+    }
+    let step = match totp::verify_code(&secret, input.code.trim()) {
+        Some(v) => v,
+        None => {
+            attempt_limiter.record_failure(None, &ip);
+            return Err(ResponseError::input_err(
+                "验证码错误，请检查验证器应用中的时间是否准确",
+                &format!("[用户ID: {}]确认两步验证时验证码错误", user_id),
+            ));
+        }
+    };
+    if client.execute(&s2, &[&user_id, &step]).await? == 0 {
+        return Err(ResponseError::already_done_err(
+            "已经启用了两步验证",
+            &format!("[用户ID: {}]重复确认两步验证", user_id),
+        ));
+    }
+    let recovery_codes = totp::replace_recovery_codes(&client, user_id).await?;
+    permission::invalidate(user_id);
+    tracing::info!("[用户ID: {}]启用了两步验证", user_id);
+
+    Ok(HttpResponse::Ok().json(PostMyTotpConfirmOutput { recovery_codes }))
+}
+
+// 关闭两步验证
+#[delete("/myself/totp")]
+pub async fn delete_my_totp(
+    req: HttpRequest,
+    auth_user: AuthUser,
+    db_pool: web::Data<Pool>,
+    attempt_limiter: web::Data<AttemptLimiterShare>,
+    input: web::Json<DeleteMyTotpInput>,
+) -> Result<HttpResponse, ResponseError> {
+    let client: Client = db_pool.get().await?;
+    let user_id = auth_user.access_token_claims()?.user_id;
+    let ip = get_client_ip(&req);
+    attempt_limiter.check(None, &ip)?;
+
+    let (s1, s2) = try_join(
+        // 判断用户是否为管理员
+        client.prepare_typed_cached(
+            "SELECT EXISTS(
+                SELECT 1 FROM igame.user_role
+                WHERE user_id = $1
+                AND role_id = (SELECT id FROM igame.role WHERE key = $2)
+                AND (expire_at IS NULL OR (expire_at IS NOT NULL AND expire_at > now()))
+            )",
+            &[DBType::INT4, DBType::TEXT],
+        ),
+        // 删除密钥与恢复码
+        client.prepare_typed_cached(
+            "WITH d AS (
+                DELETE FROM igame.user_recovery_code WHERE user_id = $1
+            )
+            DELETE FROM igame.user_totp WHERE user_id = $1",
+            &[DBType::INT4],
+        ),
+    )
+    .await?;
+
+    if GLOBAL_CONFIG.totp.require_for_admin {
+        let r1 = client
+            .query_one(&s1, &[&user_id, &RoleKey::Admin.as_str()])
+            .await?;
+        let is_admin: bool = r1.get(0);
+        if is_admin {
+            return Err(ResponseError::permission_err(
+                "管理员必须启用两步验证",
+                &format!("[用户ID: {}]管理员尝试关闭两步验证", user_id),
+            ));
+        }
+    }
+    if !totp::verify_second_factor(&client, user_id, &input.code).await? {
+        attempt_limiter.record_failure(None, &ip);
+        return Err(ResponseError::input_err(
+            "验证码错误，请重新输入",
+            &format!("[用户ID: {}]关闭两步验证时验证失败", user_id),
+        ));
+    }
+    client.execute(&s2, &[&user_id]).await?;
+    // 管理员的权限依赖于是否启用了两步验证
+    permission::invalidate(user_id);
+    tracing::info!("[用户ID: {}]关闭了两步验证", user_id);
+
+    Ok(HttpResponse::Ok().body(Body::Empty))
+}
+
+// 重新生成恢复码，旧的恢复码全部失效
+#[post("/myself/totp/recovery_codes")]
+pub async fn post_my_recovery_codes(
+    req: HttpRequest,
+    auth_user: AuthUser,
+    db_pool: web::Data<Pool>,
+    attempt_limiter: web::Data<AttemptLimiterShare>,
+    input: web::Json<PostMyRecoveryCodesInput>,
+) -> Result<HttpResponse, ResponseError> {
+    let client: Client = db_pool.get().await?;
+    let user_id = auth_user.access_token_claims()?.user_id;
+    let ip = get_client_ip(&req);
+    attempt_limiter.check(None, &ip)?;
+
+    if !totp::verify_second_factor(&client, user_id, &input.code).await? {
+        attempt_limiter.record_failure(None, &ip);
+        return Err(ResponseError::input_err(
+            "验证码错误，请重新输入",
+            &format!("[用户ID: {}]重新生成恢复码时验证失败", user_id),
+        ));
+    }
+    let recovery_codes = totp::replace_recovery_codes(&client, user_id).await?;
+
+    Ok(HttpResponse::Ok().json(PostMyRecoveryCodesOutput { recovery_codes }))
+}
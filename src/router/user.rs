@@ -1,47 +1,50 @@
-use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use actix_multipart::Multipart;
+use actix_web::{body::Body, delete, get, patch, post, web, HttpRequest, HttpResponse};
 use chrono::{DateTime, Datelike, Duration, FixedOffset, Utc};
 use deadpool_postgres::{Client, Pool};
-use futures::future::{try_join, try_join3, try_join4};
+use futures::future::{try_join, try_join3};
+use futures::TryStreamExt;
 
+use crate::attempt_limiter::AttemptLimiterShare;
+use crate::config::GLOBAL_CONFIG;
 use crate::db::Type as DBType;
+use crate::email::EMailPool;
 use crate::error::{is_db_zero_line_error, ResponseError};
 use crate::model::{
-    email::VerifyEmailType,
-    role::{Permission, Role, RoleID},
+    email::{email_change_notice_html, PostSendVerifyEmailOutput, VerifyEmailType},
+    role::{Permission, Role, RoleKey},
     user::{
-        GetMyselfOutput, GetUserOutput, GetUserPath, PostNewTokenInput, PostNewTokenOutput,
-        PostUserDailyBonusOutput, PostUserInput, PostUserLoginInput, PostUserLoginOutput,
+        AvatarThumbnail, DeleteMyselfInput, DeleteMyselfOutput, GetMyExportOutput, GetMyselfOutput,
+        GetUserOutput, GetUserPath, PatchMyselfInput, PostMyAvatarOutput, PostMyEmailConfirmInput,
+        PostMyEmailConfirmOutput, PostMyEmailInput, PostMyPasswordInput, PostMyPasswordOutput,
+        PostNewTokenInput, PostNewTokenOutput, PostUserDailyBonusOutput, PostUserInput,
+        PostUserLoginInput, PostUserLoginMagicInput, PostUserLoginTotpInput, PostUserLogoutInput,
         PostUserOutput, PostUserRegisterInput, PostUserRegisterOutput, PostUserResetPasswordInput,
         PostUserResetPasswordOutput,
     },
 };
-use crate::util::{hash, jwt, req_parse::get_user_id};
+use crate::resource_provider::ResourceProviderShare;
+use crate::util::{
+    account, avatar, captcha, email, hash, jwt,
+    ledger::{self, LedgerReason},
+    permission::{self, require, RequirePermission},
+    profile,
+    req_parse::{get_client_ip, AuthUser},
+    role,
+    session::{self, SessionDevice},
+    totp,
+};
 
 #[get("/user/{user_id}")]
 pub async fn get_user(
-    req: HttpRequest,
+    _: RequirePermission<require::GetUser>,
     db_pool: web::Data<Pool>,
     path: web::Path<GetUserPath>,
 ) -> Result<HttpResponse, ResponseError> {
     let client: Client = db_pool.get().await?;
-    let user_id = get_user_id(&req)?;
 
-    let (s1, s2) = try_join(
-        client.prepare_typed_cached(
-            &format!(
-                "SELECT bool_or({}) 
-                FROM igame.role 
-                WHERE id IN (
-                    SELECT role_id 
-                    FROM igame.user_role 
-                    WHERE user_id = $1
-                    AND (expire_at IS NULL OR (expire_at IS NOT NULL AND expire_at > now()))
-                )",
-                Permission::GetUser.to_string()
-            ),
-            &[DBType::INT4],
-        ),
-        client.prepare_typed_cached(
+    let s1 = client
+        .prepare_typed_cached(
             "SELECT u.id, u.email, u.nick_name, u.exp, u.coin, u.avatar_url, u.login_at, u.created_at, array_agg(r.id) AS role_ids, array_agg(r.name) AS role_names, array_agg(ur.expire_at) AS role_expire_ats
             FROM igame.user AS u
             INNER JOIN igame.user_role AS ur
@@ -51,28 +54,17 @@ pub async fn get_user(
             WHERE u.id = $1
             GROUP BY u.id, u.email, u.nick_name, u.exp, u.coin, u.avatar_url, u.login_at, u.created_at",
             &[DBType::INT4],
-        ),
-    )
-    .await?;
-
-    // 检查是否有对应权限
-    let r1 = client.query_one(&s1, &[&user_id]).await?;
-    let has_permission: bool = r1.get(0);
-    if !has_permission {
-        return Err(ResponseError::permission_err(
-            "获取用户信息失败，没有对应权限",
-            &format!("[用户ID: {}]没有get_user权限", user_id),
-        ));
-    }
+        )
+        .await?;
 
     // 获取用户信息
-    let r2 = client.query_one(&s2, &[&path.user_id]).await?;
+    let r1 = client.query_one(&s1, &[&path.user_id]).await?;
     // 转化成Vec<Role>类型
     let mut roles: Vec<Role> = Vec::new();
-    let role_ids: Vec<i32> = r2.get("role_ids");
+    let role_ids: Vec<i32> = r1.get("role_ids");
     if role_ids.len() > 0 {
-        let role_names: Vec<&str> = r2.get("role_names");
-        let role_expire_ats: Vec<Option<DateTime<Utc>>> = r2.get("role_expire_ats");
+        let role_names: Vec<&str> = r1.get("role_names");
+        let role_expire_ats: Vec<Option<DateTime<Utc>>> = r1.get("role_expire_ats");
         for (index, role_id) in role_ids.iter().enumerate() {
             roles.push(Role {
                 role_id: *role_id,
@@ -83,26 +75,173 @@ pub async fn get_user(
     }
 
     Ok(HttpResponse::Ok().json(GetUserOutput {
-        user_id: r2.get("id"),
-        email: r2.get("email"),
-        nick_name: r2.get("nick_name"),
-        exp: r2.get("exp"),
-        coin: r2.get("coin"),
+        user_id: r1.get("id"),
+        email: r1.get("email"),
+        nick_name: r1.get("nick_name"),
+        exp: r1.get("exp"),
+        coin: r1.get("coin"),
         roles: roles,
-        avatar_url: r2.get("avatar_url"),
-        login_at: r2.get("login_at"),
-        created_at: r2.get("created_at"),
+        avatar_url: r1.get("avatar_url"),
+        login_at: r1.get("login_at"),
+        created_at: r1.get("created_at"),
     }))
 }
 
 #[get("/myself")]
 pub async fn get_myself(
-    req: HttpRequest,
+    auth_user: AuthUser,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ResponseError> {
+    let client: Client = db_pool.get().await?;
+    let user_id = auth_user.user_id;
+
+    Ok(HttpResponse::Ok().json(query_myself(&client, user_id).await?))
+}
+
+// 修改当前用户的昵称或头像
+#[patch("/myself")]
+pub async fn patch_myself(
+    auth_user: AuthUser,
+    db_pool: web::Data<Pool>,
+    input: web::Json<PatchMyselfInput>,
+) -> Result<HttpResponse, ResponseError> {
+    let client: Client = db_pool.get().await?;
+    let user_id = auth_user.user_id;
+
+    let nick_name = match &input.nick_name {
+        Some(v) => Some(profile::check_nick_name(v)?),
+        None => None,
+    };
+    let avatar_url = match &input.avatar_url {
+        Some(v) => Some(profile::check_avatar_url(v)?),
+        None => None,
+    };
+
+    let (s1, s2) = try_join(
+        client.prepare_typed_cached(
+            "SELECT nick_name, nick_name_changed_at FROM igame.user WHERE id = $1",
+            &[DBType::INT4],
+        ),
+        // 只更新传入的字段
+        client.prepare_typed_cached(
+            "UPDATE igame.user
+            SET nick_name = COALESCE($2, nick_name),
+            nick_name_changed_at = CASE WHEN $2 IS NULL THEN nick_name_changed_at ELSE now() END,
+            avatar_url = COALESCE($3, avatar_url)
+            WHERE id = $1",
+            &[DBType::INT4, DBType::TEXT, DBType::TEXT],
+        ),
+    )
+    .await?;
+
+    // 昵称没有变化时不需要更新，也不受修改间隔的限制
+    let r1 = client.query_one(&s1, &[&user_id]).await?;
+    let old_nick_name: &str = r1.get("nick_name");
+    let nick_name = nick_name.filter(|v| v != old_nick_name);
+    if nick_name.is_some() {
+        let changed_at: Option<DateTime<Utc>> = r1.get("nick_name_changed_at");
+        if let Some(changed_at) = changed_at {
+            let cooldown = GLOBAL_CONFIG.profile.nick_name_cooldown;
+            let elapsed = (Utc::now() - changed_at).num_seconds();
+            if elapsed < cooldown {
+                let retry_after = (cooldown - elapsed) as u64;
+                return Err(ResponseError::rate_limited_err(
+                    &format!(
+                        "修改昵称过于频繁，请在{}天后重试",
+                        retry_after.div_ceil(86400)
+                    ),
+                    retry_after,
+                    &format!("[用户ID: {}]距离上次修改昵称仅{}秒", user_id, elapsed),
+                ));
+            }
+        }
+    }
+    client
+        .execute(&s2, &[&user_id, &nick_name, &avatar_url])
+        .await?;
+
+    Ok(HttpResponse::Ok().json(query_myself(&client, user_id).await?))
+}
+
+// 上传头像，生成多个尺寸的头像并将最大的尺寸设置为用户的avatar_url
+#[post("/myself/avatar")]
+pub async fn post_my_avatar(
+    auth_user: AuthUser,
     db_pool: web::Data<Pool>,
+    resource_provider: web::Data<ResourceProviderShare>,
+    mut payload: Multipart,
 ) -> Result<HttpResponse, ResponseError> {
+    let user_id = auth_user.user_id;
+    let max_size = GLOBAL_CONFIG.avatar.max_size;
+
+    // 读取名为avatar的字段，超过大小限制时立即停止读取
+    let mut content: Option<Vec<u8>> = None;
+    while let Some(mut field) = payload.try_next().await.map_err(|e| {
+        ResponseError::input_err("读取上传文件失败", &format!("解析multipart失败: {}", e))
+    })? {
+        let is_avatar = field
+            .content_disposition()
+            .is_some_and(|v| v.get_name() == Some("avatar"));
+        if !is_avatar {
+            continue;
+        }
+        let mut buffer = Vec::new();
+        while let Some(chunk) = field.try_next().await.map_err(|e| {
+            ResponseError::input_err("读取上传文件失败", &format!("读取头像失败: {}", e))
+        })? {
+            if buffer.len() + chunk.len() > max_size {
+                return Err(ResponseError::input_err(
+                    &format!("头像文件不能超过{}KB", max_size / 1024),
+                    &format!("[用户ID: {}]上传的头像超过大小限制", user_id),
+                ));
+            }
+            buffer.extend_from_slice(&chunk);
+        }
+        content = Some(buffer);
+        break;
+    }
+    let content = content.ok_or(ResponseError::input_err(
+        "请选择要上传的头像",
+        "multipart中没有avatar字段",
+    ))?;
+
+    // 同一张图片生成的文件名相同，重复上传时直接覆盖
+    let hash = blake3::hash(&content).to_hex();
+    let images = avatar::process_avatar(content).await?;
+    let mut thumbnails = Vec::new();
+    for (size, image) in images {
+        let file_name = format!("{}-{}-{}.png", user_id, &hash[..16], size);
+        let url = avatar::store_avatar(&resource_provider, &file_name, image).await?;
+        thumbnails.push(AvatarThumbnail { size, url });
+    }
+    let avatar_url = match thumbnails.last() {
+        Some(v) => v.url.clone(),
+        None => {
+            return Err(ResponseError::unexpected_err(
+                "处理头像失败",
+                "没有配置头像尺寸",
+            ))
+        }
+    };
+
     let client: Client = db_pool.get().await?;
-    let user_id = get_user_id(&req)?;
+    let s1 = client
+        .prepare_typed_cached(
+            "UPDATE igame.user SET avatar_url = $2 WHERE id = $1",
+            &[DBType::INT4, DBType::TEXT],
+        )
+        .await?;
+    client.execute(&s1, &[&user_id, &avatar_url]).await?;
+    tracing::info!("[用户ID: {}]更新了头像: {}", user_id, avatar_url);
+
+    Ok(HttpResponse::Ok().json(PostMyAvatarOutput {
+        avatar_url,
+        thumbnails,
+    }))
+}
 
+// 获取当前用户的完整信息
+async fn query_myself(client: &Client, user_id: i32) -> Result<GetMyselfOutput, ResponseError> {
     let s1 = client.prepare_typed_cached(
         "SELECT u.id, u.email, u.nick_name, u.exp, u.coin, u.avatar_url, u.login_at, u.created_at, array_agg(r.id) AS role_ids, array_agg(r.name) AS role_names, array_agg(ur.expire_at) AS role_expire_ats
         FROM igame.user AS u
@@ -131,7 +270,7 @@ pub async fn get_myself(
         }
     }
 
-    Ok(HttpResponse::Ok().json(GetMyselfOutput {
+    Ok(GetMyselfOutput {
         user_id: r1.get("id"),
         email: r1.get("email"),
         nick_name: r1.get("nick_name"),
@@ -141,87 +280,229 @@ pub async fn get_myself(
         avatar_url: r1.get("avatar_url"),
         login_at: r1.get("login_at"),
         created_at: r1.get("created_at"),
-    }))
+    })
 }
 
 #[post("/user/login")]
 pub async fn post_user_login(
+    req: HttpRequest,
     db_pool: web::Data<Pool>,
+    attempt_limiter: web::Data<AttemptLimiterShare>,
     input: web::Json<PostUserLoginInput>,
 ) -> Result<HttpResponse, ResponseError> {
     let client: Client = db_pool.get().await?;
+    let ip = get_client_ip(&req);
+    let email_addr = &email::normalize_addr(&input.email);
+    // 检查账号与ip是否因为多次失败被锁定
+    attempt_limiter.check(Some(email_addr), &ip)?;
 
-    let (s1, s2) = try_join(
+    let (s1, s2, s3) = try_join3(
         // 获取用户id跟密码
         client.prepare_typed_cached(
             "SELECT id, password
             FROM igame.user
-            WHERE email = $1",
+            WHERE lower(email) = $1",
             &[DBType::TEXT],
         ),
-        // 设置login_at时间
+        // 将密码升级为当前格式
         client.prepare_typed_cached(
             "UPDATE igame.user
-            SET login_at = $1
+            SET password = $1
             WHERE id = $2",
-            &[DBType::TIMESTAMPTZ, DBType::INT4],
+            &[DBType::BYTEA, DBType::INT4],
+        ),
+        // 判断用户是否开启了两步验证
+        client.prepare_typed_cached(
+            "SELECT EXISTS(SELECT 1 FROM igame.user_totp WHERE user_id = $1 AND enabled = true)",
+            &[DBType::INT4],
         ),
     )
     .await?;
 
     let r1 =
-        client.query_one(&s1, &[&input.email]).await.map_err(|e| {
+        client.query_one(&s1, &[email_addr]).await.map_err(|e| {
             match is_db_zero_line_error(&e) {
-                true => ResponseError::input_err("邮箱或密码不正确，请重新输入", "错误的邮箱地址"),
+                true => {
+                    attempt_limiter.record_failure(Some(email_addr), &ip);
+                    ResponseError::input_err("邮箱或密码不正确，请重新输入", "错误的邮箱地址")
+                }
                 false => ResponseError::from(e),
             }
         })?;
     let user_id: i32 = r1.get("id");
     let password: Vec<u8> = r1.get("password");
-    let same = hash::compare_password(&input.password, &password)?;
+    let same = hash::compare_password(&input.password, &password).await?;
     if !same {
+        attempt_limiter.record_failure(Some(email_addr), &ip);
         return Err(ResponseError::input_err(
             "邮箱或密码不正确，请重新输入",
             "错误的密码",
         ));
     }
+    attempt_limiter.record_success(email_addr);
 
-    client
-        .execute(&s2, &[&chrono::Utc::now(), &user_id])
+    // 旧格式或参数已变更的密码，在登陆成功后重新生成
+    if hash::need_rehash(&password) {
+        let new_password = hash::hash_password(&input.password).await?;
+        client.execute(&s2, &[&new_password, &user_id]).await?;
+        tracing::info!("[用户ID: {}]密码已升级为当前格式", user_id);
+    }
+
+    // 开启了两步验证，先签发challenge_token，验证通过后再创建会话
+    let r3 = client.query_one(&s3, &[&user_id]).await?;
+    let totp_enabled: bool = r3.get(0);
+    if totp_enabled {
+        return Ok(HttpResponse::Ok().json(session::totp_challenge(user_id)?));
+    }
+
+    Ok(HttpResponse::Ok().json(session::finish_login(&client, &req, user_id).await?))
+}
+
+// 使用邮件中的登陆码登陆，登陆码只能使用一次
+#[post("/user/login/magic")]
+pub async fn post_user_login_magic(
+    req: HttpRequest,
+    db_pool: web::Data<Pool>,
+    attempt_limiter: web::Data<AttemptLimiterShare>,
+    input: web::Json<PostUserLoginMagicInput>,
+) -> Result<HttpResponse, ResponseError> {
+    let client: Client = db_pool.get().await?;
+    let ip = get_client_ip(&req);
+    let email_addr = &email::normalize_addr(&input.email);
+    attempt_limiter.check(Some(email_addr), &ip)?;
+    // 登陆码不区分大小写，允许用户输入时带有空白或分隔符
+    let code: String = input
+        .code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+
+    let (s1, s2, s3) = try_join3(
+        client.prepare_typed_cached(
+            "SELECT id FROM igame.user WHERE lower(email) = $1",
+            &[DBType::TEXT],
+        ),
+        // 设置验证邮件为已使用
+        client.prepare_typed_cached(
+            "UPDATE igame.verify_email
+            SET used = TRUE
+            WHERE id = $1 AND used = FALSE",
+            &[DBType::INT4],
+        ),
+        // 判断用户是否开启了两步验证
+        client.prepare_typed_cached(
+            "SELECT EXISTS(SELECT 1 FROM igame.user_totp WHERE user_id = $1 AND enabled = true)",
+            &[DBType::INT4],
+        ),
+    )
+    .await?;
+
+    // 校验登陆码，输错时同时计入账号与ip的失败次数
+    let email_id = match email::check_verify_code(
+        &client,
+        &VerifyEmailType::MagicLogin,
+        email_addr,
+        None,
+        &code,
+    )
+    .await
+    {
+        Ok(v) => v,
+        Err(e) => {
+            attempt_limiter.record_failure(Some(email_addr), &ip);
+            return Err(e);
+        }
+    };
+    // 先确认用户存在再使用登陆码，用户不存在时登陆码不会被消耗
+    let r1 =
+        client.query_one(&s1, &[email_addr]).await.map_err(|e| {
+            match is_db_zero_line_error(&e) {
+                true => ResponseError::input_err(
+                    "该邮箱不存在，请检查是否填写正确",
+                    &format!("[邮箱地址: {}]使用登陆码登陆时用户不存在", email_addr),
+                ),
+                false => ResponseError::from(e),
+            }
+        })?;
+    let user_id: i32 = r1.get("id");
+    // 并发使用同一个登陆码时只有一个请求能成功
+    if client.execute(&s2, &[&email_id]).await? == 0 {
+        return Err(ResponseError::input_err(
+            "该登陆码已失效，请尝试重新发送邮件",
+            &format!("[邮箱地址: {}]登陆码已被使用", email_addr),
+        ));
+    }
+    attempt_limiter.record_success(email_addr);
+
+    // 登陆码只能代替密码，开启了两步验证时仍然需要完成两步验证
+    let r3 = client.query_one(&s3, &[&user_id]).await?;
+    let totp_enabled: bool = r3.get(0);
+    if totp_enabled {
+        return Ok(HttpResponse::Ok().json(session::totp_challenge(user_id)?));
+    }
+
+    Ok(HttpResponse::Ok().json(session::finish_login(&client, &req, user_id).await?))
+}
+
+// 登陆的第二步，使用验证器应用生成的验证码或恢复码完成登陆
+#[post("/user/login/totp")]
+pub async fn post_user_login_totp(
+    req: HttpRequest,
+    db_pool: web::Data<Pool>,
+    attempt_limiter: web::Data<AttemptLimiterShare>,
+    input: web::Json<PostUserLoginTotpInput>,
+) -> Result<HttpResponse, ResponseError> {
+    let client: Client = db_pool.get().await?;
+    let claims = jwt::parse_totp_challenge_token(&input.challenge_token)?;
+    let user_id = claims.user_id;
+
+    let s1 = client
+        .prepare_typed_cached(
+            "SELECT email FROM igame.user WHERE id = $1",
+            &[DBType::INT4],
+        )
         .await?;
+    let r1 = client.query_one(&s1, &[&user_id]).await?;
+    let email: String = r1.get("email");
 
-    let access_token = jwt::generate_access_token(user_id)?;
-    let refresh_token = jwt::generate_refresh_token(user_id, &hex::encode(password))?;
-    Ok(HttpResponse::Ok().json(PostUserLoginOutput {
-        user_id,
-        access_token,
-        refresh_token,
-    }))
+    // 与密码共用失败次数，防止暴力尝试验证码
+    let ip = get_client_ip(&req);
+    attempt_limiter.check(Some(&email), &ip)?;
+    if !totp::verify_second_factor(&client, user_id, &input.code).await? {
+        attempt_limiter.record_failure(Some(&email), &ip);
+        return Err(ResponseError::input_err(
+            "验证码错误，请重新输入",
+            &format!("[用户ID: {}]两步验证失败", user_id),
+        ));
+    }
+    attempt_limiter.record_success(&email);
+
+    Ok(HttpResponse::Ok().json(session::finish_login(&client, &req, user_id).await?))
 }
 
 #[post("/user/register")]
 pub async fn post_user_register(
+    req: HttpRequest,
     db_pool: web::Data<Pool>,
+    attempt_limiter: web::Data<AttemptLimiterShare>,
     input: web::Json<PostUserRegisterInput>,
 ) -> Result<HttpResponse, ResponseError> {
-    let client: Client = db_pool.get().await?;
+    let mut client: Client = db_pool.get().await?;
+    let ip = get_client_ip(&req);
+    attempt_limiter.check(None, &ip)?;
+    captcha::verify_solution(&input.captcha)?;
+    let email_addr = &email::normalize_addr(&input.email);
+    let nick_name = profile::check_nick_name(&input.nick_name)?;
+    hash::check_password_policy(&input.password, email_addr)?;
 
-    let (s1, s2, s3, s4) = try_join4(
-        // 获取最近的一次注册验证邮件
-        client.prepare_typed_cached(
-            "SELECT id, code, used, created_at
-            FROM igame.verify_email
-            WHERE type = $1 AND addr = $2
-            ORDER BY created_at DESC
-            LIMIT 1",
-            &[DBType::INT2, DBType::TEXT],
-        ),
+    let (s1, s2, s3) = try_join3(
         // 判断用户的邮箱是否存在
         client.prepare_typed_cached(
-            "SELECT EXISTS(SELECT 1 FROM igame.user WHERE email = $1)",
+            "SELECT EXISTS(SELECT 1 FROM igame.user WHERE lower(email) = $1)",
             &[DBType::TEXT],
         ),
-        // 添加记录到igame.user,igame.user_notice, igame.user_role表中
+        // 添加记录到igame.user,igame.user_notice表中，角色通过role::grant_initial_role授予
         client.prepare_typed_cached(
             "WITH
             u AS (
@@ -233,9 +514,8 @@ pub async fn post_user_register(
                 SELECT (SELECT id FROM u), id FROM igame.notice
                 WHERE send_new_user = true
             )
-            INSERT INTO igame.user_role(user_id, role_id) 
-            SELECT id, $4 FROM u RETURNING user_id",
-            &[DBType::TEXT, DBType::TEXT, DBType::BYTEA, DBType::INT4],
+            SELECT id AS user_id FROM u",
+            &[DBType::TEXT, DBType::TEXT, DBType::BYTEA],
         ),
         // 将注册验证邮件设置为已使用
         client.prepare_typed_cached(
@@ -247,150 +527,411 @@ pub async fn post_user_register(
     )
     .await?;
 
-    let (r1, r2) = try_join(
-        client.query_one(
-            &s1,
-            &[&VerifyEmailType::UserRegister.to_int2(), &input.email],
-        ),
-        client.query_one(&s2, &[&input.email]),
+    // 校验验证码，输错时计入ip的失败次数
+    let email_id = match email::check_verify_code(
+        &client,
+        &VerifyEmailType::UserRegister,
+        email_addr,
+        None,
+        &input.verify_code,
     )
     .await
-    .map_err(|e| match is_db_zero_line_error(&e) {
-        true => ResponseError::input_err(
-            "无法验证邮箱，请尝试重新发送邮件",
-            &format!("[邮箱地址: {}]未找到对应的注册验证邮件", &input.email),
+    {
+        Ok(v) => v,
+        Err(e) => {
+            attempt_limiter.record_failure(None, &ip);
+            return Err(e);
+        }
+    };
+    let r1 = client.query_one(&s1, &[email_addr]).await?;
+    let exist: bool = r1.get(0);
+    if exist {
+        return Err(ResponseError::input_err(
+            "该邮箱地址已注册，请使用其他邮箱",
+            &format!("[邮箱地址: {}]该地址早已存在", email_addr),
+        ));
+    }
+
+    let hased_password = hash::hash_password(&input.password).await?;
+    let transaction = client.transaction().await?;
+    //创建新用户
+    let r2 = transaction
+        .query_one(&s2, &[email_addr, &nick_name, &hased_password])
+        .await?;
+    let user_id: i32 = r2.get("user_id");
+    role::grant_initial_role(&transaction, user_id, RoleKey::User.as_str(), None).await?;
+    //设置verify_code为已使用
+    transaction.execute(&s3, &[&email_id]).await?;
+    transaction.commit().await?;
+
+    let tokens =
+        session::create_session(&client, user_id, &SessionDevice::from_request(&req)).await?;
+    Ok(HttpResponse::Ok().json(PostUserRegisterOutput {
+        user_id,
+        access_token: tokens.access_token,
+        refresh_token: tokens.refresh_token,
+    }))
+}
+
+#[post("/user/new_token")]
+pub async fn post_user_new_token(
+    req: HttpRequest,
+    db_pool: web::Data<Pool>,
+    input: web::Json<PostNewTokenInput>,
+) -> Result<HttpResponse, ResponseError> {
+    let client: Client = db_pool.get().await?;
+
+    let (user_id, tokens) = session::rotate_session(
+        &client,
+        &input.refresh_token,
+        &SessionDevice::from_request(&req),
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(PostNewTokenOutput {
+        user_id,
+        access_token: tokens.access_token,
+        refresh_token: tokens.refresh_token,
+    }))
+}
+
+// 退出当前设备的登陆
+#[post("/user/logout")]
+pub async fn post_user_logout(
+    db_pool: web::Data<Pool>,
+    input: web::Json<PostUserLogoutInput>,
+) -> Result<HttpResponse, ResponseError> {
+    let client: Client = db_pool.get().await?;
+
+    let user_id = session::revoke_session_by_refresh_token(&client, &input.refresh_token).await?;
+    tracing::info!("[用户ID: {}]已退出登陆", user_id);
+
+    Ok(HttpResponse::Ok().body(Body::Empty))
+}
+
+// 退出所有设备的登陆
+#[post("/user/logout_all")]
+pub async fn post_user_logout_all(
+    auth_user: AuthUser,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ResponseError> {
+    let client: Client = db_pool.get().await?;
+    let user_id = auth_user.access_token_claims()?.user_id;
+
+    session::revoke_user_sessions(&client, user_id).await?;
+    tracing::info!("[用户ID: {}]已退出所有设备的登陆", user_id);
+
+    Ok(HttpResponse::Ok().body(Body::Empty))
+}
+
+// 已登陆的用户使用当前密码修改密码
+#[post("/myself/password")]
+pub async fn post_my_password(
+    req: HttpRequest,
+    auth_user: AuthUser,
+    db_pool: web::Data<Pool>,
+    attempt_limiter: web::Data<AttemptLimiterShare>,
+    input: web::Json<PostMyPasswordInput>,
+) -> Result<HttpResponse, ResponseError> {
+    let client: Client = db_pool.get().await?;
+    let user_id = auth_user.access_token_claims()?.user_id;
+
+    let (s1, s2) = try_join(
+        client.prepare_typed_cached(
+            "SELECT email, password FROM igame.user WHERE id = $1",
+            &[DBType::INT4],
         ),
-        false => ResponseError::from(e),
-    })?;
-    let email_id: i32 = r1.get("id");
-    let code: &str = r1.get("code");
-    let used: bool = r1.get("used");
-    let created_at: DateTime<Utc> = r1.get("created_at");
-    if used == true {
+        client.prepare_typed_cached(
+            "UPDATE igame.user SET password = $2 WHERE id = $1",
+            &[DBType::INT4, DBType::BYTEA],
+        ),
+    )
+    .await?;
+
+    let r1 = client.query_one(&s1, &[&user_id]).await?;
+    let email: String = r1.get("email");
+    let password: Vec<u8> = r1.get("password");
+    // 与登陆共用失败次数，防止通过该接口暴力尝试密码
+    let ip = get_client_ip(&req);
+    attempt_limiter.check(Some(&email), &ip)?;
+    if !hash::compare_password(&input.old_password, &password).await? {
+        attempt_limiter.record_failure(Some(&email), &ip);
         return Err(ResponseError::input_err(
-            "该验证码已被使用，请尝试重新发送邮件",
-            &format!("[邮箱地址: {}]注册验证码已被使用", &input.email),
+            "当前密码不正确，请重新输入",
+            &format!("[用户ID: {}]修改密码时当前密码错误", user_id),
         ));
     }
-    if code != input.verify_code {
+    attempt_limiter.record_success(&email);
+    hash::check_password_policy(&input.new_password, &email)?;
+
+    let hased_password = hash::hash_password(&input.new_password).await?;
+    client.execute(&s2, &[&user_id, &hased_password]).await?;
+
+    // 其他设备上的会话全部失效，当前设备获得一对新的凭证
+    session::revoke_user_sessions(&client, user_id).await?;
+    let tokens =
+        session::create_session(&client, user_id, &SessionDevice::from_request(&req)).await?;
+    tracing::info!("[用户ID: {}]修改了密码", user_id);
+
+    Ok(HttpResponse::Ok().json(PostMyPasswordOutput {
+        user_id,
+        access_token: tokens.access_token,
+        refresh_token: tokens.refresh_token,
+    }))
+}
+
+// 修改邮箱，向新邮箱发送验证码，同时通知旧邮箱
+#[post("/myself/email")]
+pub async fn post_my_email(
+    req: HttpRequest,
+    auth_user: AuthUser,
+    db_pool: web::Data<Pool>,
+    email_pool: web::Data<EMailPool>,
+    attempt_limiter: web::Data<AttemptLimiterShare>,
+    input: web::Json<PostMyEmailInput>,
+) -> Result<HttpResponse, ResponseError> {
+    let mut client: Client = db_pool.get().await?;
+    let user_id = auth_user.access_token_claims()?.user_id;
+    let new_email = &email::normalize_addr(&input.new_email);
+
+    let (s1, s2) = try_join(
+        client.prepare_typed_cached(
+            "SELECT email, password FROM igame.user WHERE id = $1",
+            &[DBType::INT4],
+        ),
+        client.prepare_typed_cached(
+            "SELECT EXISTS(SELECT 1 FROM igame.user WHERE lower(email) = $1)",
+            &[DBType::TEXT],
+        ),
+    )
+    .await?;
+
+    let r1 = client.query_one(&s1, &[&user_id]).await?;
+    let email: String = r1.get("email");
+    let password: Vec<u8> = r1.get("password");
+    // 与登陆共用失败次数，防止通过该接口暴力尝试密码
+    let ip = get_client_ip(&req);
+    attempt_limiter.check(Some(&email), &ip)?;
+    if !hash::compare_password(&input.password, &password).await? {
+        attempt_limiter.record_failure(Some(&email), &ip);
         return Err(ResponseError::input_err(
-            "验证码错误，请尝试重新输入",
-            &format!(
-                "[邮箱地址: {}]发送的注册验证码{}与记录值不匹配",
-                &input.email, &input.verify_code
-            ),
+            "当前密码不正确，请重新输入",
+            &format!("[用户ID: {}]修改邮箱时当前密码错误", user_id),
         ));
     }
-    if created_at < Utc::now() - Duration::hours(2) {
+    attempt_limiter.record_success(&email);
+
+    if new_email.eq_ignore_ascii_case(&email) {
         return Err(ResponseError::input_err(
-            "验证码已过期，请尝试重新发送邮件",
-            &format!("[邮箱地址: {}]注册验证码已过期", &input.email),
+            "新邮箱与当前邮箱相同",
+            &format!("[用户ID: {}]修改邮箱时新邮箱与当前邮箱相同", user_id),
         ));
     }
+    let r2 = client.query_one(&s2, &[&new_email]).await?;
     let exist: bool = r2.get(0);
     if exist {
         return Err(ResponseError::input_err(
-            "该邮箱地址已注册，请使用其他邮箱",
-            &format!("[邮箱地址: {}]该地址早已存在", &input.email),
+            "邮箱地址已注册，请使用其他邮箱",
+            "EmailChange验证邮件发送失败，igame.user表内的email字段已存在",
         ));
     }
 
-    let hased_password = hash::hash_password(&input.password);
-    let (r3, _) = try_join(
-        //创建新用户
-        client.query_one(
-            &s3,
-            &[
-                &input.email,
-                &input.nick_name,
-                &hased_password,
-                &RoleID::User.to_i32(),
-            ],
+    let (email_id, created_at) = email::send_verify_email(
+        &mut client,
+        &email_pool,
+        &VerifyEmailType::EmailChange,
+        new_email,
+        Some(user_id),
+        &ip,
+    )
+    .await?;
+    // 通知旧邮箱，发送失败不影响修改流程
+    if let Err(e) = email::send_email(
+        &email_pool,
+        &GLOBAL_CONFIG.email.sender,
+        &email,
+        "邮箱变更通知",
+        &email_change_notice_html(new_email),
+    )
+    .await
+    {
+        tracing::warn!("[用户ID: {}]发送邮箱变更通知失败: {:?}", user_id, e);
+    }
+
+    Ok(HttpResponse::Ok().json(PostSendVerifyEmailOutput {
+        email_id,
+        created_at,
+    }))
+}
+
+// 使用新邮箱收到的验证码确认修改邮箱
+#[post("/myself/email/confirm")]
+pub async fn post_my_email_confirm(
+    req: HttpRequest,
+    auth_user: AuthUser,
+    db_pool: web::Data<Pool>,
+    attempt_limiter: web::Data<AttemptLimiterShare>,
+    input: web::Json<PostMyEmailConfirmInput>,
+) -> Result<HttpResponse, ResponseError> {
+    let mut client: Client = db_pool.get().await?;
+    let user_id = auth_user.access_token_claims()?.user_id;
+    let new_email = &email::normalize_addr(&input.new_email);
+    let ip = get_client_ip(&req);
+    attempt_limiter.check(Some(new_email), &ip)?;
+
+    let (s1, s2, s3) = try_join3(
+        client.prepare_typed_cached(
+            "SELECT EXISTS(SELECT 1 FROM igame.user WHERE lower(email) = $1)",
+            &[DBType::TEXT],
+        ),
+        client.prepare_typed_cached(
+            "UPDATE igame.user SET email = $2 WHERE id = $1",
+            &[DBType::INT4, DBType::TEXT],
+        ),
+        // 设置验证邮件为已使用
+        client.prepare_typed_cached(
+            "UPDATE igame.verify_email
+            SET used = TRUE
+            WHERE id = $1",
+            &[DBType::INT4],
         ),
-        //设置verify_code为已使用
-        client.execute(&s4, &[&email_id]),
     )
     .await?;
-    let user_id: i32 = r3.get("user_id");
 
-    let access_token = jwt::generate_access_token(user_id)?;
-    let refresh_token = jwt::generate_refresh_token(user_id, &hex::encode(hased_password))?;
-    Ok(HttpResponse::Ok().json(PostUserRegisterOutput {
+    // 校验验证码，输错时同时计入新邮箱与ip的失败次数
+    let email_id = match email::check_verify_code(
+        &client,
+        &VerifyEmailType::EmailChange,
+        new_email,
+        Some(user_id),
+        &input.verify_code,
+    )
+    .await
+    {
+        Ok(v) => v,
+        Err(e) => {
+            attempt_limiter.record_failure(Some(new_email), &ip);
+            return Err(e);
+        }
+    };
+    // 发送验证码后邮箱可能已被其他账号注册，提交前再检查一次
+    let r1 = client.query_one(&s1, &[&new_email]).await?;
+    let exist: bool = r1.get(0);
+    if exist {
+        return Err(ResponseError::input_err(
+            "邮箱地址已注册，请使用其他邮箱",
+            &format!(
+                "[用户ID: {}]修改邮箱时[邮箱地址: {}]已存在",
+                user_id, new_email
+            ),
+        ));
+    }
+
+    // 修改邮箱与设置验证码为已使用必须同时生效，防止验证码被重复使用
+    let transaction = client.transaction().await?;
+    transaction.execute(&s2, &[&user_id, &new_email]).await?;
+    transaction.execute(&s3, &[&email_id]).await?;
+    transaction.commit().await?;
+    tracing::info!("[用户ID: {}]修改邮箱为{}", user_id, new_email);
+
+    Ok(HttpResponse::Ok().json(PostMyEmailConfirmOutput {
         user_id,
-        access_token,
-        refresh_token,
+        email: new_email.to_string(),
     }))
 }
 
-#[post("/user/new_token")]
-pub async fn post_user_new_token(
+// 申请注销账号，冷静期过后数据会被清除
+#[delete("/myself")]
+pub async fn delete_myself(
+    req: HttpRequest,
+    auth_user: AuthUser,
     db_pool: web::Data<Pool>,
-    input: web::Json<PostNewTokenInput>,
+    attempt_limiter: web::Data<AttemptLimiterShare>,
+    input: web::Json<DeleteMyselfInput>,
 ) -> Result<HttpResponse, ResponseError> {
     let client: Client = db_pool.get().await?;
-    let claims = jwt::parse_refresh_token(&input.refresh_token)?;
+    let user_id = auth_user.access_token_claims()?.user_id;
 
     let s1 = client
         .prepare_typed_cached(
-            "SELECT password FROM igame.user WHERE id = $1",
+            "SELECT email, password FROM igame.user WHERE id = $1",
             &[DBType::INT4],
         )
         .await?;
-    let r1 = client
-        .query_one(&s1, &[&claims.user_id])
-        .await
-        .map_err(|e| match is_db_zero_line_error(&e) {
-            true => ResponseError::input_err(
-                "用户不存在，请重新登陆",
-                &format!("[用户ID: {}]该用户不存在", &claims.user_id),
-            ),
-            false => ResponseError::from(e),
-        })?;
+    let r1 = client.query_one(&s1, &[&user_id]).await?;
+    let email: String = r1.get("email");
     let password: Vec<u8> = r1.get("password");
-    let same = claims.password == hex::encode(password);
-    if !same {
+    // 与登陆共用失败次数，防止通过该接口暴力尝试密码
+    let ip = get_client_ip(&req);
+    attempt_limiter.check(Some(&email), &ip)?;
+    if !hash::compare_password(&input.password, &password).await? {
+        attempt_limiter.record_failure(Some(&email), &ip);
         return Err(ResponseError::input_err(
-            "密码已更改，请重新登陆",
-            &format!("[用户ID: {}]密码不匹配", &claims.user_id),
+            "当前密码不正确，请重新输入",
+            &format!("[用户ID: {}]注销账号时当前密码错误", user_id),
         ));
     }
+    attempt_limiter.record_success(&email);
 
-    let access_token = jwt::generate_access_token(claims.user_id)?;
-    let refresh_token = jwt::generate_refresh_token(claims.user_id, &claims.password)?;
-    Ok(HttpResponse::Ok().json(PostNewTokenOutput {
-        user_id: claims.user_id,
-        access_token,
-        refresh_token,
-    }))
+    let delete_at = account::schedule_deletion(&client, user_id).await?;
+    // 所有设备退出登陆，冷静期内重新登陆即可取消注销
+    session::revoke_user_sessions(&client, user_id).await?;
+    tracing::info!("[用户ID: {}]申请注销账号，将于{}清除", user_id, delete_at);
+
+    Ok(HttpResponse::Ok().json(DeleteMyselfOutput { user_id, delete_at }))
+}
+
+// 导出保存的该用户的全部数据
+#[get("/myself/export")]
+pub async fn get_my_export(
+    auth_user: AuthUser,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ResponseError> {
+    let client: Client = db_pool.get().await?;
+    let user_id = auth_user.access_token_claims()?.user_id;
+
+    let archive = account::export_user_data(&client, user_id).await?;
+    let exported_at = Utc::now();
+
+    Ok(HttpResponse::Ok()
+        .insert_header((
+            "Content-Disposition",
+            format!(
+                "attachment; filename=\"igame-{}-{}.json\"",
+                user_id,
+                exported_at.format("%Y%m%d%H%M%S")
+            ),
+        ))
+        .json(GetMyExportOutput {
+            user_id,
+            exported_at,
+            archive,
+        }))
 }
 
 #[post["/user/reset_password"]]
 pub async fn post_user_reset_password(
+    req: HttpRequest,
     db_pool: web::Data<Pool>,
+    attempt_limiter: web::Data<AttemptLimiterShare>,
     input: web::Json<PostUserResetPasswordInput>,
 ) -> Result<HttpResponse, ResponseError> {
     let client: Client = db_pool.get().await?;
+    let ip = get_client_ip(&req);
+    let email_addr = &email::normalize_addr(&input.email);
+    attempt_limiter.check(Some(email_addr), &ip)?;
+    hash::check_password_policy(&input.new_password, email_addr)?;
 
-    let (s1, s2, s3, s4) = try_join4(
-        // 获取最近的一次重置密码验证邮件
-        client.prepare_typed_cached(
-            "SELECT id, code, used, created_at
-            FROM igame.verify_email
-            WHERE type = $1 AND addr = $2
-            ORDER BY created_at DESC
-            LIMIT 1",
-            &[DBType::INT2, DBType::TEXT],
-        ),
+    let (s1, s2, s3) = try_join3(
         // 判断用户的邮箱是否存在
         client.prepare_typed_cached(
-            "SELECT EXISTS(SELECT 1 FROM igame.user WHERE email = $1)",
+            "SELECT EXISTS(SELECT 1 FROM igame.user WHERE lower(email) = $1)",
             &[DBType::TEXT],
         ),
         // 重置密码
         client.prepare_typed_cached(
             "UPDATE igame.user
             SET password = $1
-            WHERE email = $2
+            WHERE lower(email) = $2
             RETURNING id",
             &[DBType::BYTEA, DBType::TEXT],
         ),
@@ -404,70 +945,54 @@ pub async fn post_user_reset_password(
     )
     .await?;
 
-    let (r1, r2) = try_join(
-        client.query_one(
-            &s1,
-            &[&VerifyEmailType::UserRegister.to_int2(), &input.email],
-        ),
-        client.query_one(&s2, &[&input.email]),
+    // 校验验证码，输错时同时计入账号与ip的失败次数
+    let email_id = match email::check_verify_code(
+        &client,
+        &VerifyEmailType::PasswordReset,
+        email_addr,
+        None,
+        &input.verify_code,
     )
     .await
-    .map_err(|e| match is_db_zero_line_error(&e) {
-        true => ResponseError::input_err(
-            "无法验证邮箱，请尝试重新发送邮件",
-            &format!("[邮箱地址: {}]未找到对应的重置密码验证邮件", &input.email),
-        ),
-        false => ResponseError::from(e),
-    })?;
-    let email_id: i32 = r1.get("id");
-    let code: &str = r1.get("code");
-    let used: bool = r1.get("used");
-    let created_at: DateTime<Utc> = r1.get("created_at");
-    if used == true {
-        return Err(ResponseError::input_err(
-            "该验证码已被使用，请尝试重新发送邮件",
-            &format!("[邮箱地址: {}]重置密码验证码已被使用", &input.email),
-        ));
-    }
-    if code != input.verify_code {
-        return Err(ResponseError::input_err(
-            "验证码错误，请尝试重新输入",
-            &format!(
-                "[邮箱地址: {}]发送的重置密码验证码{}与记录值不匹配",
-                &input.email, &input.verify_code
-            ),
-        ));
-    }
-    if created_at < Utc::now() - Duration::hours(2) {
-        return Err(ResponseError::input_err(
-            "验证码已过期，请尝试重新发送邮件",
-            &format!("[邮箱地址: {}]重置密码验证码已过期", &input.email),
-        ));
-    }
-    let exist: bool = r2.get(0);
+    {
+        Ok(v) => v,
+        Err(e) => {
+            attempt_limiter.record_failure(Some(email_addr), &ip);
+            return Err(e);
+        }
+    };
+    let r1 = client.query_one(&s1, &[email_addr]).await?;
+    let exist: bool = r1.get(0);
     if !exist {
         return Err(ResponseError::input_err(
             "该邮箱地址不存在，请使用其他邮箱",
-            &format!("[邮箱地址: {}]该地址不存在", &input.email),
+            &format!("[邮箱地址: {}]该地址不存在", email_addr),
         ));
     }
 
-    let hased_password = hash::hash_password(&input.new_password);
-    let (r3, _) = try_join(
+    let hased_password = hash::hash_password(&input.new_password).await?;
+    let (r2, _) = try_join(
         //设置新密码
-        client.query_one(&s3, &[&hased_password, &input.email]),
+        client.query_one(&s2, &[&hased_password, email_addr]),
         //设置verify_code为已使用
-        client.execute(&s4, &[&email_id]),
+        client.execute(&s3, &[&email_id]),
     )
     .await?;
-    let user_id = r3.get("id");
+    let user_id = r2.get("id");
+    attempt_limiter.record_success(email_addr);
 
-    let access_token = jwt::generate_access_token(user_id)?;
-    let refresh_token = jwt::generate_refresh_token(user_id, &hex::encode(hased_password))?;
+    // 冷静期内通过邮箱重置密码同样视为重新登陆，取消注销
+    if account::cancel_deletion(&client, user_id).await? {
+        tracing::info!("[用户ID: {}]重置密码后取消了账号注销", user_id);
+    }
+    // 密码重置后，之前签发的所有会话都将失效
+    session::revoke_user_sessions(&client, user_id).await?;
+    let tokens =
+        session::create_session(&client, user_id, &SessionDevice::from_request(&req)).await?;
     Ok(HttpResponse::Ok().json(PostUserResetPasswordOutput {
         user_id,
-        access_token,
-        refresh_token,
+        access_token: tokens.access_token,
+        refresh_token: tokens.refresh_token,
     }))
 }
 
@@ -475,34 +1000,21 @@ pub async fn post_user_reset_password(
 #[post("/user")]
 pub async fn post_user(
     req: HttpRequest,
+    guard: RequirePermission<require::CreateUser>,
     db_pool: web::Data<Pool>,
     input: web::Json<PostUserInput>,
 ) -> Result<HttpResponse, ResponseError> {
-    let client: Client = db_pool.get().await?;
-    let user_id = get_user_id(&req)?;
+    let mut client: Client = db_pool.get().await?;
+    let operator_id = guard.auth_user.user_id;
+    let email_addr = &email::normalize_addr(&input.email);
 
-    let (s1, s2, s3) = try_join3(
-        // 检查是否有创建用户的权限
-        client.prepare_typed_cached(
-            &format!(
-                "SELECT bool_or({}) 
-                FROM igame.role 
-                WHERE id IN (
-                    SELECT role_id 
-                    FROM igame.user_role 
-                    WHERE user_id = $1
-                    AND (expire_at IS NULL OR (expire_at IS NOT NULL AND expire_at > now()))
-                )",
-                Permission::CreateUser.to_string()
-            ),
-            &[DBType::INT4],
-        ),
+    let (s2, s3) = try_join(
         // 判断用户的邮箱是否存在
         client.prepare_typed_cached(
-            "SELECT EXISTS(SELECT 1 FROM igame.user WHERE email = $1)",
+            "SELECT EXISTS(SELECT 1 FROM igame.user WHERE lower(email) = $1)",
             &[DBType::TEXT],
         ),
-        // 添加记录到igame.user,igame.user_notice, igame.user_role表中
+        // 添加记录到igame.user,igame.user_notice表中，角色通过role::grant_initial_role授予
         client.prepare_typed_cached(
             "WITH
             u AS (
@@ -514,62 +1026,61 @@ pub async fn post_user(
                 SELECT (SELECT id FROM u), id FROM igame.notice
                 WHERE send_new_user = true
             )
-            INSERT INTO igame.user_role(user_id, role_id) 
-            SELECT id, $4 FROM u RETURNING user_id",
-            &[DBType::TEXT, DBType::TEXT, DBType::BYTEA, DBType::INT4],
+            SELECT id AS user_id FROM u",
+            &[DBType::TEXT, DBType::TEXT, DBType::BYTEA],
         ),
     )
     .await?;
 
-    let (r1, r2) = try_join(
-        // 检查是否有对应权限
-        client.query_one(&s1, &[&user_id]),
-        //检查邮箱是否存在
-        client.query_one(&s2, &[&input.email]),
-    )
-    .await?;
-    let has_permission: bool = r1.get(0);
-    if !has_permission {
-        return Err(ResponseError::permission_err(
-            "创建用户失败，没有对应权限",
-            &format!("[用户ID: {}]没有create_user权限", user_id),
-        ));
-    }
+    //检查邮箱是否存在
+    let r2 = client.query_one(&s2, &[email_addr]).await?;
     let exist: bool = r2.get(0);
     if exist {
         return Err(ResponseError::input_err(
             "该邮箱地址已注册，请使用其他邮箱",
-            &format!("[邮箱地址: {}]该地址早已存在", &input.email),
+            &format!("[邮箱地址: {}]该地址早已存在", email_addr),
         ));
     }
 
     // 添加用户
-    let hased_password = hash::hash_password(&input.password);
-    let r3 = client
-        .query_one(
-            &s3,
-            &[
-                &input.email,
-                &input.nick_name,
-                &hased_password,
-                &input.role.to_i32(),
-            ],
-        )
+    let nick_name = profile::check_nick_name(&input.nick_name)?;
+    hash::check_password_policy(&input.password, email_addr)?;
+    // 指定普通用户以外的角色时，与授予角色一样需要管理角色的权限
+    if input.role != RoleKey::User.as_str() {
+        let permissions = permission::get_permissions(&req, &client, &guard.auth_user).await?;
+        if !permissions.contains(Permission::ManageRole) {
+            return Err(ResponseError::permission_err(
+                "没有授予该角色的权限",
+                &format!(
+                    "[用户ID: {}]没有管理角色的权限，尝试创建角色为[{}]的用户",
+                    operator_id, input.role
+                ),
+            ));
+        }
+    }
+    let hased_password = hash::hash_password(&input.password).await?;
+    let transaction = client.transaction().await?;
+    let role_id = role::get_role_id(&transaction, &input.role).await?;
+    role::check_manageable(&transaction, operator_id, role_id, &input.role).await?;
+    let r3 = transaction
+        .query_one(&s3, &[email_addr, &nick_name, &hased_password])
         .await?;
-    let user_id: i32 = r3.get("id");
+    let user_id: i32 = r3.get("user_id");
+    role::grant_initial_role(&transaction, user_id, &input.role, Some(operator_id)).await?;
+    transaction.commit().await?;
 
     Ok(HttpResponse::Ok().json(PostUserOutput { user_id }))
 }
 
 #[post("/user/daily_bonus")]
 pub async fn post_user_daily_bonus(
-    req: HttpRequest,
+    auth_user: AuthUser,
     db_pool: web::Data<Pool>,
 ) -> Result<HttpResponse, ResponseError> {
     let mut client: Client = db_pool.get().await?;
-    let user_id = get_user_id(&req)?;
+    let user_id = auth_user.user_id;
 
-    let (s1, s2, s3) = try_join3(
+    let (s1, s2) = try_join(
         // 获取签到记录
         client.prepare_typed_cached(
             "SELECT time, count 
@@ -586,14 +1097,6 @@ pub async fn post_user_daily_bonus(
             RETURNING id",
             &[DBType::INT4, DBType::INT4],
         ),
-        // 更新用户的无限币跟经验
-        client.prepare_typed_cached(
-            "UPDATE igame.user 
-            SET coin = coin + $1, exp = exp + $2 
-            WHERE id = $3 
-            RETURNING coin, exp",
-            &[DBType::INT4, DBType::INT4, DBType::INT4],
-        ),
     )
     .await?;
 
@@ -641,19 +1144,28 @@ pub async fn post_user_daily_bonus(
 
     // 启用事务来更新签到后的用户信息，以及插入新的签到行
     let transaction = client.transaction().await?;
-    let (r2, r3) = try_join(
-        transaction.query_one(&s2, &[&user_id, &count]),
-        transaction.query_one(&s3, &[&added_coin, &added_exp, &user_id]),
+    let r2 = transaction.query_one(&s2, &[&user_id, &count]).await?;
+    let daily_bonus_id: i32 = r2.get("id");
+    let balance = ledger::apply(
+        &transaction,
+        user_id,
+        added_coin,
+        added_exp,
+        LedgerReason::DailyBonus,
+        Some(daily_bonus_id),
     )
-    .await?;
+    .await?
+    .ok_or_else(|| {
+        ResponseError::unexpected_err("签到失败", &format!("用户ID: {}不存在", user_id))
+    })?;
     transaction.commit().await?;
 
     Ok(HttpResponse::Ok().json(PostUserDailyBonusOutput {
-        daily_bonus_id: r2.get("id"),
+        daily_bonus_id,
         count,
         added_coin,
         added_exp,
-        total_coin: r3.get("coin"),
-        total_exp: r3.get("exp"),
+        total_coin: balance.coin,
+        total_exp: balance.exp,
     }))
 }
@@ -0,0 +1,133 @@
+use actix_web::{get, post, web, HttpResponse};
+use chrono::{Duration, Utc};
+use deadpool_postgres::{Client, Pool};
+
+use crate::config::GLOBAL_CONFIG;
+use crate::db::Type as DBType;
+use crate::error::ResponseError;
+use crate::model::{
+    role::RoleKey,
+    trade::TradeType,
+    vip::{GetVipPlansOutput, GetVipPlansOutputItem, PostVipPurchaseInput, PostVipPurchaseOutput},
+};
+use crate::util::{
+    ledger::{self, LedgerReason},
+    permission,
+    req_parse::AuthUser,
+    role::{self, GrantAction},
+};
+
+// 获取可以购买的会员套餐
+#[get("/vip/plans")]
+pub async fn get_vip_plans() -> Result<HttpResponse, ResponseError> {
+    let output: GetVipPlansOutput = GLOBAL_CONFIG
+        .vip
+        .plans
+        .iter()
+        .map(|v| GetVipPlansOutputItem {
+            plan_id: v.id.clone(),
+            name: v.name.clone(),
+            days: v.days,
+            price: v.price,
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(output))
+}
+
+// 使用无限币购买会员，已是会员时在原过期时间上累加
+#[post("/vip/purchase")]
+pub async fn post_vip_purchase(
+    auth_user: AuthUser,
+    db_pool: web::Data<Pool>,
+    input: web::Json<PostVipPurchaseInput>,
+) -> Result<HttpResponse, ResponseError> {
+    let mut client: Client = db_pool.get().await?;
+    let user_id = auth_user.access_token_claims()?.user_id;
+    let plan = GLOBAL_CONFIG
+        .vip
+        .plans
+        .iter()
+        .find(|v| v.id == input.plan_id)
+        .ok_or_else(|| {
+            ResponseError::input_err(
+                "会员套餐不存在",
+                &format!("[用户ID: {}]会员套餐[{}]不存在", user_id, input.plan_id),
+            )
+        })?;
+
+    let transaction = client.transaction().await?;
+    let s1 = transaction
+        .prepare_typed_cached(
+            "INSERT INTO igame.trade (user_id, type, cost, vip_plan) VALUES ($1, $2, $3, $4) RETURNING id",
+            &[DBType::INT4, DBType::INT2, DBType::INT4, DBType::TEXT],
+        )
+        .await?;
+    let r1 = transaction
+        .query_one(
+            &s1,
+            &[
+                &user_id,
+                &TradeType::VipPurchase.as_i16(),
+                &plan.price,
+                &plan.id,
+            ],
+        )
+        .await?;
+    let trade_id: i32 = r1.get("id");
+    // 扣除无限币时会锁定用户行，同一用户的购买请求依次执行
+    let balance = match ledger::apply(
+        &transaction,
+        user_id,
+        -plan.price,
+        0,
+        LedgerReason::VipPurchase,
+        Some(trade_id),
+    )
+    .await?
+    {
+        Some(balance) => balance,
+        None => {
+            return Err(ResponseError::lack_coin_err(
+                "用户无限币不足，无法购买会员",
+                plan.price,
+                &format!("[用户ID: {}]购买会员套餐[{}]", user_id, plan.id),
+            ));
+        }
+    };
+
+    let role_id = role::get_role_id(&transaction, RoleKey::Vip.as_str()).await?;
+    let previous = role::lock_grant(&transaction, user_id, role_id).await?;
+    let now = Utc::now();
+    let (action, start_at) = match previous {
+        Some(previous) if previous.is_active() => match previous.expire_at {
+            Some(expire_at) => (GrantAction::Extend, expire_at),
+            None => {
+                return Err(ResponseError::already_done_err(
+                    "您已经是永久会员",
+                    &format!("[用户ID: {}]已经是永久会员", user_id),
+                ));
+            }
+        },
+        _ => (GrantAction::Grant, now),
+    };
+    let expire_at = start_at + Duration::days(plan.days);
+    role::set_grant(
+        &transaction,
+        user_id,
+        role_id,
+        previous,
+        Some(expire_at),
+        action,
+        None,
+    )
+    .await?;
+    transaction.commit().await?;
+    permission::invalidate(user_id);
+
+    Ok(HttpResponse::Ok().json(PostVipPurchaseOutput {
+        trade_id,
+        remain_coin: balance.coin,
+        expire_at,
+    }))
+}
@@ -8,6 +8,8 @@ use tracing::Span;
 use tracing_actix_web::{root_span_macro::private, RootSpanBuilder};
 use tracing_futures::Instrument;
 
+use crate::util::req_parse::client_ip;
+
 /// We will define a custom root span builder to capture additional fields, specific
 /// to our application, on top of the ones provided by `DefaultRootSpanBuilder` out of the box.
 pub struct CustomRootSpanBuilder;
@@ -27,7 +29,7 @@ impl RootSpanBuilder for CustomRootSpanBuilder {
         let span = private::tracing::info_span!(
             "HTTP request",
             id = %private::get_request_id(request),
-            ip = %connection_info.realip_remote_addr().unwrap_or(""),
+            ip = %client_ip(request.peer_addr(), request.headers()),
             host = %connection_info.host(),
             method = %private::http_method_str(request.method()),
             target = %request.uri().path_and_query().map(|p| p.as_str()).unwrap_or(""),
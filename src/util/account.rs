@@ -0,0 +1,192 @@
+use chrono::{DateTime, Duration, Utc};
+use deadpool_postgres::Client;
+
+use crate::config::GLOBAL_CONFIG;
+use crate::db::Type as DBType;
+use crate::error::ResponseError;
+
+// 申请注销账号，返回计划清除数据的时间
+pub async fn schedule_deletion(
+    client: &Client,
+    user_id: i32,
+) -> Result<DateTime<Utc>, ResponseError> {
+    let s1 = client
+        .prepare_typed_cached(
+            "UPDATE igame.user
+            SET delete_at = COALESCE(delete_at, $2)
+            WHERE id = $1 AND deleted_at IS NULL
+            RETURNING delete_at",
+            &[DBType::INT4, DBType::TIMESTAMPTZ],
+        )
+        .await?;
+    let delete_at = Utc::now() + Duration::seconds(GLOBAL_CONFIG.account.deletion_grace_period);
+    let r1 = client.query_one(&s1, &[&user_id, &delete_at]).await?;
+    Ok(r1.get("delete_at"))
+}
+
+// 冷静期内登陆时取消注销，返回是否取消了注销
+pub async fn cancel_deletion(client: &Client, user_id: i32) -> Result<bool, ResponseError> {
+    let s1 = client
+        .prepare_typed_cached(
+            "UPDATE igame.user
+            SET delete_at = NULL
+            WHERE id = $1 AND delete_at > now() AND deleted_at IS NULL",
+            &[DBType::INT4],
+        )
+        .await?;
+    Ok(client.execute(&s1, &[&user_id]).await? > 0)
+}
+
+// 清除所有冷静期已过的账号，返回清除的账号数量
+// 用户行本身会被匿名化而不是删除，交易记录、流水与角色变更记录仍然关联在匿名用户上以便对账
+// 清零的余额会记入流水，保证余额与流水之和一致
+pub async fn purge_deleted_users(client: &Client) -> Result<u64, ResponseError> {
+    let s1 = client
+        .prepare_typed_cached(
+            "WITH
+            u AS (
+                SELECT id, email, coin, exp FROM igame.user
+                WHERE delete_at <= now() AND deleted_at IS NULL
+                FOR UPDATE
+            ),
+            d1 AS (DELETE FROM igame.user_role WHERE user_id IN (SELECT id FROM u)),
+            d2 AS (DELETE FROM igame.user_notice WHERE user_id IN (SELECT id FROM u)),
+            d3 AS (DELETE FROM igame.user_app_sub WHERE user_id IN (SELECT id FROM u)),
+            d4 AS (DELETE FROM igame.daily_bonus WHERE user_id IN (SELECT id FROM u)),
+            d5 AS (DELETE FROM igame.session WHERE user_id IN (SELECT id FROM u)),
+            d6 AS (DELETE FROM igame.user_totp WHERE user_id IN (SELECT id FROM u)),
+            d7 AS (DELETE FROM igame.user_recovery_code WHERE user_id IN (SELECT id FROM u)),
+            d8 AS (
+                DELETE FROM igame.verify_email
+                WHERE addr IN (SELECT email FROM u) OR user_id IN (SELECT id FROM u)
+            ),
+            d9 AS (DELETE FROM igame.user_identity WHERE user_id IN (SELECT id FROM u)),
+            d10 AS (DELETE FROM igame.api_key WHERE user_id IN (SELECT id FROM u)),
+            l AS (
+                INSERT INTO igame.ledger (user_id, currency, delta, balance, reason)
+                SELECT id, 'coin', -coin, 0, 'account_purge' FROM u WHERE coin <> 0
+                UNION ALL
+                SELECT id, 'exp', -exp, 0, 'account_purge' FROM u WHERE exp <> 0
+            )
+            UPDATE igame.user
+            SET email = 'deleted-' || id || '@deleted.invalid',
+                nick_name = '已注销用户',
+                password = ''::BYTEA,
+                avatar_url = DEFAULT,
+                coin = 0,
+                exp = 0,
+                nick_name_changed_at = NULL,
+                delete_at = NULL,
+                deleted_at = now()
+            WHERE id IN (SELECT id FROM u)",
+            &[],
+        )
+        .await?;
+    Ok(client.execute(&s1, &[]).await?)
+}
+
+// 导出保存的该用户的全部数据，密码、验证码与凭证等不会被导出
+pub async fn export_user_data(
+    client: &Client,
+    user_id: i32,
+) -> Result<serde_json::Value, ResponseError> {
+    let s1 = client
+        .prepare_typed_cached(
+            "SELECT json_build_object(
+                'user', (
+                    SELECT to_jsonb(u) - 'password' FROM igame.user AS u WHERE u.id = $1
+                ),
+                'roles', (
+                    SELECT COALESCE(json_agg(json_build_object(
+                        'role_id', r.id, 'name', r.name, 'expire_at', ur.expire_at
+                    )), '[]')
+                    FROM igame.user_role AS ur
+                    INNER JOIN igame.role AS r
+                    ON ur.role_id = r.id
+                    WHERE ur.user_id = $1
+                ),
+                'role_grants', (
+                    SELECT COALESCE(json_agg(json_build_object(
+                        'role_id', l.role_id, 'action', l.action, 'previous_expire_at', l.previous_expire_at,
+                        'expire_at', l.expire_at, 'created_at', l.created_at
+                    ) ORDER BY l.id), '[]')
+                    FROM igame.role_grant_log AS l
+                    WHERE l.user_id = $1
+                ),
+                'notices', (
+                    SELECT COALESCE(json_agg(json_build_object(
+                        'notice_id', n.id, 'title', n.title, 'read', un.read, 'created_at', n.created_at
+                    )), '[]')
+                    FROM igame.user_notice AS un
+                    INNER JOIN igame.notice AS n
+                    ON un.notice_id = n.id
+                    WHERE un.user_id = $1
+                ),
+                'app_subscriptions', (
+                    SELECT COALESCE(json_agg(s), '[]') FROM igame.user_app_sub AS s WHERE s.user_id = $1
+                ),
+                'daily_bonuses', (
+                    SELECT COALESCE(json_agg(d ORDER BY d.time), '[]') FROM igame.daily_bonus AS d WHERE d.user_id = $1
+                ),
+                'trades', (
+                    SELECT COALESCE(json_agg(t ORDER BY t.id), '[]') FROM igame.trade AS t WHERE t.user_id = $1
+                ),
+                'ledger', (
+                    SELECT COALESCE(json_agg(json_build_object(
+                        'currency', l.currency, 'delta', l.delta, 'balance', l.balance, 'reason', l.reason,
+                        'ref_id', l.ref_id, 'created_at', l.created_at
+                    ) ORDER BY l.id), '[]')
+                    FROM igame.ledger AS l
+                    WHERE l.user_id = $1
+                ),
+                'sessions', (
+                    SELECT COALESCE(json_agg(json_build_object(
+                        'device', s.device, 'ip', s.ip, 'login_at', s.login_at, 'created_at', s.created_at,
+                        'used_at', s.used_at, 'revoked_at', s.revoked_at, 'expire_at', s.expire_at
+                    ) ORDER BY s.created_at), '[]')
+                    FROM igame.session AS s
+                    WHERE s.user_id = $1
+                ),
+                'verify_emails', (
+                    SELECT COALESCE(json_agg(json_build_object(
+                        'type', v.type, 'addr', v.addr, 'used', v.used, 'created_at', v.created_at
+                    ) ORDER BY v.created_at), '[]')
+                    FROM igame.verify_email AS v
+                    WHERE v.addr = (SELECT email FROM igame.user WHERE id = $1) OR v.user_id = $1
+                ),
+                'verify_email_sends', (
+                    SELECT COALESCE(json_agg(json_build_object(
+                        'addr', vs.addr, 'ip', vs.ip, 'sent_at', vs.sent_at
+                    ) ORDER BY vs.sent_at), '[]')
+                    FROM igame.verify_email_send AS vs
+                    WHERE vs.addr = (SELECT email FROM igame.user WHERE id = $1)
+                ),
+                'identities', (
+                    SELECT COALESCE(json_agg(json_build_object(
+                        'provider', i.provider, 'subject', i.subject, 'email', i.email, 'created_at', i.created_at
+                    ) ORDER BY i.created_at), '[]')
+                    FROM igame.user_identity AS i
+                    WHERE i.user_id = $1
+                ),
+                'api_keys', (
+                    SELECT COALESCE(json_agg(json_build_object(
+                        'name', k.name, 'key_prefix', k.key_prefix, 'scopes', k.scopes, 'expire_at', k.expire_at,
+                        'used_at', k.used_at, 'revoked_at', k.revoked_at, 'created_at', k.created_at
+                    ) ORDER BY k.created_at), '[]')
+                    FROM igame.api_key AS k
+                    WHERE k.user_id = $1
+                ),
+                'totp', (
+                    SELECT json_build_object('enabled', t.enabled, 'enabled_at', t.enabled_at, 'created_at', t.created_at)
+                    FROM igame.user_totp AS t
+                    WHERE t.user_id = $1
+                )
+            )::TEXT AS archive",
+            &[DBType::INT4],
+        )
+        .await?;
+    let r1 = client.query_one(&s1, &[&user_id]).await?;
+    let archive: &str = r1.get("archive");
+    serde_json::from_str(archive)
+        .map_err(|e| ResponseError::unexpected_err("导出数据失败", &format!("{}", e)))
+}
@@ -0,0 +1,92 @@
+use deadpool_postgres::Client;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+
+use crate::db::Type as DBType;
+use crate::error::ResponseError;
+use crate::model::role::Permission;
+
+// API密钥的固定前缀，方便在日志与代码仓库中识别泄露的密钥
+const KEY_PREFIX: &str = "igk_";
+// 列表中展示的密钥开头部分的长度，用于区分不同的密钥
+const DISPLAY_PREFIX_LEN: usize = KEY_PREFIX.len() + 8;
+
+// 通过API密钥认证的身份，由api_key_middleware写入请求的extensions
+#[derive(Debug, Clone)]
+pub struct ApiKeyIdentity {
+    pub key_id: i32,
+    pub user_id: i32,
+    pub scopes: Vec<String>,
+}
+
+impl ApiKeyIdentity {
+    pub fn has_scope(&self, permission: Permission) -> bool {
+        self.scopes.contains(&permission.key())
+    }
+}
+
+// 生成新的API密钥，明文只在创建时返回给用户一次
+pub fn generate_api_key() -> String {
+    format!(
+        "{}{}",
+        KEY_PREFIX,
+        hex::encode(rand::thread_rng().gen::<[u8; 32]>())
+    )
+}
+
+// 密钥本身是高熵的随机数，使用sha256摘要即可，不需要慢哈希
+pub fn hash_api_key(api_key: &str) -> Vec<u8> {
+    Sha256::digest(api_key.as_bytes()).to_vec()
+}
+
+pub fn display_prefix(api_key: &str) -> String {
+    api_key.chars().take(DISPLAY_PREFIX_LEN).collect()
+}
+
+// 校验API密钥并记录使用时间，已吊销、已过期或属于已申请注销用户的密钥无效
+// 使用时间精确到分钟，一分钟内重复使用同一个密钥不会再写入数据库
+pub async fn authenticate(client: &Client, api_key: &str) -> Result<ApiKeyIdentity, ResponseError> {
+    let invalid_err = || {
+        ResponseError::access_token_err(
+            "API密钥无效或已过期",
+            &format!("API密钥[{}]无效", display_prefix(api_key)),
+        )
+    };
+    if !api_key.starts_with(KEY_PREFIX) {
+        return Err(invalid_err());
+    }
+
+    let s1 = client
+        .prepare_typed_cached(
+            "WITH
+            k AS (
+                SELECT k.id, k.user_id, k.scopes, k.used_at
+                FROM igame.api_key AS k
+                INNER JOIN igame.user AS u
+                ON k.user_id = u.id
+                WHERE k.key_hash = $1
+                AND k.revoked_at IS NULL AND (k.expire_at IS NULL OR k.expire_at > now())
+                AND u.delete_at IS NULL AND u.deleted_at IS NULL
+            ),
+            u AS (
+                UPDATE igame.api_key SET used_at = now()
+                WHERE id IN (
+                    SELECT id FROM k
+                    WHERE used_at IS NULL OR used_at < now() - INTERVAL '1 minute'
+                )
+            )
+            SELECT id, user_id, scopes FROM k",
+            &[DBType::BYTEA],
+        )
+        .await?;
+    let r1 = client
+        .query_opt(&s1, &[&hash_api_key(api_key)])
+        .await?
+        .ok_or_else(invalid_err)?;
+
+    Ok(ApiKeyIdentity {
+        key_id: r1.get("id"),
+        user_id: r1.get("user_id"),
+        scopes: r1.get("scopes"),
+    })
+}
@@ -0,0 +1,110 @@
+use actix_web::web;
+use image::{imageops::FilterType, io::Limits, io::Reader, ImageFormat, ImageOutputFormat};
+use std::io::Cursor;
+use std::path::Path;
+
+use crate::config::GLOBAL_CONFIG;
+use crate::error::ResponseError;
+use crate::resource_provider::ResourceProviderShare;
+
+// 允许上传的图片格式，其余格式即使能解码也会被拒绝
+const ALLOWED_FORMATS: &[ImageFormat] = &[
+    ImageFormat::Png,
+    ImageFormat::Jpeg,
+    ImageFormat::Gif,
+    ImageFormat::WebP,
+];
+
+// 解码上传的图片，裁剪为正方形后按配置的尺寸重新编码为png
+// 重新编码后不会保留原图中的exif等元数据
+pub async fn process_avatar(content: Vec<u8>) -> Result<Vec<(u32, Vec<u8>)>, ResponseError> {
+    web::block(move || process_avatar_blocking(&content))
+        .await
+        .map_err(|e| ResponseError::unexpected_err("处理头像失败", &format!("{}", e)))?
+}
+
+// 保存头像并返回访问地址
+pub async fn store_avatar(
+    resource_provider: &ResourceProviderShare,
+    file_name: &str,
+    content: Vec<u8>,
+) -> Result<String, ResponseError> {
+    let config = &GLOBAL_CONFIG.avatar;
+    match config.storage.as_str() {
+        "local" => {
+            let dir = Path::new(&config.local_dir);
+            tokio::fs::create_dir_all(dir).await.map_err(|e| {
+                ResponseError::unexpected_err("保存头像失败", &format!("创建目录失败: {}", e))
+            })?;
+            tokio::fs::write(dir.join(file_name), content)
+                .await
+                .map_err(|e| {
+                    ResponseError::unexpected_err("保存头像失败", &format!("写入文件失败: {}", e))
+                })?;
+        }
+        provider_id => {
+            let resource_path =
+                format!("{}/{}", config.remote_dir.trim_end_matches('/'), file_name);
+            resource_provider
+                .upload_file(provider_id, &resource_path, content)
+                .await?;
+        }
+    }
+    Ok(format!(
+        "{}/{}",
+        config.base_url.trim_end_matches('/'),
+        file_name
+    ))
+}
+
+fn process_avatar_blocking(content: &[u8]) -> Result<Vec<(u32, Vec<u8>)>, ResponseError> {
+    let config = &GLOBAL_CONFIG.avatar;
+    let mut reader = Reader::new(Cursor::new(content))
+        .with_guessed_format()
+        .map_err(|e| ResponseError::unexpected_err("处理头像失败", &format!("{}", e)))?;
+    match reader.format() {
+        Some(format) if ALLOWED_FORMATS.contains(&format) => {}
+        format => {
+            return Err(ResponseError::input_err(
+                "不支持该图片格式，请上传png、jpeg、gif或webp格式的图片",
+                &format!("上传的头像格式为{:?}", format),
+            ))
+        }
+    }
+    // 在解码前限制宽高，防止解压炸弹
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(config.max_dimension);
+    limits.max_image_height = Some(config.max_dimension);
+    reader.limits(limits);
+    let image = reader.decode().map_err(|e| {
+        ResponseError::input_err(
+            &format!(
+                "无法读取该图片，请确认图片完整且宽高不超过{}像素",
+                config.max_dimension
+            ),
+            &format!("解码头像失败: {}", e),
+        )
+    })?;
+
+    // 从中心裁剪为正方形
+    let side = image.width().min(image.height());
+    let square = image.crop_imm(
+        (image.width() - side) / 2,
+        (image.height() - side) / 2,
+        side,
+        side,
+    );
+    let mut outputs = Vec::new();
+    for size in config.sizes.iter() {
+        let mut buffer = Cursor::new(Vec::new());
+        square
+            .resize_exact(*size, *size, FilterType::Lanczos3)
+            .into_rgba8()
+            .write_to(&mut buffer, ImageOutputFormat::Png)
+            .map_err(|e| {
+                ResponseError::unexpected_err("处理头像失败", &format!("编码头像失败: {}", e))
+            })?;
+        outputs.push((*size, buffer.into_inner()));
+    }
+    Ok(outputs)
+}
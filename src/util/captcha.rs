@@ -0,0 +1,172 @@
+use jsonwebtoken::errors::ErrorKind;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::GLOBAL_CONFIG;
+use crate::error::ResponseError;
+use crate::model::captcha::CaptchaInput;
+use crate::util::{jwt::generate_token_id, jwt_key};
+
+const CAPTCHA_TOKEN_TYPE: &str = "captcha";
+
+lazy_static! {
+    // 已经使用过的题目ID -> 题目过期的时间，过期前同一道题不能被再次使用
+    static ref USED_CHALLENGES: Mutex<HashMap<String, u64>> = Mutex::new(HashMap::new());
+}
+
+// 题目本身就是签名过的jwt，服务端不需要保存未使用的题目
+#[derive(Debug, Serialize, Deserialize)]
+struct CaptchaClaims {
+    jti: String,
+    difficulty: u32,
+    typ: String,
+    iat: u64,
+    exp: u64,
+}
+
+// 生成一道新的工作量证明题目，返回题目、难度和过期时间
+// 客户端需要找到一个字符串solution，使sha256(challenge + ":" + solution)开头至少有difficulty个0比特
+pub fn generate_challenge() -> Result<(String, u32, u64), ResponseError> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let claims = CaptchaClaims {
+        jti: generate_token_id(),
+        difficulty: GLOBAL_CONFIG.captcha.difficulty,
+        typ: CAPTCHA_TOKEN_TYPE.to_string(),
+        iat: now,
+        exp: now + GLOBAL_CONFIG.captcha.expire,
+    };
+    let challenge = jwt_key::sign(&claims)?;
+    Ok((challenge, claims.difficulty, claims.exp))
+}
+
+// 校验题目的答案，每道题只能使用一次
+pub fn verify_solution(input: &CaptchaInput) -> Result<(), ResponseError> {
+    let token = jwt_key::verify::<CaptchaClaims>(&input.challenge).map_err(|e| match e.kind() {
+        &ErrorKind::ExpiredSignature => {
+            ResponseError::input_err("人机验证已过期，请重新验证", "captcha已过期")
+        }
+        _ => ResponseError::input_err(
+            "人机验证失败，请重新验证",
+            &format!("解码captcha错误，详细信息：{}", e),
+        ),
+    })?;
+    let claims = token.claims;
+    if claims.typ != CAPTCHA_TOKEN_TYPE {
+        return Err(ResponseError::input_err(
+            "人机验证失败，请重新验证",
+            &format!("凭证类型不正确: {}", claims.typ),
+        ));
+    }
+
+    if !check_solution(&input.challenge, &input.solution, claims.difficulty) {
+        return Err(ResponseError::input_err(
+            "人机验证失败，请重新验证",
+            &format!("captcha[{}]的答案不正确", claims.jti),
+        ));
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    if !claim_challenge(
+        &mut USED_CHALLENGES.lock().unwrap(),
+        &claims.jti,
+        claims.exp,
+        now,
+    ) {
+        return Err(ResponseError::input_err(
+            "人机验证已被使用，请重新验证",
+            &format!("captcha[{}]被重复使用", claims.jti),
+        ));
+    }
+    Ok(())
+}
+
+// sha256(challenge + ":" + solution)开头的0比特数是否达到难度
+fn check_solution(challenge: &str, solution: &str, difficulty: u32) -> bool {
+    let mut hasher = Sha256::new();
+    hasher.update(challenge.as_bytes());
+    hasher.update(b":");
+    hasher.update(solution.as_bytes());
+    leading_zero_bits(&hasher.finalize()) >= difficulty
+}
+
+// 记录题目已被使用，题目在过期前已被使用过时返回false
+fn claim_challenge(
+    used_challenges: &mut HashMap<String, u64>,
+    jti: &str,
+    exp: u64,
+    now: u64,
+) -> bool {
+    // 顺便清理已经过期的题目
+    used_challenges.retain(|_, exp| *exp > now);
+    if used_challenges.contains_key(jti) {
+        return false;
+    }
+    used_challenges.insert(jti.to_string(), exp);
+    true
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut count = 0;
+    for byte in hash {
+        if *byte == 0 {
+            count += 8;
+            continue;
+        }
+        count += byte.leading_zeros();
+        break;
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 暴力寻找一个满足难度的答案
+    fn solve(challenge: &str, difficulty: u32) -> String {
+        (0u64..)
+            .map(|v| v.to_string())
+            .find(|v| check_solution(challenge, v, difficulty))
+            .unwrap()
+    }
+
+    #[test]
+    fn leading_zero_bits_counts_across_bytes() {
+        assert_eq!(leading_zero_bits(&[0xff]), 0);
+        assert_eq!(leading_zero_bits(&[0x01, 0x00]), 7);
+        assert_eq!(leading_zero_bits(&[0x00, 0x00, 0x10]), 19);
+        assert_eq!(leading_zero_bits(&[0x00, 0x00]), 16);
+    }
+
+    #[test]
+    fn solution_must_reach_difficulty() {
+        let solution = solve("challenge", 12);
+        assert!(check_solution("challenge", &solution, 12));
+        // 答案与题目绑定，不能用于其他题目
+        assert!(!check_solution("other-challenge", &solution, 12));
+        assert!(check_solution("challenge", "anything", 0));
+        assert!(!check_solution("challenge", &solution, 257));
+    }
+
+    #[test]
+    fn challenge_can_only_be_claimed_once() {
+        let mut used = HashMap::new();
+        assert!(claim_challenge(&mut used, "a", 100, 10));
+        assert!(!claim_challenge(&mut used, "a", 100, 20));
+        assert!(claim_challenge(&mut used, "b", 100, 20));
+        // 过期的题目会被清理，过期后本身也无法通过签名校验
+        assert!(claim_challenge(&mut used, "c", 200, 100));
+        assert!(!used.contains_key("a"));
+        assert!(!used.contains_key("b"));
+    }
+}
@@ -6,9 +6,17 @@ use lettre::{
     AsyncTransport, Message,
 };
 
-use crate::email::EMailPool;
-use crate::error::ResponseError;
+use chrono::{DateTime, Duration, Utc};
+use deadpool_postgres::Client;
+use futures::future::{try_join, try_join5};
 use rand::Rng;
+use reqwest::Url;
+
+use crate::config::GLOBAL_CONFIG;
+use crate::db::Type as DBType;
+use crate::email::EMailPool;
+use crate::error::{is_db_zero_line_error, ResponseError};
+use crate::model::email::VerifyEmailType;
 
 pub fn generate_verify_code() -> String {
     const CHARSET: &[u8] = b"0123456789";
@@ -24,6 +32,37 @@ pub fn generate_verify_code() -> String {
     verify_code
 }
 
+// 登陆码可以直接换取凭证，使用比普通验证码更长的随机码
+pub fn generate_magic_login_code() -> String {
+    const CHARSET: &[u8] = b"ABCDEFGHJKMNPQRSTUVWXYZ23456789";
+    const CODE_LEN: usize = 8;
+    let mut rng = rand::thread_rng();
+
+    (0..CODE_LEN)
+        .map(|_| CHARSET[rng.gen_range(0..CHARSET.len())] as char)
+        .collect()
+}
+
+// 统一邮箱地址的格式，查询与写入验证邮件记录前都要经过这里，防止通过大小写或空白绕过频率限制
+pub fn normalize_addr(addr: &str) -> String {
+    addr.trim().to_lowercase()
+}
+
+// 生成登陆邮件中的一键登陆地址，未配置link_url时不生成
+pub fn magic_login_link(addr: &str, code: &str) -> Option<String> {
+    let link_url = &GLOBAL_CONFIG.magic_login.link_url;
+    if link_url.is_empty() {
+        return None;
+    }
+    match Url::parse_with_params(link_url, &[("email", addr), ("code", code)]) {
+        Ok(v) => Some(v.to_string()),
+        Err(e) => {
+            tracing::error!("登陆链接地址[{}]格式不正确: {}", link_url, e);
+            None
+        }
+    }
+}
+
 #[derive(Clone)]
 struct ListUnsubscribeHeader {}
 
@@ -57,3 +96,290 @@ pub async fn send_email(
     mail_pool.send(email).await?;
     Ok(())
 }
+
+// 发送验证邮件，返回验证邮件的id与生成时间
+// 会拒绝禁止列表中的域名并检查发送频率，最近的验证码仍然有效时沿用该验证码
+// user_id为发起修改邮箱的用户，其他类型的验证邮件为空
+pub async fn send_verify_email(
+    client: &mut Client,
+    email_pool: &EMailPool,
+    email_type: &VerifyEmailType,
+    email_addr: &str,
+    user_id: Option<i32>,
+    ip: &str,
+) -> Result<(i32, DateTime<Utc>), ResponseError> {
+    let config = &GLOBAL_CONFIG.verify_email;
+    let email_addr = &normalize_addr(email_addr);
+
+    // 拒绝一次性邮箱
+    let domain = match email_addr.rsplit_once('@') {
+        Some((_, v)) => v,
+        None => {
+            return Err(ResponseError::input_err(
+                "邮箱地址格式不正确",
+                &format!("[邮箱地址: {}]缺少@", email_addr),
+            ))
+        }
+    };
+    let denied = config.deny_domains.iter().any(|v| {
+        let v = v.to_ascii_lowercase();
+        domain == v || domain.ends_with(&format!(".{}", v))
+    });
+    if denied {
+        return Err(ResponseError::input_err(
+            "不支持该邮箱，请使用其他邮箱",
+            &format!("[邮箱地址: {}]域名在禁止列表中", email_addr),
+        ));
+    }
+
+    // 同一邮箱的发送请求依次执行，检查频率与记录本次发送之间不会插入其他请求
+    // 提交记录后再发送邮件，发送邮件期间不持有锁
+    let transaction = client.transaction().await?;
+    let (s1, s2, s3, s4, s5) = try_join5(
+        transaction.prepare_typed_cached(
+            "SELECT pg_advisory_xact_lock(hashtext('verify_email_send:' || $1))",
+            &[DBType::TEXT],
+        ),
+        // 统计邮箱与ip最近的发送记录
+        transaction.prepare_typed_cached(
+            "SELECT
+                (SELECT max(sent_at) FROM igame.verify_email_send WHERE addr = $1) AS last_sent_at,
+                a.count AS addr_count, a.first_sent_at AS addr_first_sent_at,
+                i.count AS ip_count, i.first_sent_at AS ip_first_sent_at
+            FROM
+                (SELECT count(*) AS count, min(sent_at) AS first_sent_at
+                FROM igame.verify_email_send
+                WHERE addr = $1 AND sent_at > now() - INTERVAL '1 day') AS a,
+                (SELECT count(*) AS count, min(sent_at) AS first_sent_at
+                FROM igame.verify_email_send
+                WHERE ip = $2 AND sent_at > now() - INTERVAL '1 day') AS i",
+            &[DBType::TEXT, DBType::TEXT],
+        ),
+        // 获取该用户最近的一次验证邮件，仍然有效时重新发送该验证码
+        transaction.prepare_typed_cached(
+            "SELECT id, code, used, attempts, created_at
+            FROM igame.verify_email
+            WHERE type = $1 AND addr = $2 AND user_id IS NOT DISTINCT FROM $3
+            ORDER BY created_at DESC
+            LIMIT 1",
+            &[DBType::INT2, DBType::TEXT, DBType::INT4],
+        ),
+        transaction.prepare_typed_cached(
+            "INSERT INTO igame.verify_email (type, addr, code, user_id) VALUES ($1, $2, $3, $4) RETURNING id, created_at",
+            &[DBType::INT2, DBType::TEXT, DBType::TEXT, DBType::INT4],
+        ),
+        // 记录本次发送
+        transaction.prepare_typed_cached(
+            "INSERT INTO igame.verify_email_send (verify_email_id, addr, ip) VALUES ($1, $2, $3) RETURNING id",
+            &[DBType::INT4, DBType::TEXT, DBType::TEXT],
+        ),
+    )
+    .await?;
+
+    // 检查发送频率
+    transaction.execute(&s1, &[&email_addr]).await?;
+    let r2 = transaction.query_one(&s2, &[&email_addr, &ip]).await?;
+    let now = Utc::now();
+    let last_sent_at: Option<DateTime<Utc>> = r2.get("last_sent_at");
+    if let Some(last_sent_at) = last_sent_at {
+        let elapsed = (now - last_sent_at).num_seconds();
+        if elapsed < config.resend_cooldown {
+            let retry_after = (config.resend_cooldown - elapsed) as u64;
+            return Err(ResponseError::rate_limited_err(
+                &format!("邮件发送过于频繁，请在{}秒后重试", retry_after),
+                retry_after,
+                &format!("[邮箱地址: {}]距离上次发送仅{}秒", email_addr, elapsed),
+            ));
+        }
+    }
+    let addr_count: i64 = r2.get("addr_count");
+    if addr_count >= config.daily_limit_per_addr {
+        let first_sent_at: DateTime<Utc> = r2.get("addr_first_sent_at");
+        let retry_after = (first_sent_at + Duration::days(1) - now)
+            .num_seconds()
+            .max(1) as u64;
+        return Err(ResponseError::rate_limited_err(
+            "该邮箱今天接收的验证邮件过多，请明天再试",
+            retry_after,
+            &format!("[邮箱地址: {}]24小时内已发送{}次", email_addr, addr_count),
+        ));
+    }
+    let ip_count: i64 = r2.get("ip_count");
+    if ip_count >= config.daily_limit_per_ip {
+        let first_sent_at: DateTime<Utc> = r2.get("ip_first_sent_at");
+        let retry_after = (first_sent_at + Duration::days(1) - now)
+            .num_seconds()
+            .max(1) as u64;
+        return Err(ResponseError::rate_limited_err(
+            "今天发送的验证邮件过多，请明天再试",
+            retry_after,
+            &format!("[ip: {}]24小时内已发送{}次", ip, ip_count),
+        ));
+    }
+
+    // 最近的验证码未使用、未输错过多且生成不久，则沿用该验证码
+    let reusable = match transaction
+        .query_opt(&s3, &[&email_type.to_int2(), &email_addr, &user_id])
+        .await?
+    {
+        Some(r3) => {
+            let used: bool = r3.get("used");
+            let attempts: i16 = r3.get("attempts");
+            let created_at: DateTime<Utc> = r3.get("created_at");
+            match !used
+                && attempts < config.max_attempts
+                && created_at > now - Duration::seconds(config.reuse_window)
+                && created_at > now - email_type.to_expire()
+            {
+                true => Some((
+                    r3.get::<_, i32>("id"),
+                    r3.get::<_, String>("code"),
+                    created_at,
+                )),
+                false => None,
+            }
+        }
+        None => None,
+    };
+    let verify_code = match &reusable {
+        Some((_, code, _)) => code.clone(),
+        None => match email_type {
+            VerifyEmailType::MagicLogin => generate_magic_login_code(),
+            _ => generate_verify_code(),
+        },
+    };
+
+    //添加verify_email记录
+    let (email_id, created_at) = match reusable {
+        Some((email_id, _, created_at)) => (email_id, created_at),
+        None => {
+            let r4 = transaction
+                .query_one(
+                    &s4,
+                    &[&email_type.to_int2(), &email_addr, &verify_code, &user_id],
+                )
+                .await?;
+            (r4.get("id"), r4.get("created_at"))
+        }
+    };
+    let r5 = transaction
+        .query_one(&s5, &[&email_id, &email_addr, &ip])
+        .await?;
+    let send_id: i32 = r5.get("id");
+    transaction.commit().await?;
+
+    //发送验证邮件
+    let subject = email_type.to_subject();
+    let link = match email_type {
+        VerifyEmailType::MagicLogin => magic_login_link(email_addr, &verify_code),
+        _ => None,
+    };
+    let html = email_type.to_html(&verify_code, link.as_deref());
+    if let Err(e) = send_email(
+        email_pool,
+        &GLOBAL_CONFIG.email.sender,
+        email_addr,
+        &subject,
+        &html,
+    )
+    .await
+    {
+        // 发送失败时删除本次发送记录，不计入发送次数
+        let s6 = client
+            .prepare_typed_cached(
+                "DELETE FROM igame.verify_email_send WHERE id = $1",
+                &[DBType::INT4],
+            )
+            .await?;
+        client.execute(&s6, &[&send_id]).await?;
+        return Err(e);
+    }
+
+    Ok((email_id, created_at))
+}
+
+// 校验邮箱最近一次收到的验证码，成功时返回该验证邮件的id
+// 验证码输错次数达到上限后即失效，需要重新发送邮件
+// 修改邮箱的验证码只能由发起修改的用户使用，user_id需要与发送时一致
+pub async fn check_verify_code(
+    client: &Client,
+    email_type: &VerifyEmailType,
+    addr: &str,
+    user_id: Option<i32>,
+    input_code: &str,
+) -> Result<i32, ResponseError> {
+    let addr = &normalize_addr(addr);
+    let (s1, s2) = try_join(
+        // 获取最近的一次验证邮件
+        client.prepare_typed_cached(
+            "SELECT id, code, used, attempts, created_at
+            FROM igame.verify_email
+            WHERE type = $1 AND addr = $2 AND user_id IS NOT DISTINCT FROM $3
+            ORDER BY created_at DESC
+            LIMIT 1",
+            &[DBType::INT2, DBType::TEXT, DBType::INT4],
+        ),
+        // 记录一次输错，达到上限时设置为已使用
+        client.prepare_typed_cached(
+            "UPDATE igame.verify_email
+            SET attempts = attempts + 1, used = (attempts + 1 >= $2)
+            WHERE id = $1
+            RETURNING attempts",
+            &[DBType::INT4, DBType::INT2],
+        ),
+    )
+    .await?;
+
+    let r1 = client
+        .query_one(&s1, &[&email_type.to_int2(), &addr, &user_id])
+        .await
+        .map_err(|e| match is_db_zero_line_error(&e) {
+            true => ResponseError::input_err(
+                "无法验证邮箱，请尝试重新发送邮件",
+                &format!(
+                    "[邮箱地址: {}]未找到对应的{}",
+                    addr,
+                    email_type.to_subject()
+                ),
+            ),
+            false => ResponseError::from(e),
+        })?;
+    let email_id: i32 = r1.get("id");
+    let code: &str = r1.get("code");
+    let used: bool = r1.get("used");
+    let attempts: i16 = r1.get("attempts");
+    let created_at: DateTime<Utc> = r1.get("created_at");
+    let max_attempts = GLOBAL_CONFIG.verify_email.max_attempts;
+    if used {
+        return Err(ResponseError::input_err(
+            "该验证码已失效，请尝试重新发送邮件",
+            &format!("[邮箱地址: {}]验证码已被使用或输错{}次", addr, attempts),
+        ));
+    }
+    if created_at < Utc::now() - email_type.to_expire() {
+        return Err(ResponseError::input_err(
+            "验证码已过期，请尝试重新发送邮件",
+            &format!("[邮箱地址: {}]验证码已过期", addr),
+        ));
+    }
+    if code != input_code {
+        let r2 = client.query_one(&s2, &[&email_id, &max_attempts]).await?;
+        let attempts: i16 = r2.get("attempts");
+        let internal_message = format!(
+            "[邮箱地址: {}]发送的验证码{}与记录值不匹配，已输错{}次",
+            addr, input_code, attempts
+        );
+        if attempts >= max_attempts {
+            return Err(ResponseError::input_err(
+                "验证码错误次数过多，该验证码已失效，请重新发送邮件",
+                &internal_message,
+            ));
+        }
+        return Err(ResponseError::input_err(
+            &format!("验证码错误，还可以尝试{}次", max_attempts - attempts),
+            &internal_message,
+        ));
+    }
+
+    Ok(email_id)
+}
@@ -1,49 +1,140 @@
+use actix_web::web;
+use argon2::password_hash::{
+    rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+};
+use argon2::{Algorithm, Argon2, Params, Version};
 use blake3::{Hash, Hasher};
-use rand::Rng;
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 
+use crate::config::GLOBAL_CONFIG;
 use crate::error::ResponseError;
 
-pub fn hash_password(password: &str) -> Vec<u8> {
-    let salt = &rand::thread_rng().gen::<[u8; 32]>();
+// 旧版密码格式: salt(32字节) || blake3(salt || password)(32字节)
+const LEGACY_PASSWORD_LEN: usize = 64;
+// 新版密码格式: argon2id的PHC字符串，例如 $argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>
+const ARGON2ID_PREFIX: &[u8] = b"$argon2id$";
 
-    let mut byted_password: Vec<u8> = Vec::new();
-    byted_password.extend_from_slice(salt);
-    byted_password.extend_from_slice(password.as_bytes());
-
-    let mut hasher = Hasher::new();
-    hasher.update(byted_password.as_slice());
-    let hash = hasher.finalize();
-    let hashed_password: &[u8; 32] = hash.as_bytes();
+fn new_argon2() -> Result<Argon2<'static>, ResponseError> {
+    let config = &GLOBAL_CONFIG.password;
+    let params = Params::new(
+        config.argon2_memory_cost,
+        config.argon2_time_cost,
+        config.argon2_parallelism,
+        None,
+    )
+    .map_err(|e| {
+        ResponseError::unexpected_err("生成密码失败", &format!("argon2参数错误: {}", e))
+    })?;
+    Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+}
 
-    let mut salted_password: Vec<u8> = Vec::new();
-    salted_password.extend_from_slice(salt);
-    salted_password.extend_from_slice(hashed_password);
-    salted_password
+fn hash_password_blocking(password: &str) -> Result<Vec<u8>, ResponseError> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hashed_password = new_argon2()?
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| ResponseError::unexpected_err("生成密码失败", &format!("{}", e)))?;
+    Ok(hashed_password.to_string().into_bytes())
 }
 
-pub fn compare_password(
+fn compare_password_blocking(
     input_password: &str,
-    salted_password: &Vec<u8>,
+    stored_password: &[u8],
 ) -> Result<bool, ResponseError> {
-    if salted_password.len() != 64 {
-        return Err(ResponseError::unexpected_err(
-            "比较密码失败",
-            "salted_password的长度应该等于64比特",
-        ));
+    if stored_password.starts_with(ARGON2ID_PREFIX) {
+        let phc = std::str::from_utf8(stored_password)
+            .map_err(|e| ResponseError::unexpected_err("比较密码失败", &format!("{}", e)))?;
+        let parsed_hash = PasswordHash::new(phc)
+            .map_err(|e| ResponseError::unexpected_err("比较密码失败", &format!("{}", e)))?;
+        // 校验时使用存储的参数，而不是当前配置的参数
+        return Ok(Argon2::default()
+            .verify_password(input_password.as_bytes(), &parsed_hash)
+            .is_ok());
+    }
+    if stored_password.len() == LEGACY_PASSWORD_LEN {
+        return Ok(compare_legacy_password(input_password, stored_password));
     }
+    Err(ResponseError::unexpected_err(
+        "比较密码失败",
+        "无法识别的密码格式",
+    ))
+}
+
+fn compare_legacy_password(input_password: &str, salted_password: &[u8]) -> bool {
     let (salt, right) = salted_password.split_at(32);
-    let hashed_password: [u8; 32] = right
-        .try_into()
-        .map_err(|e| ResponseError::unexpected_err("比较密码失败", &format!("{}", e)))?;
+    let hashed_password: [u8; 32] = right.try_into().unwrap();
     let hash2 = Hash::from(hashed_password);
 
     let mut hasher = Hasher::new();
-    let mut byted_password: Vec<u8> = Vec::new();
-    byted_password.extend_from_slice(salt);
-    byted_password.extend_from_slice(input_password.as_bytes());
-    hasher.update(byted_password.as_slice());
+    hasher.update(salt);
+    hasher.update(input_password.as_bytes());
     let hash1 = hasher.finalize();
 
-    Ok(hash1 == hash2)
+    hash1 == hash2
+}
+
+pub async fn hash_password(password: &str) -> Result<Vec<u8>, ResponseError> {
+    let password = password.to_string();
+    web::block(move || hash_password_blocking(&password))
+        .await
+        .map_err(|e| ResponseError::unexpected_err("生成密码失败", &format!("{}", e)))?
+}
+
+pub async fn compare_password(
+    input_password: &str,
+    stored_password: &[u8],
+) -> Result<bool, ResponseError> {
+    let input_password = input_password.to_string();
+    let stored_password = stored_password.to_vec();
+    web::block(move || compare_password_blocking(&input_password, &stored_password))
+        .await
+        .map_err(|e| ResponseError::unexpected_err("比较密码失败", &format!("{}", e)))?
+}
+
+// 检查密码是否符合密码策略：长度在限制内，同时包含字母与数字，且不能与邮箱相同
+pub fn check_password_policy(password: &str, email: &str) -> Result<(), ResponseError> {
+    let config = &GLOBAL_CONFIG.password;
+    let len = password.chars().count();
+    if len < config.min_len || len > config.max_len {
+        return Err(ResponseError::input_err(
+            &format!(
+                "密码长度需要在{}到{}个字符之间",
+                config.min_len, config.max_len
+            ),
+            &format!("密码长度为{}", len),
+        ));
+    }
+    let has_letter = password.chars().any(|c| c.is_alphabetic());
+    let has_digit = password.chars().any(|c| c.is_ascii_digit());
+    if !has_letter || !has_digit {
+        return Err(ResponseError::input_err(
+            "密码需要同时包含字母与数字",
+            "密码缺少字母或数字",
+        ));
+    }
+    if password.eq_ignore_ascii_case(email) {
+        return Err(ResponseError::input_err(
+            "密码不能与邮箱相同",
+            "密码与邮箱相同",
+        ));
+    }
+    Ok(())
+}
+
+// 判断存储的密码是否需要以当前配置重新生成，旧版blake3格式或argon2参数已变更时返回true
+pub fn need_rehash(stored_password: &[u8]) -> bool {
+    if !stored_password.starts_with(ARGON2ID_PREFIX) {
+        return true;
+    }
+    let phc = match std::str::from_utf8(stored_password) {
+        Ok(v) => v,
+        Err(_) => return true,
+    };
+    let params = match PasswordHash::new(phc).and_then(|h| Params::try_from(&h)) {
+        Ok(v) => v,
+        Err(_) => return true,
+    };
+    let config = &GLOBAL_CONFIG.password;
+    params.m_cost() != config.argon2_memory_cost
+        || params.t_cost() != config.argon2_time_cost
+        || params.p_cost() != config.argon2_parallelism
 }
@@ -1,15 +1,30 @@
-use jsonwebtoken::{
-    decode, encode, errors::ErrorKind, DecodingKey, EncodingKey, Header, Validation,
-};
+use jsonwebtoken::errors::ErrorKind;
+use lazy_static::lazy_static;
+use rand::Rng;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::RwLock;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::config::GLOBAL_CONFIG;
 use crate::error::ResponseError;
+use crate::util::jwt_key;
 
-#[derive(Debug, Serialize, Deserialize)]
+const ACCESS_TOKEN_TYPE: &str = "access";
+const REFRESH_TOKEN_TYPE: &str = "refresh";
+const TOTP_CHALLENGE_TOKEN_TYPE: &str = "totp_challenge";
+const OIDC_STATE_TOKEN_TYPE: &str = "oidc_state";
+
+lazy_static! {
+    // 已吊销的会话ID -> 该会话签发的access_token全部过期的时间
+    static ref REVOKED_SESSIONS: RwLock<HashMap<String, u64>> = RwLock::new(HashMap::new());
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AccessTokenClaims {
     pub user_id: i32,
+    pub sid: String,
+    typ: String,
     iat: u64,
     exp: u64,
 }
@@ -17,19 +32,44 @@ pub struct AccessTokenClaims {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RefreshTokenClaims {
     pub user_id: i32,
-    pub password: String,
+    pub jti: String,
+    typ: String,
+    iat: u64,
+    exp: u64,
+}
+
+// 密码验证通过但还需要完成两步验证时签发的临时凭证
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TotpChallengeClaims {
+    pub user_id: i32,
+    typ: String,
+    iat: u64,
+    exp: u64,
+}
+
+// 发起第三方登陆时签发给客户端保存的凭证，回调时用于校验state并取回PKCE的code_verifier
+// 该凭证只在客户端与后台之间传递，不会出现在跳转地址中
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OidcStateClaims {
+    pub provider: String,
+    pub jti: String,
+    pub nonce: String,
+    pub code_verifier: String,
+    typ: String,
     iat: u64,
     exp: u64,
 }
 
 impl AccessTokenClaims {
-    pub fn new(user_id: i32) -> Self {
+    pub fn new(user_id: i32, sid: &str) -> Self {
         let now = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_secs();
         Self {
             user_id,
+            sid: sid.to_string(),
+            typ: ACCESS_TOKEN_TYPE.to_string(),
             iat: now,
             exp: now + GLOBAL_CONFIG.jwt.access_token_expire,
         }
@@ -37,27 +77,93 @@ impl AccessTokenClaims {
 }
 
 impl RefreshTokenClaims {
-    pub fn new(user_id: i32, password: &str) -> Self {
+    pub fn new(user_id: i32, jti: &str) -> Self {
         let now = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_secs();
         Self {
             user_id,
-            password: password.to_string(),
+            jti: jti.to_string(),
+            typ: REFRESH_TOKEN_TYPE.to_string(),
             iat: now,
             exp: now + GLOBAL_CONFIG.jwt.refresh_token_expire,
         }
     }
 }
 
+impl TotpChallengeClaims {
+    pub fn new(user_id: i32) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        Self {
+            user_id,
+            typ: TOTP_CHALLENGE_TOKEN_TYPE.to_string(),
+            iat: now,
+            exp: now + GLOBAL_CONFIG.totp.challenge_expire,
+        }
+    }
+}
+
+impl OidcStateClaims {
+    pub fn new(provider: &str, code_verifier: &str) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        Self {
+            provider: provider.to_string(),
+            jti: generate_token_id(),
+            nonce: generate_token_id(),
+            code_verifier: code_verifier.to_string(),
+            typ: OIDC_STATE_TOKEN_TYPE.to_string(),
+            iat: now,
+            exp: now + GLOBAL_CONFIG.oidc.state_expire,
+        }
+    }
+}
+
+// 生成随机的凭证ID
+pub fn generate_token_id() -> String {
+    hex::encode(rand::thread_rng().gen::<[u8; 16]>())
+}
+
+// 将会话加入吊销列表，在access_token自然过期前拒绝该会话签发的所有access_token
+pub fn add_revoked_session(sid: &str, revoked_at: u64) {
+    add_revoked_sessions(vec![(sid.to_string(), revoked_at)]);
+}
+
+// 批量加入吊销列表，参数为(会话ID, 吊销时间)
+pub fn add_revoked_sessions(sessions: Vec<(String, u64)>) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let mut revoked_sessions = REVOKED_SESSIONS.write().unwrap();
+    // 顺便清理已经没有意义的记录
+    revoked_sessions.retain(|_, expire_at| *expire_at > now);
+    for (sid, revoked_at) in sessions {
+        let expire_at = revoked_at + GLOBAL_CONFIG.jwt.access_token_expire;
+        let entry = revoked_sessions.entry(sid).or_insert(expire_at);
+        *entry = (*entry).max(expire_at);
+    }
+}
+
+fn is_session_revoked(sid: &str) -> bool {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    match REVOKED_SESSIONS.read().unwrap().get(sid) {
+        Some(expire_at) => *expire_at > now,
+        None => false,
+    }
+}
+
 pub fn parse_access_token(jwt: &str) -> Result<AccessTokenClaims, ResponseError> {
-    let token = decode::<AccessTokenClaims>(
-        &jwt,
-        &DecodingKey::from_secret(GLOBAL_CONFIG.jwt.token_secret.as_bytes()),
-        &Validation::default(),
-    )
-    .map_err(|e| match e.kind() {
+    let token = jwt_key::verify::<AccessTokenClaims>(jwt).map_err(|e| match e.kind() {
         &ErrorKind::ExpiredSignature => {
             ResponseError::access_token_err("用户访问凭证已过期", "access_token已过期")
         }
@@ -66,44 +172,105 @@ pub fn parse_access_token(jwt: &str) -> Result<AccessTokenClaims, ResponseError>
             &format!("解码access_token错误，详细信息：{}", e),
         ),
     })?;
+    if token.claims.typ != ACCESS_TOKEN_TYPE {
+        return Err(ResponseError::access_token_err(
+            "解析用户访问凭证失败",
+            &format!("凭证类型不正确: {}", token.claims.typ),
+        ));
+    }
+    if is_session_revoked(&token.claims.sid) {
+        return Err(ResponseError::access_token_err(
+            "用户访问凭证已失效，请重新登陆",
+            &format!(
+                "[用户ID: {}]会话[{}]已被吊销",
+                token.claims.user_id, token.claims.sid
+            ),
+        ));
+    }
     Ok(token.claims)
 }
 
 pub fn parse_refresh_token(jwt: &str) -> Result<RefreshTokenClaims, ResponseError> {
-    let token = decode::<RefreshTokenClaims>(
-        &jwt,
-        &DecodingKey::from_secret(GLOBAL_CONFIG.jwt.token_secret.as_bytes()),
-        &Validation::default(),
-    )
-    .map_err(|e| match e.kind() {
+    let token = jwt_key::verify::<RefreshTokenClaims>(jwt).map_err(|e| match e.kind() {
         &ErrorKind::ExpiredSignature => {
             ResponseError::refresh_token_err("用户刷新凭证已过期", "refresh_token已过期")
         }
-        _ => ResponseError::access_token_err(
+        _ => ResponseError::refresh_token_err(
             "解析用户刷新凭证失败",
             &format!("解码refresh_token错误，详细信息：{}", e),
         ),
     })?;
+    if token.claims.typ != REFRESH_TOKEN_TYPE {
+        return Err(ResponseError::refresh_token_err(
+            "解析用户刷新凭证失败",
+            &format!("凭证类型不正确: {}", token.claims.typ),
+        ));
+    }
     Ok(token.claims)
 }
 
-pub fn generate_access_token(user_id: i32) -> Result<String, jsonwebtoken::errors::Error> {
-    let token = encode(
-        &Header::default(),
-        &AccessTokenClaims::new(user_id),
-        &EncodingKey::from_secret(GLOBAL_CONFIG.jwt.token_secret.as_bytes()),
-    )?;
+pub fn generate_access_token(
+    user_id: i32,
+    sid: &str,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let token = jwt_key::sign(&AccessTokenClaims::new(user_id, sid))?;
     Ok(token)
 }
 
 pub fn generate_refresh_token(
     user_id: i32,
-    password: &str,
+    jti: &str,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let token = jwt_key::sign(&RefreshTokenClaims::new(user_id, jti))?;
+    Ok(token)
+}
+
+pub fn parse_totp_challenge_token(jwt: &str) -> Result<TotpChallengeClaims, ResponseError> {
+    let token = jwt_key::verify::<TotpChallengeClaims>(jwt).map_err(|e| match e.kind() {
+        &ErrorKind::ExpiredSignature => {
+            ResponseError::access_token_err("两步验证已超时，请重新登陆", "challenge_token已过期")
+        }
+        _ => ResponseError::access_token_err(
+            "解析两步验证凭证失败",
+            &format!("解码challenge_token错误，详细信息：{}", e),
+        ),
+    })?;
+    if token.claims.typ != TOTP_CHALLENGE_TOKEN_TYPE {
+        return Err(ResponseError::access_token_err(
+            "解析两步验证凭证失败",
+            &format!("凭证类型不正确: {}", token.claims.typ),
+        ));
+    }
+    Ok(token.claims)
+}
+
+pub fn generate_totp_challenge_token(user_id: i32) -> Result<String, jsonwebtoken::errors::Error> {
+    let token = jwt_key::sign(&TotpChallengeClaims::new(user_id))?;
+    Ok(token)
+}
+
+pub fn generate_oidc_state_token(
+    claims: &OidcStateClaims,
 ) -> Result<String, jsonwebtoken::errors::Error> {
-    let token = encode(
-        &Header::default(),
-        &RefreshTokenClaims::new(user_id, password),
-        &EncodingKey::from_secret(GLOBAL_CONFIG.jwt.token_secret.as_bytes()),
-    )?;
+    let token = jwt_key::sign(claims)?;
     Ok(token)
 }
+
+pub fn parse_oidc_state_token(jwt: &str) -> Result<OidcStateClaims, ResponseError> {
+    let token = jwt_key::verify::<OidcStateClaims>(jwt).map_err(|e| match e.kind() {
+        &ErrorKind::ExpiredSignature => {
+            ResponseError::input_err("第三方登陆已超时，请重新登陆", "state_token已过期")
+        }
+        _ => ResponseError::input_err(
+            "第三方登陆已失效，请重新登陆",
+            &format!("解码state_token错误，详细信息：{}", e),
+        ),
+    })?;
+    if token.claims.typ != OIDC_STATE_TOKEN_TYPE {
+        return Err(ResponseError::input_err(
+            "第三方登陆已失效，请重新登陆",
+            &format!("凭证类型不正确: {}", token.claims.typ),
+        ));
+    }
+    Ok(token.claims)
+}
@@ -0,0 +1,368 @@
+use data_encoding::{BASE64, BASE64URL_NOPAD};
+use jsonwebtoken::{
+    decode, decode_header, encode,
+    errors::{Error, ErrorKind},
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, OctetKeyPairParameters,
+        OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+    },
+    Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation,
+};
+use lazy_static::lazy_static;
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+
+use crate::config::{JwtKeyAlgorithm, JwtKeyConfig, GLOBAL_CONFIG};
+
+// Ed25519公钥的SPKI格式是固定的12字节前缀加32字节公钥
+const ED25519_SPKI_PREFIX: &[u8] = &[
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+lazy_static! {
+    static ref KEY_STORE: KeyStore = KeyStore::load();
+}
+
+struct VerificationKey {
+    algorithm: Algorithm,
+    decoding_key: DecodingKey,
+}
+
+// 签发与校验凭证使用的全部密钥，启动时从配置文件中加载
+struct KeyStore {
+    header: Header,
+    encoding_key: EncodingKey,
+    // 没有kid的凭证使用token_secret校验
+    secret_key: Option<DecodingKey>,
+    keys: HashMap<String, VerificationKey>,
+    jwk_set: JwkSet,
+}
+
+impl KeyStore {
+    fn load() -> Self {
+        let config = &GLOBAL_CONFIG.jwt;
+        let mut keys = HashMap::new();
+        let mut jwk_set = JwkSet { keys: Vec::new() };
+        for key_config in config
+            .signing_key
+            .iter()
+            .chain(config.verification_keys.iter())
+        {
+            let jwk = load_public_jwk(key_config);
+            let decoding_key = DecodingKey::from_jwk(&jwk)
+                .unwrap_or_else(|e| panic!("无法使用JWT公钥[{}]: {}", key_config.kid, e));
+            let old = keys.insert(
+                key_config.kid.clone(),
+                VerificationKey {
+                    algorithm: to_algorithm(key_config.algorithm),
+                    decoding_key,
+                },
+            );
+            if old.is_some() {
+                panic!("JWT密钥的kid[{}]重复", key_config.kid);
+            }
+            jwk_set.keys.push(jwk);
+        }
+
+        let (header, encoding_key) = match &config.signing_key {
+            Some(key_config) => {
+                let path = key_config
+                    .private_key_path
+                    .as_ref()
+                    .unwrap_or_else(|| panic!("JWT签名密钥[{}]缺少私钥", key_config.kid));
+                let pem = read_key_file(path);
+                let encoding_key = match key_config.algorithm {
+                    JwtKeyAlgorithm::EdDSA => EncodingKey::from_ed_pem(&pem),
+                    JwtKeyAlgorithm::RS256 => EncodingKey::from_rsa_pem(&pem),
+                }
+                .unwrap_or_else(|e| panic!("无法读取JWT私钥[{}]: {}", path, e));
+                let mut header = Header::new(to_algorithm(key_config.algorithm));
+                header.kid = Some(key_config.kid.clone());
+                (header, encoding_key)
+            }
+            None => (
+                Header::default(),
+                EncodingKey::from_secret(config.token_secret.as_bytes()),
+            ),
+        };
+        let accept_token_secret = config
+            .accept_token_secret
+            .unwrap_or_else(|| config.signing_key.is_none());
+        let secret_key = match accept_token_secret {
+            true => Some(DecodingKey::from_secret(config.token_secret.as_bytes())),
+            false => None,
+        };
+
+        Self {
+            header,
+            encoding_key,
+            secret_key,
+            keys,
+            jwk_set,
+        }
+    }
+}
+
+// 启动时加载密钥，配置错误时尽早退出
+pub fn load_keys() {
+    lazy_static::initialize(&KEY_STORE);
+    tracing::info!(
+        "加载了{}个JWT公钥，签名算法: {:?}",
+        KEY_STORE.keys.len(),
+        KEY_STORE.header.alg
+    );
+    if GLOBAL_CONFIG.jwt.signing_key.is_some() && KEY_STORE.secret_key.is_some() {
+        tracing::warn!("已配置JWT签名密钥但仍然接受token_secret签名的凭证，旧凭证过期后应关闭accept_token_secret");
+    }
+}
+
+// 使用当前的签名密钥签发凭证
+pub fn sign<T: Serialize>(claims: &T) -> Result<String, Error> {
+    encode(&KEY_STORE.header, claims, &KEY_STORE.encoding_key)
+}
+
+// 按照凭证头部的kid选择密钥校验凭证，没有kid时使用token_secret
+pub fn verify<T: DeserializeOwned>(token: &str) -> Result<TokenData<T>, Error> {
+    let header = decode_header(token)?;
+    match header.kid {
+        Some(kid) => {
+            let key = KEY_STORE
+                .keys
+                .get(&kid)
+                .ok_or_else(|| Error::from(ErrorKind::InvalidKeyFormat))?;
+            decode(token, &key.decoding_key, &Validation::new(key.algorithm))
+        }
+        None => match &KEY_STORE.secret_key {
+            Some(secret_key) => decode(token, secret_key, &Validation::default()),
+            None => Err(ErrorKind::InvalidAlgorithm.into()),
+        },
+    }
+}
+
+// 所有可用于校验凭证的公钥，供其他服务获取
+pub fn jwk_set() -> &'static JwkSet {
+    &KEY_STORE.jwk_set
+}
+
+fn to_algorithm(algorithm: JwtKeyAlgorithm) -> Algorithm {
+    match algorithm {
+        JwtKeyAlgorithm::EdDSA => Algorithm::EdDSA,
+        JwtKeyAlgorithm::RS256 => Algorithm::RS256,
+    }
+}
+
+fn read_key_file(path: &str) -> Vec<u8> {
+    std::fs::read(path).unwrap_or_else(|e| panic!("无法读取JWT密钥文件[{}]: {}", path, e))
+}
+
+fn load_public_jwk(key_config: &JwtKeyConfig) -> Jwk {
+    let path = &key_config.public_key_path;
+    let pem = String::from_utf8(read_key_file(path))
+        .unwrap_or_else(|_| panic!("JWT公钥[{}]不是PEM格式", path));
+    public_jwk(key_config, &pem)
+}
+
+// 从PEM格式的公钥生成JWK
+fn public_jwk(key_config: &JwtKeyConfig, pem: &str) -> Jwk {
+    let path = &key_config.public_key_path;
+    let der = pem_to_der(pem).unwrap_or_else(|| panic!("JWT公钥[{}]不是PEM格式", path));
+    let common = CommonParameters {
+        public_key_use: Some(PublicKeyUse::Signature),
+        algorithm: Some(to_algorithm(key_config.algorithm)),
+        key_id: Some(key_config.kid.clone()),
+        ..Default::default()
+    };
+    let algorithm = match key_config.algorithm {
+        JwtKeyAlgorithm::EdDSA => {
+            if der.len() != ED25519_SPKI_PREFIX.len() + 32 || !der.starts_with(ED25519_SPKI_PREFIX)
+            {
+                panic!("JWT公钥[{}]不是Ed25519公钥", path);
+            }
+            AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair,
+                curve: EllipticCurve::Ed25519,
+                x: BASE64URL_NOPAD.encode(&der[ED25519_SPKI_PREFIX.len()..]),
+            })
+        }
+        JwtKeyAlgorithm::RS256 => {
+            let (n, e) = parse_rsa_public_key(&der)
+                .unwrap_or_else(|| panic!("JWT公钥[{}]不是RSA公钥", path));
+            AlgorithmParameters::RSA(RSAKeyParameters {
+                key_type: RSAKeyType::RSA,
+                n: BASE64URL_NOPAD.encode(n),
+                e: BASE64URL_NOPAD.encode(e),
+            })
+        }
+    };
+    Jwk { common, algorithm }
+}
+
+fn pem_to_der(pem: &str) -> Option<Vec<u8>> {
+    let body: String = pem
+        .lines()
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty() && !v.starts_with("-----"))
+        .collect();
+    BASE64.decode(body.as_bytes()).ok()
+}
+
+// 解析SPKI或PKCS#1格式的RSA公钥，返回去掉前导0的模数与指数
+fn parse_rsa_public_key(der: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (tag, content, _) = read_der(der)?;
+    if tag != 0x30 {
+        return None;
+    }
+    let (tag, first, rest) = read_der(content)?;
+    let (n, e) = match tag {
+        // PKCS#1: SEQUENCE { INTEGER n, INTEGER e }
+        0x02 => (first, read_der(rest)?),
+        // SPKI: SEQUENCE { SEQUENCE { 算法 }, BIT STRING { PKCS#1 } }
+        0x30 => {
+            let (tag, bit_string, _) = read_der(rest)?;
+            if tag != 0x03 || bit_string.first() != Some(&0) {
+                return None;
+            }
+            return parse_rsa_public_key(&bit_string[1..]);
+        }
+        _ => return None,
+    };
+    if e.0 != 0x02 {
+        return None;
+    }
+    Some((trim_leading_zero(n), trim_leading_zero(e.1)))
+}
+
+// 读取一个DER编码的TLV，返回标签、内容与剩余的字节
+fn read_der(der: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let tag = *der.first()?;
+    let first_len = *der.get(1)? as usize;
+    let (len, offset) = match first_len {
+        0..=0x7f => (first_len, 2),
+        0x81..=0x84 => {
+            let count = first_len - 0x80;
+            let len = der
+                .get(2..2 + count)?
+                .iter()
+                .fold(0usize, |acc, v| (acc << 8) | *v as usize);
+            (len, 2 + count)
+        }
+        _ => return None,
+    };
+    let content = der.get(offset..offset + len)?;
+    Some((tag, content, &der[offset + len..]))
+}
+
+fn trim_leading_zero(v: &[u8]) -> &[u8] {
+    match v {
+        [0, rest @ ..] if !rest.is_empty() => rest,
+        _ => v,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 同一个RSA密钥的SPKI与PKCS#1格式公钥，以及一个Ed25519公钥
+    const RSA_SPKI_PEM: &str = "-----BEGIN PUBLIC KEY-----\n\
+MIIBIjANBgkqhkiG9w0BAQEFAAOCAQ8AMIIBCgKCAQEAn6JQhLe19KpX/IcX8BN3\n\
+soEmYRO7dzU9rkbyCzScTmb8bM8BdZY798z9+YXG1crZkl1S5614rcZMJ8WKiOBX\n\
+EwMVVeJmiGFSTF/JLSzIYYTg8T7JfaFosb5NL0SNUsSJ2hRHgLRM/ONTk27e0I8q\n\
+593G+H6XI0n3ctwNugRZxP3nSVcAvKQUtXoUX3TRcvYx0J14yzQO0eI7izJ2Bn+G\n\
+yFDE90Sx3/RPIvnyaWBWtr/ui/tyULGWl3dDjSXklriuAAB9QiYR1zajJe25pZ6X\n\
+/um3uf7IkeqnOcRaOAD/DsO1+wnNO5NqSjLdZSgx2tdNLEhAINK+1Q0SDOJKB4ch\n\
+aQIDAQAB\n\
+-----END PUBLIC KEY-----";
+    const RSA_PKCS1_PEM: &str = "-----BEGIN RSA PUBLIC KEY-----\n\
+MIIBCgKCAQEAn6JQhLe19KpX/IcX8BN3soEmYRO7dzU9rkbyCzScTmb8bM8BdZY7\n\
+98z9+YXG1crZkl1S5614rcZMJ8WKiOBXEwMVVeJmiGFSTF/JLSzIYYTg8T7JfaFo\n\
+sb5NL0SNUsSJ2hRHgLRM/ONTk27e0I8q593G+H6XI0n3ctwNugRZxP3nSVcAvKQU\n\
+tXoUX3TRcvYx0J14yzQO0eI7izJ2Bn+GyFDE90Sx3/RPIvnyaWBWtr/ui/tyULGW\n\
+l3dDjSXklriuAAB9QiYR1zajJe25pZ6X/um3uf7IkeqnOcRaOAD/DsO1+wnNO5Nq\n\
+SjLdZSgx2tdNLEhAINK+1Q0SDOJKB4chaQIDAQAB\n\
+-----END RSA PUBLIC KEY-----";
+    const ED25519_PEM: &str = "-----BEGIN PUBLIC KEY-----\n\
+MCowBQYDK2VwAyEAwbf3ssg/iJFW427OeciHltiPD6e7HToEGg+bLiQk59Q=\n\
+-----END PUBLIC KEY-----";
+    const RSA_N: &str = "n6JQhLe19KpX_IcX8BN3soEmYRO7dzU9rkbyCzScTmb8bM8BdZY798z9-YXG1crZkl1S5614rcZMJ8WKiOBXEwMVVeJmiGFSTF_JLSzIYYTg8T7JfaFosb5NL0SNUsSJ2hRHgLRM_ONTk27e0I8q593G-H6XI0n3ctwNugRZxP3nSVcAvKQUtXoUX3TRcvYx0J14yzQO0eI7izJ2Bn-GyFDE90Sx3_RPIvnyaWBWtr_ui_tyULGWl3dDjSXklriuAAB9QiYR1zajJe25pZ6X_um3uf7IkeqnOcRaOAD_DsO1-wnNO5NqSjLdZSgx2tdNLEhAINK-1Q0SDOJKB4chaQ";
+    const RSA_E: &str = "AQAB";
+    const ED25519_X: &str = "wbf3ssg_iJFW427OeciHltiPD6e7HToEGg-bLiQk59Q";
+
+    fn key_config(algorithm: JwtKeyAlgorithm) -> JwtKeyConfig {
+        JwtKeyConfig {
+            kid: "test".to_string(),
+            algorithm,
+            public_key_path: "test.pem".to_string(),
+            private_key_path: None,
+        }
+    }
+
+    fn rsa_components(pem: &str) -> (String, String) {
+        let der = pem_to_der(pem).unwrap();
+        let (n, e) = parse_rsa_public_key(&der).unwrap();
+        (BASE64URL_NOPAD.encode(n), BASE64URL_NOPAD.encode(e))
+    }
+
+    #[test]
+    fn parse_rsa_spki_public_key() {
+        assert_eq!(
+            rsa_components(RSA_SPKI_PEM),
+            (RSA_N.to_string(), RSA_E.to_string())
+        );
+    }
+
+    #[test]
+    fn parse_rsa_pkcs1_public_key() {
+        assert_eq!(
+            rsa_components(RSA_PKCS1_PEM),
+            (RSA_N.to_string(), RSA_E.to_string())
+        );
+    }
+
+    #[test]
+    fn parse_rsa_rejects_ed25519_key() {
+        let der = pem_to_der(ED25519_PEM).unwrap();
+        assert!(parse_rsa_public_key(&der).is_none());
+    }
+
+    #[test]
+    fn read_der_lengths() {
+        assert_eq!(
+            read_der(&[0x02, 0x01, 0x05, 0xff]),
+            Some((0x02, &[0x05][..], &[0xff][..]))
+        );
+        let mut long = vec![0x04, 0x81, 0x80];
+        long.extend_from_slice(&[0xaa; 0x80]);
+        let (tag, content, rest) = read_der(&long).unwrap();
+        assert_eq!((tag, content.len(), rest.len()), (0x04, 0x80, 0));
+        // 长度超出剩余字节或使用不定长编码时拒绝
+        assert_eq!(read_der(&[0x02, 0x02, 0x05]), None);
+        assert_eq!(read_der(&[0x30, 0x80, 0x00, 0x00]), None);
+        assert_eq!(read_der(&[0x30]), None);
+    }
+
+    #[test]
+    fn rsa_public_jwk() {
+        let jwk = public_jwk(&key_config(JwtKeyAlgorithm::RS256), RSA_SPKI_PEM);
+        assert_eq!(jwk.common.key_id.as_deref(), Some("test"));
+        match jwk.algorithm {
+            AlgorithmParameters::RSA(v) => assert_eq!((v.n.as_str(), v.e.as_str()), (RSA_N, RSA_E)),
+            _ => panic!("不是RSA公钥"),
+        }
+    }
+
+    #[test]
+    fn ed25519_public_jwk() {
+        let jwk = public_jwk(&key_config(JwtKeyAlgorithm::EdDSA), ED25519_PEM);
+        match jwk.algorithm {
+            AlgorithmParameters::OctetKeyPair(v) => assert_eq!(v.x, ED25519_X),
+            _ => panic!("不是Ed25519公钥"),
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn ed25519_public_jwk_rejects_rsa_key() {
+        public_jwk(&key_config(JwtKeyAlgorithm::EdDSA), RSA_SPKI_PEM);
+    }
+}
@@ -0,0 +1,117 @@
+use deadpool_postgres::{Client, Transaction};
+use derive_more::Display;
+
+use crate::db::Type as DBType;
+use crate::error::ResponseError;
+
+// igame.ledger中的reason，另外期初流水opening在迁移时写入，注销清零account_purge在清除账号时写入
+#[derive(Debug, Display, Clone, Copy)]
+pub enum LedgerReason {
+    #[display(fmt = "daily_bonus")]
+    DailyBonus,
+    #[display(fmt = "resource_download")]
+    ResourceDownload,
+    #[display(fmt = "vip_purchase")]
+    VipPurchase,
+}
+
+// 变化后的余额
+#[derive(Debug, Clone, Copy)]
+pub struct Balance {
+    pub coin: i32,
+    pub exp: i32,
+}
+
+// 余额与流水之和不一致的用户
+#[derive(Debug)]
+pub struct Drift {
+    pub user_id: i32,
+    pub coin: i32,
+    pub ledger_coin: i64,
+    pub exp: i32,
+    pub ledger_exp: i64,
+}
+
+// 修改用户的无限币与经验值，并为每个不为0的变化写入一条流水，所有余额修改都要通过这里
+// 无限币余额不足时不做任何修改并返回None，ref_id为关联记录的ID
+pub async fn apply(
+    transaction: &Transaction<'_>,
+    user_id: i32,
+    coin_delta: i32,
+    exp_delta: i32,
+    reason: LedgerReason,
+    ref_id: Option<i32>,
+) -> Result<Option<Balance>, tokio_postgres::Error> {
+    let s1 = transaction
+        .prepare_typed_cached(
+            "WITH
+            u AS (
+                UPDATE igame.user
+                SET coin = coin + $2, exp = exp + $3
+                WHERE id = $1 AND coin + $2 >= 0
+                RETURNING coin, exp
+            ),
+            l AS (
+                INSERT INTO igame.ledger (user_id, currency, delta, balance, reason, ref_id)
+                SELECT $1, 'coin', $2, coin, $4, $5 FROM u WHERE $2 <> 0
+                UNION ALL
+                SELECT $1, 'exp', $3, exp, $4, $5 FROM u WHERE $3 <> 0
+            )
+            SELECT coin, exp FROM u",
+            &[
+                DBType::INT4,
+                DBType::INT4,
+                DBType::INT4,
+                DBType::TEXT,
+                DBType::INT4,
+            ],
+        )
+        .await?;
+    let r1 = transaction
+        .query_opt(
+            &s1,
+            &[
+                &user_id,
+                &coin_delta,
+                &exp_delta,
+                &reason.to_string(),
+                &ref_id,
+            ],
+        )
+        .await?;
+    Ok(r1.map(|r1| Balance {
+        coin: r1.get("coin"),
+        exp: r1.get("exp"),
+    }))
+}
+
+// 检查每个用户的余额是否等于流水之和，返回不一致的用户
+pub async fn reconcile(client: &Client) -> Result<Vec<Drift>, ResponseError> {
+    let s1 = client
+        .prepare_typed_cached(
+            "SELECT u.id, u.coin, u.exp, COALESCE(l.coin, 0) AS ledger_coin, COALESCE(l.exp, 0) AS ledger_exp
+            FROM igame.user AS u
+            LEFT JOIN (
+                SELECT user_id,
+                    SUM(delta) FILTER (WHERE currency = 'coin') AS coin,
+                    SUM(delta) FILTER (WHERE currency = 'exp') AS exp
+                FROM igame.ledger
+                GROUP BY user_id
+            ) AS l
+            ON u.id = l.user_id
+            WHERE u.coin <> COALESCE(l.coin, 0) OR u.exp <> COALESCE(l.exp, 0)",
+            &[],
+        )
+        .await?;
+    let r1s = client.query(&s1, &[]).await?;
+    Ok(r1s
+        .iter()
+        .map(|r1| Drift {
+            user_id: r1.get("id"),
+            coin: r1.get("coin"),
+            ledger_coin: r1.get("ledger_coin"),
+            exp: r1.get("exp"),
+            ledger_exp: r1.get("ledger_exp"),
+        })
+        .collect())
+}
@@ -1,5 +1,16 @@
+pub mod account;
+pub mod api_key;
+pub mod avatar;
+pub mod captcha;
 pub mod email;
 pub mod hash;
 pub mod jwt;
+pub mod jwt_key;
+pub mod ledger;
+pub mod permission;
+pub mod profile;
 pub mod req_parse;
+pub mod role;
 pub mod serde_fn;
+pub mod session;
+pub mod totp;
@@ -0,0 +1,184 @@
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+use deadpool_postgres::{Client, Pool};
+use futures::future::LocalBoxFuture;
+use lazy_static::lazy_static;
+use std::collections::{HashMap, HashSet};
+use std::marker::PhantomData;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::GLOBAL_CONFIG;
+use crate::db::Type as DBType;
+use crate::error::ResponseError;
+use crate::model::role::Permission;
+use crate::util::{req_parse::AuthUser, totp};
+
+// 超过该数量后清理已经过期的缓存
+const PRUNE_THRESHOLD: usize = 10000;
+
+lazy_static! {
+    // 用户ID -> (查询时间, 用户角色拥有的权限)
+    static ref PERMISSION_CACHE: Mutex<HashMap<i32, (Instant, PermissionSet)>> =
+        Mutex::new(HashMap::new());
+}
+
+// 用户在当前请求中实际可以使用的权限
+#[derive(Debug, Clone, Default)]
+pub struct PermissionSet(HashSet<Permission>);
+
+impl PermissionSet {
+    pub fn contains(&self, permission: Permission) -> bool {
+        self.0.contains(&permission)
+    }
+}
+
+// 要求当前用户拥有某个权限的extractor，例如RequirePermission<require::CreateNotice>
+// 未登陆时返回access_token_err，没有权限时返回permission_err
+pub struct RequirePermission<P: RequiredPermission> {
+    pub auth_user: AuthUser,
+    _permission: PhantomData<P>,
+}
+
+pub trait RequiredPermission {
+    const PERMISSION: Permission;
+    // 没有权限时返回给用户的错误信息
+    const DENIED_MESSAGE: &'static str;
+}
+
+// 为每个权限生成一个用于RequirePermission的类型
+macro_rules! required_permissions {
+    ($($name:ident => $message:expr),* $(,)?) => {
+        pub mod require {
+            use super::RequiredPermission;
+            use crate::model::role::Permission;
+
+            $(
+                pub struct $name;
+
+                impl RequiredPermission for $name {
+                    const PERMISSION: Permission = Permission::$name;
+                    const DENIED_MESSAGE: &'static str = $message;
+                }
+            )*
+        }
+    };
+}
+
+required_permissions!(
+    GetUser => "获取用户信息失败，没有对应权限",
+    CreateUser => "创建用户失败，没有对应权限",
+    SendEmail => "发送email失败，没有对应权限",
+    CreateNotice => "创建通知失败，没有对应权限",
+    FreeDownload => "没有免费下载的权限",
+    FreeInstall => "没有免费安装的权限",
+    IgnoreExp => "没有无视等级限制的权限",
+    ManageRole => "管理角色失败，没有对应权限",
+);
+
+impl<P: RequiredPermission + 'static> FromRequest for RequirePermission<P> {
+    type Error = ResponseError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        let auth_user = AuthUser::from_request(&req, payload);
+        Box::pin(async move {
+            let auth_user = auth_user.await?;
+            let db_pool = req.app_data::<web::Data<Pool>>().ok_or_else(|| {
+                ResponseError::unexpected_err("检查权限失败", "找不到数据库连接池")
+            })?;
+            let client: Client = db_pool.get().await?;
+            let permissions = get_permissions(&req, &client, &auth_user).await?;
+            if !permissions.contains(P::PERMISSION) {
+                return Err(ResponseError::permission_err(
+                    P::DENIED_MESSAGE,
+                    &format!("[用户ID: {}]没有{}权限", auth_user.user_id, P::PERMISSION),
+                ));
+            }
+            Ok(Self {
+                auth_user,
+                _permission: PhantomData,
+            })
+        })
+    }
+}
+
+// 获取当前用户实际可以使用的权限，结果缓存在请求的extensions中
+// API密钥只能使用创建时选择的权限
+pub async fn get_permissions(
+    req: &HttpRequest,
+    client: &Client,
+    auth_user: &AuthUser,
+) -> Result<PermissionSet, ResponseError> {
+    if let Some(permissions) = req.extensions().get::<PermissionSet>() {
+        return Ok(permissions.clone());
+    }
+    let role_permissions = load_role_permissions(client, auth_user.user_id).await?;
+    let permissions = PermissionSet(
+        role_permissions
+            .0
+            .into_iter()
+            .filter(|v| auth_user.has_scope(*v))
+            .collect(),
+    );
+    req.extensions_mut().insert(permissions.clone());
+    Ok(permissions)
+}
+
+// 修改用户角色后调用，使进程内缓存的权限立即失效
+pub fn invalidate(user_id: i32) {
+    PERMISSION_CACHE.lock().unwrap().remove(&user_id);
+}
+
+// 修改角色拥有的权限后调用，清空所有用户的缓存
+pub fn invalidate_all() {
+    PERMISSION_CACHE.lock().unwrap().clear();
+}
+
+// 查询用户所有未过期的角色拥有的权限，开启了cache_ttl时使用进程内缓存
+async fn load_role_permissions(
+    client: &Client,
+    user_id: i32,
+) -> Result<PermissionSet, ResponseError> {
+    let ttl = Duration::from_secs(GLOBAL_CONFIG.permission.cache_ttl);
+    if !ttl.is_zero() {
+        if let Some((loaded_at, permissions)) = PERMISSION_CACHE.lock().unwrap().get(&user_id) {
+            if loaded_at.elapsed() < ttl {
+                return Ok(permissions.clone());
+            }
+        }
+    }
+
+    let s1 = client
+        .prepare_typed_cached(
+            &format!(
+                "SELECT DISTINCT p.key
+                FROM igame.user_role AS ur
+                INNER JOIN igame.role_permission AS rp
+                ON ur.role_id = rp.role_id
+                INNER JOIN igame.permission AS p
+                ON rp.permission_id = p.id
+                WHERE ur.user_id = $1
+                AND (ur.expire_at IS NULL OR ur.expire_at > now())
+                {}",
+                totp::admin_role_filter()
+            ),
+            &[DBType::INT4],
+        )
+        .await?;
+    let r1s = client.query(&s1, &[&user_id]).await?;
+    let permissions = PermissionSet(
+        r1s.iter()
+            .filter_map(|r1| Permission::from_key(r1.get("key")))
+            .collect(),
+    );
+
+    if !ttl.is_zero() {
+        let mut cache = PERMISSION_CACHE.lock().unwrap();
+        if cache.len() > PRUNE_THRESHOLD {
+            cache.retain(|_, (loaded_at, _)| loaded_at.elapsed() < ttl);
+        }
+        cache.insert(user_id, (Instant::now(), permissions.clone()));
+    }
+    Ok(permissions)
+}
@@ -0,0 +1,193 @@
+use reqwest::Url;
+
+use crate::config::{ProfileConfig, GLOBAL_CONFIG};
+use crate::error::ResponseError;
+
+const AVATAR_URL_MAX_LEN: usize = 512;
+// 容易被用来伪装或注入的字符
+const FORBIDDEN_CHARS: &[char] = &['<', '>', '&', '"', '\'', '/', '\\', '@', '`'];
+
+// 校验并规范化昵称，返回去掉首尾空白后的昵称
+pub fn check_nick_name(nick_name: &str) -> Result<String, ResponseError> {
+    check_nick_name_with_config(&GLOBAL_CONFIG.profile, nick_name)
+}
+
+fn check_nick_name_with_config(
+    config: &ProfileConfig,
+    nick_name: &str,
+) -> Result<String, ResponseError> {
+    let nick_name = nick_name.trim();
+
+    let len = nick_name.chars().count();
+    if len < config.nick_name_min_len || len > config.nick_name_max_len {
+        return Err(ResponseError::input_err(
+            &format!(
+                "昵称长度需要在{}到{}个字符之间",
+                config.nick_name_min_len, config.nick_name_max_len
+            ),
+            &format!("[昵称: {}]长度为{}", nick_name, len),
+        ));
+    }
+    // 控制字符、零宽字符与除普通空格外的空白字符都不允许出现
+    let forbidden = nick_name.chars().find(|c| {
+        c.is_control()
+            || (c.is_whitespace() && *c != ' ')
+            || ('\u{200b}'..='\u{200f}').contains(c)
+            || *c == '\u{feff}'
+            || FORBIDDEN_CHARS.contains(c)
+    });
+    if let Some(c) = forbidden {
+        return Err(ResponseError::input_err(
+            "昵称中包含不允许使用的字符",
+            &format!("[昵称: {}]包含字符{:?}", nick_name, c),
+        ));
+    }
+    // 去掉空白与标点后再比较，避免用"a d m i n"之类的写法绕过
+    let normalized: String = nick_name
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect();
+    let sensitive = config.sensitive_words.iter().find(|word| {
+        let word: String = word
+            .chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect();
+        !word.is_empty() && normalized.contains(&word)
+    });
+    if let Some(word) = sensitive {
+        return Err(ResponseError::input_err(
+            "昵称中包含敏感词，请修改后重试",
+            &format!("[昵称: {}]包含敏感词{}", nick_name, word),
+        ));
+    }
+
+    Ok(nick_name.to_string())
+}
+
+// 校验头像地址，只允许https地址，配置了avatar_hosts时还需要域名匹配
+pub fn check_avatar_url(avatar_url: &str) -> Result<String, ResponseError> {
+    check_avatar_url_with_config(&GLOBAL_CONFIG.profile, avatar_url)
+}
+
+fn check_avatar_url_with_config(
+    config: &ProfileConfig,
+    avatar_url: &str,
+) -> Result<String, ResponseError> {
+    let avatar_url = avatar_url.trim();
+    if avatar_url.len() > AVATAR_URL_MAX_LEN {
+        return Err(ResponseError::input_err(
+            "头像地址过长",
+            &format!("头像地址长度为{}", avatar_url.len()),
+        ));
+    }
+    let url = Url::parse(avatar_url).map_err(|e| {
+        ResponseError::input_err(
+            "头像地址格式不正确",
+            &format!("[头像地址: {}]解析失败: {}", avatar_url, e),
+        )
+    })?;
+    if url.scheme() != "https" {
+        return Err(ResponseError::input_err(
+            "头像地址必须使用https",
+            &format!("[头像地址: {}]协议不是https", avatar_url),
+        ));
+    }
+    let hosts = &config.avatar_hosts;
+    let host = url.host_str().unwrap_or("");
+    if !hosts.is_empty() && !hosts.iter().any(|v| v.eq_ignore_ascii_case(host)) {
+        return Err(ResponseError::input_err(
+            "不支持该头像地址",
+            &format!("[头像地址: {}]域名不在允许列表中", avatar_url),
+        ));
+    }
+
+    Ok(url.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> ProfileConfig {
+        ProfileConfig {
+            nick_name_min_len: 2,
+            nick_name_max_len: 6,
+            sensitive_words: vec!["admin".to_string(), "管理员".to_string()],
+            ..ProfileConfig::default()
+        }
+    }
+
+    #[test]
+    fn nick_name_is_trimmed() {
+        let nick_name = check_nick_name_with_config(&config(), "  小明 abc ").unwrap();
+        assert_eq!(nick_name, "小明 abc");
+    }
+
+    #[test]
+    fn nick_name_length_counts_chars() {
+        let config = config();
+        assert!(check_nick_name_with_config(&config, "a").is_err());
+        assert!(check_nick_name_with_config(&config, "  a  ").is_err());
+        assert!(check_nick_name_with_config(&config, "一二三四五六").is_ok());
+        assert!(check_nick_name_with_config(&config, "一二三四五六七").is_err());
+    }
+
+    #[test]
+    fn nick_name_rejects_forbidden_chars() {
+        let config = config();
+        for nick_name in [
+            "a<b",
+            "a/b",
+            "a@b",
+            "a\tb",
+            "a\u{200b}b",
+            "a\u{feff}b",
+            "a\u{3000}b",
+        ] {
+            let err = check_nick_name_with_config(&config, nick_name).unwrap_err();
+            assert_eq!(err.err_code, 1, "{:?}", nick_name);
+        }
+    }
+
+    #[test]
+    fn nick_name_rejects_sensitive_words() {
+        let config = config();
+        assert!(check_nick_name_with_config(&config, "Admin1").is_err());
+        // 空白与标点不能绕过敏感词
+        assert!(check_nick_name_with_config(&config, "a d-m.i n").is_err());
+        assert!(check_nick_name_with_config(&config, "我是管理员").is_err());
+        assert!(check_nick_name_with_config(&config, "adm").is_ok());
+    }
+
+    #[test]
+    fn avatar_url_requires_https() {
+        let config = config();
+        assert!(check_avatar_url_with_config(&config, "http://example.com/a.png").is_err());
+        assert!(check_avatar_url_with_config(&config, "javascript:alert(1)").is_err());
+        assert!(check_avatar_url_with_config(&config, "not a url").is_err());
+        let url = check_avatar_url_with_config(&config, " https://example.com/a.png ").unwrap();
+        assert_eq!(url, "https://example.com/a.png");
+    }
+
+    #[test]
+    fn avatar_url_rejects_long_urls() {
+        let url = format!("https://example.com/{}", "a".repeat(AVATAR_URL_MAX_LEN));
+        assert!(check_avatar_url_with_config(&config(), &url).is_err());
+    }
+
+    #[test]
+    fn avatar_url_checks_host_allowlist() {
+        let config = ProfileConfig {
+            avatar_hosts: vec!["cdn.example.com".to_string()],
+            ..config()
+        };
+        assert!(check_avatar_url_with_config(&config, "https://CDN.example.com/a.png").is_ok());
+        assert!(check_avatar_url_with_config(&config, "https://evil.com/a.png").is_err());
+        assert!(
+            check_avatar_url_with_config(&config, "https://cdn.example.com.evil.com/a.png")
+                .is_err()
+        );
+    }
+}
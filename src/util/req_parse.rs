@@ -1,22 +1,238 @@
-use actix_web::{http::header, HttpRequest};
+use actix_web::{
+    dev::Payload,
+    http::{header, HeaderMap},
+    FromRequest, HttpRequest,
+};
+use futures::future::{ready, Ready};
+use ipnet::IpNet;
+use lazy_static::lazy_static;
+use std::net::{IpAddr, SocketAddr};
 
+use crate::config::GLOBAL_CONFIG;
 use crate::error::ResponseError;
-use crate::util::jwt::parse_access_token;
+use crate::model::role::Permission;
+use crate::util::api_key::ApiKeyIdentity;
+use crate::util::jwt::{parse_access_token, AccessTokenClaims};
 
+const BEARER_SCHEME: &str = "Bearer ";
+const API_KEY_SCHEME: &str = "ApiKey ";
+const X_FORWARDED_FOR: &str = "X-Forwarded-For";
+
+lazy_static! {
+    // 可信的反向代理，单个地址视为/32或/128
+    static ref TRUSTED_PROXIES: Vec<IpNet> = GLOBAL_CONFIG
+        .app
+        .trusted_proxies
+        .iter()
+        .map(|v| {
+            v.parse::<IpNet>()
+                .or_else(|_| v.parse::<IpAddr>().map(IpNet::from))
+                .unwrap_or_else(|_| panic!("trusted_proxies中的地址[{}]格式不正确", v))
+        })
+        .collect();
+}
+
+// 请求的认证方式
+#[derive(Debug, Clone)]
+pub enum AuthMethod {
+    AccessToken(AccessTokenClaims),
+    ApiKey(ApiKeyIdentity),
+}
+
+// 已登陆的用户，作为handler的参数时未登陆或凭证无效的请求会直接返回错误
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub user_id: i32,
+    pub method: AuthMethod,
+}
+
+impl AuthUser {
+    // 修改密码等账号安全相关的操作只接受access_token，不接受API密钥
+    pub fn access_token_claims(&self) -> Result<&AccessTokenClaims, ResponseError> {
+        match &self.method {
+            AuthMethod::AccessToken(claims) => Ok(claims),
+            AuthMethod::ApiKey(identity) => Err(ResponseError::access_token_err(
+                "该操作需要登陆后进行，不能使用API密钥",
+                &format!(
+                    "[用户ID: {}]使用API密钥[{}]访问了只接受access_token的接口",
+                    self.user_id, identity.key_id
+                ),
+            )),
+        }
+    }
+
+    // 判断当前凭证是否允许使用该权限，API密钥只能使用创建时选择的权限，access_token不受限制
+    // 用户的角色是否拥有该权限仍然需要通过permission::get_permissions查询
+    pub fn has_scope(&self, permission: Permission) -> bool {
+        match &self.method {
+            AuthMethod::AccessToken(_) => true,
+            AuthMethod::ApiKey(identity) => identity.has_scope(permission),
+        }
+    }
+}
+
+impl FromRequest for AuthUser {
+    type Error = ResponseError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(authenticate(req).and_then(|v| {
+            v.ok_or_else(|| {
+                ResponseError::access_token_err("解析错误", "无法从头部获取access_token")
+            })
+        }))
+    }
+}
+
+// 可能未登陆的用户，携带了无效的凭证时仍然返回错误
+#[derive(Debug, Clone)]
+pub struct OptionalUser(pub Option<AuthUser>);
+
+impl FromRequest for OptionalUser {
+    type Error = ResponseError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(authenticate(req).map(OptionalUser))
+    }
+}
+
+// 解析请求的认证信息，结果缓存在请求的extensions中，没有携带Authorization头部时返回None
+pub fn authenticate(req: &HttpRequest) -> Result<Option<AuthUser>, ResponseError> {
+    if let Some(auth_user) = req.extensions().get::<AuthUser>() {
+        return Ok(Some(auth_user.clone()));
+    }
+    // API密钥需要查询数据库，由api_key_middleware提前认证
+    let auth_user = if let Some(identity) = req.extensions().get::<ApiKeyIdentity>() {
+        AuthUser {
+            user_id: identity.user_id,
+            method: AuthMethod::ApiKey(identity.clone()),
+        }
+    } else if let Some(access_token) = get_access_token(req) {
+        let claims = parse_access_token(access_token)?;
+        AuthUser {
+            user_id: claims.user_id,
+            method: AuthMethod::AccessToken(claims),
+        }
+    } else if req.headers().contains_key(header::AUTHORIZATION) {
+        return Err(ResponseError::access_token_err(
+            "解析错误",
+            "Authorization头部既不是Bearer也不是ApiKey",
+        ));
+    } else {
+        return Ok(None);
+    };
+    req.extensions_mut().insert(auth_user.clone());
+    Ok(Some(auth_user))
+}
+
+// 获取Authorization: Bearer头部中的access_token
 pub fn get_access_token(req: &HttpRequest) -> Option<&str> {
     let header_maps = req.headers();
     let value = header_maps.get(header::AUTHORIZATION)?.to_str().ok()?;
-    if value.len() < 7 {
-        return None;
+    value.strip_prefix(BEARER_SCHEME).map(|v| v.trim())
+}
+
+// 获取Authorization: ApiKey头部中的API密钥
+pub fn get_api_key(req: &HttpRequest) -> Option<&str> {
+    let header_maps = req.headers();
+    let value = header_maps.get(header::AUTHORIZATION)?.to_str().ok()?;
+    value.strip_prefix(API_KEY_SCHEME).map(|v| v.trim())
+}
+
+pub fn get_user_agent(req: &HttpRequest) -> &str {
+    req.headers()
+        .get(header::USER_AGENT)
+        .map(|h| h.to_str().unwrap_or(""))
+        .unwrap_or("")
+}
+
+// 获取客户端的ip地址
+pub fn get_client_ip(req: &HttpRequest) -> String {
+    client_ip(req.peer_addr(), req.headers())
+}
+
+// 默认使用直接连接的地址，该地址是可信代理时才从X-Forwarded-For中获取客户端地址
+pub fn client_ip(peer_addr: Option<SocketAddr>, headers: &HeaderMap) -> String {
+    let peer_ip = match peer_addr {
+        Some(v) => v.ip(),
+        None => return String::new(),
+    };
+    let forwarded_for: Vec<&str> = headers
+        .get_all(X_FORWARDED_FOR)
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .collect();
+    resolve_client_ip(peer_ip, &forwarded_for, &TRUSTED_PROXIES).to_string()
+}
+
+// 从右向左跳过可信代理，第一个不可信的地址即为客户端地址，无法解析的地址之后的内容都不可信
+fn resolve_client_ip(peer_ip: IpAddr, forwarded_for: &[&str], trusted_proxies: &[IpNet]) -> IpAddr {
+    let is_trusted = |ip: &IpAddr| trusted_proxies.iter().any(|v| v.contains(ip));
+    let mut client_ip = peer_ip;
+    for addr in forwarded_for.iter().rev() {
+        if !is_trusted(&client_ip) {
+            break;
+        }
+        match parse_forwarded_addr(addr) {
+            Some(ip) => client_ip = ip,
+            None => break,
+        }
     }
-    Some(&value[7..])
+    client_ip
 }
 
-pub fn get_user_id(req: &HttpRequest) -> Result<i32, ResponseError> {
-    let access_token = get_access_token(&req).ok_or(ResponseError::access_token_err(
-        "解析错误",
-        "无法从头部获取access_token",
-    ))?;
-    let user_id = parse_access_token(access_token)?.user_id;
-    Ok(user_id)
+// X-Forwarded-For中的地址可能带有端口号
+fn parse_forwarded_addr(addr: &str) -> Option<IpAddr> {
+    let addr = addr.trim();
+    addr.parse::<IpAddr>()
+        .ok()
+        .or_else(|| addr.parse::<SocketAddr>().ok().map(|v| v.ip()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proxies() -> Vec<IpNet> {
+        vec![
+            "10.0.0.0/8".parse().unwrap(),
+            "127.0.0.1/32".parse().unwrap(),
+        ]
+    }
+
+    fn ip(v: &str) -> IpAddr {
+        v.parse().unwrap()
+    }
+
+    #[test]
+    fn untrusted_peer_ignores_forwarded_for() {
+        let client = resolve_client_ip(ip("203.0.113.7"), &["198.51.100.1"], &proxies());
+        assert_eq!(client, ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn trusted_peer_uses_forwarded_for() {
+        let client = resolve_client_ip(ip("10.0.0.2"), &["198.51.100.1"], &proxies());
+        assert_eq!(client, ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn spoofed_entries_left_of_client_are_ignored() {
+        let forwarded_for = ["1.2.3.4", " 198.51.100.1", " 10.0.0.3"];
+        let client = resolve_client_ip(ip("127.0.0.1"), &forwarded_for, &proxies());
+        assert_eq!(client, ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn invalid_entry_stops_at_last_trusted_hop() {
+        let client = resolve_client_ip(ip("10.0.0.2"), &["unknown"], &proxies());
+        assert_eq!(client, ip("10.0.0.2"));
+    }
+
+    #[test]
+    fn forwarded_addr_with_port() {
+        let client = resolve_client_ip(ip("10.0.0.2"), &["198.51.100.1:4711"], &proxies());
+        assert_eq!(client, ip("198.51.100.1"));
+    }
 }
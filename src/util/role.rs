@@ -0,0 +1,272 @@
+use chrono::{DateTime, Utc};
+use deadpool_postgres::Transaction;
+use derive_more::Display;
+
+use crate::db::Type as DBType;
+use crate::error::ResponseError;
+use crate::model::role::{Permission, RoleKey};
+
+// igame.role_grant_log中的action
+#[derive(Debug, Display, Clone, Copy)]
+pub enum GrantAction {
+    #[display(fmt = "grant")]
+    Grant,
+    #[display(fmt = "extend")]
+    Extend,
+    #[display(fmt = "revoke")]
+    Revoke,
+}
+
+// 用户当前持有的某个角色
+#[derive(Debug, Clone, Copy)]
+pub struct RoleGrant {
+    pub expire_at: Option<DateTime<Utc>>,
+}
+
+impl RoleGrant {
+    pub fn is_active(&self) -> bool {
+        match self.expire_at {
+            Some(expire_at) => expire_at > Utc::now(),
+            None => true,
+        }
+    }
+}
+
+// 根据key查询角色ID，不存在时返回input_err
+pub async fn get_role_id(
+    transaction: &Transaction<'_>,
+    role_key: &str,
+) -> Result<i32, ResponseError> {
+    let s1 = transaction
+        .prepare_typed_cached("SELECT id FROM igame.role WHERE key = $1", &[DBType::TEXT])
+        .await?;
+    match transaction.query_opt(&s1, &[&role_key]).await? {
+        Some(r1) => Ok(r1.get("id")),
+        None => Err(ResponseError::input_err(
+            "角色不存在",
+            &format!("角色[{}]不存在", role_key),
+        )),
+    }
+}
+
+// 角色是否拥有某个权限
+pub async fn role_has_permission(
+    transaction: &Transaction<'_>,
+    role_id: i32,
+    permission: Permission,
+) -> Result<bool, ResponseError> {
+    let s1 = transaction
+        .prepare_typed_cached(
+            "SELECT EXISTS(
+                SELECT 1 FROM igame.role_permission AS rp
+                INNER JOIN igame.permission AS p
+                ON rp.permission_id = p.id
+                WHERE rp.role_id = $1 AND p.key = $2
+            )",
+            &[DBType::INT4, DBType::TEXT],
+        )
+        .await?;
+    let r1 = transaction
+        .query_one(&s1, &[&role_id, &permission.key()])
+        .await?;
+    Ok(r1.get(0))
+}
+
+// 用户当前是否拥有某个未过期的角色
+pub async fn has_active_role(
+    transaction: &Transaction<'_>,
+    user_id: i32,
+    role_key: RoleKey,
+) -> Result<bool, ResponseError> {
+    let s1 = transaction
+        .prepare_typed_cached(
+            "SELECT EXISTS(
+                SELECT 1 FROM igame.user_role AS ur
+                INNER JOIN igame.role AS r
+                ON ur.role_id = r.id
+                WHERE ur.user_id = $1 AND r.key = $2
+                AND (ur.expire_at IS NULL OR ur.expire_at > now())
+            )",
+            &[DBType::INT4, DBType::TEXT],
+        )
+        .await?;
+    let r1 = transaction
+        .query_one(&s1, &[&user_id, &role_key.as_str()])
+        .await?;
+    Ok(r1.get(0))
+}
+
+// 拥有管理角色权限的角色只有管理员可以修改或授予，避免持有该权限的非管理员扩大自己的权限
+pub async fn check_manageable(
+    transaction: &Transaction<'_>,
+    operator_id: i32,
+    role_id: i32,
+    role_key: &str,
+) -> Result<(), ResponseError> {
+    if role_has_permission(transaction, role_id, Permission::ManageRole).await? {
+        check_admin(transaction, operator_id, role_key).await?;
+    }
+    Ok(())
+}
+
+// 操作者当前必须是管理员
+pub async fn check_admin(
+    transaction: &Transaction<'_>,
+    operator_id: i32,
+    role_key: &str,
+) -> Result<(), ResponseError> {
+    if !has_active_role(transaction, operator_id, RoleKey::Admin).await? {
+        return Err(ResponseError::permission_err(
+            "只有管理员可以修改或授予拥有管理角色权限的角色",
+            &format!(
+                "[用户ID: {}]不是管理员，尝试修改或授予角色[{}]",
+                operator_id, role_key
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// 为新用户授予初始角色，operator_id为空表示由系统操作
+pub async fn grant_initial_role(
+    transaction: &Transaction<'_>,
+    user_id: i32,
+    role_key: &str,
+    operator_id: Option<i32>,
+) -> Result<(), ResponseError> {
+    let role_id = get_role_id(transaction, role_key).await?;
+    set_grant(
+        transaction,
+        user_id,
+        role_id,
+        None,
+        None,
+        GrantAction::Grant,
+        operator_id,
+    )
+    .await
+}
+
+// 查询并锁定用户的角色记录，包括已经过期的记录
+pub async fn lock_grant(
+    transaction: &Transaction<'_>,
+    user_id: i32,
+    role_id: i32,
+) -> Result<Option<RoleGrant>, ResponseError> {
+    let s1 = transaction
+        .prepare_typed_cached(
+            "SELECT expire_at FROM igame.user_role WHERE user_id = $1 AND role_id = $2 FOR UPDATE",
+            &[DBType::INT4, DBType::INT4],
+        )
+        .await?;
+    let r1 = transaction.query_opt(&s1, &[&user_id, &role_id]).await?;
+    Ok(r1.map(|r1| RoleGrant {
+        expire_at: r1.get("expire_at"),
+    }))
+}
+
+// 写入或更新用户的角色记录，并记录到igame.role_grant_log
+// previous为lock_grant的结果，operator_id为空表示由系统操作
+pub async fn set_grant(
+    transaction: &Transaction<'_>,
+    user_id: i32,
+    role_id: i32,
+    previous: Option<RoleGrant>,
+    expire_at: Option<DateTime<Utc>>,
+    action: GrantAction,
+    operator_id: Option<i32>,
+) -> Result<(), ResponseError> {
+    let s1 =
+        match previous {
+            Some(_) => transaction
+                .prepare_typed_cached(
+                    "UPDATE igame.user_role SET expire_at = $3 WHERE user_id = $1 AND role_id = $2",
+                    &[DBType::INT4, DBType::INT4, DBType::TIMESTAMPTZ],
+                )
+                .await?,
+            None => transaction
+                .prepare_typed_cached(
+                    "INSERT INTO igame.user_role (user_id, role_id, expire_at) VALUES ($1, $2, $3)",
+                    &[DBType::INT4, DBType::INT4, DBType::TIMESTAMPTZ],
+                )
+                .await?,
+        };
+    transaction
+        .execute(&s1, &[&user_id, &role_id, &expire_at])
+        .await?;
+    write_log(
+        transaction,
+        user_id,
+        role_id,
+        action,
+        previous.and_then(|v| v.expire_at),
+        expire_at,
+        operator_id,
+    )
+    .await
+}
+
+// 删除用户的角色记录，并记录到igame.role_grant_log
+pub async fn delete_grant(
+    transaction: &Transaction<'_>,
+    user_id: i32,
+    role_id: i32,
+    previous: RoleGrant,
+    operator_id: Option<i32>,
+) -> Result<(), ResponseError> {
+    let s1 = transaction
+        .prepare_typed_cached(
+            "DELETE FROM igame.user_role WHERE user_id = $1 AND role_id = $2",
+            &[DBType::INT4, DBType::INT4],
+        )
+        .await?;
+    transaction.execute(&s1, &[&user_id, &role_id]).await?;
+    write_log(
+        transaction,
+        user_id,
+        role_id,
+        GrantAction::Revoke,
+        previous.expire_at,
+        None,
+        operator_id,
+    )
+    .await
+}
+
+async fn write_log(
+    transaction: &Transaction<'_>,
+    user_id: i32,
+    role_id: i32,
+    action: GrantAction,
+    previous_expire_at: Option<DateTime<Utc>>,
+    expire_at: Option<DateTime<Utc>>,
+    operator_id: Option<i32>,
+) -> Result<(), ResponseError> {
+    let s1 = transaction
+        .prepare_typed_cached(
+            "INSERT INTO igame.role_grant_log (user_id, role_id, action, previous_expire_at, expire_at, operator_id) VALUES ($1, $2, $3, $4, $5, $6)",
+            &[
+                DBType::INT4,
+                DBType::INT4,
+                DBType::TEXT,
+                DBType::TIMESTAMPTZ,
+                DBType::TIMESTAMPTZ,
+                DBType::INT4,
+            ],
+        )
+        .await?;
+    transaction
+        .execute(
+            &s1,
+            &[
+                &user_id,
+                &role_id,
+                &action.to_string(),
+                &previous_expire_at,
+                &expire_at,
+                &operator_id,
+            ],
+        )
+        .await?;
+    Ok(())
+}
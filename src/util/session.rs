@@ -0,0 +1,368 @@
+use actix_web::HttpRequest;
+use chrono::{DateTime, Duration, Utc};
+use deadpool_postgres::Client;
+use futures::future::try_join;
+
+use crate::config::GLOBAL_CONFIG;
+use crate::db::Type as DBType;
+use crate::error::{is_db_zero_line_error, ResponseError};
+use crate::model::user::PostUserLoginOutput;
+use crate::util::{
+    account, jwt,
+    req_parse::{get_client_ip, get_user_agent},
+};
+
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+// 签发凭证时所使用的设备信息
+pub struct SessionDevice {
+    pub device: String,
+    pub ip: String,
+}
+
+impl SessionDevice {
+    pub fn from_request(req: &HttpRequest) -> Self {
+        Self {
+            device: device_label(get_user_agent(req)),
+            ip: get_client_ip(req),
+        }
+    }
+}
+
+// 为用户创建一个新的会话，并签发一对新的凭证
+pub async fn create_session(
+    client: &Client,
+    user_id: i32,
+    device: &SessionDevice,
+) -> Result<TokenPair, ResponseError> {
+    let family_id = jwt::generate_token_id();
+    issue_tokens(client, user_id, &family_id, &Utc::now(), device).await
+}
+
+// 签发两步验证的challenge_token，此时不创建会话
+pub fn totp_challenge(user_id: i32) -> Result<PostUserLoginOutput, ResponseError> {
+    Ok(PostUserLoginOutput {
+        user_id,
+        totp_required: true,
+        challenge_token: Some(jwt::generate_totp_challenge_token(user_id)?),
+        access_token: None,
+        refresh_token: None,
+    })
+}
+
+// 记录登陆时间并创建新的会话
+pub async fn finish_login(
+    client: &Client,
+    req: &HttpRequest,
+    user_id: i32,
+) -> Result<PostUserLoginOutput, ResponseError> {
+    let s1 = client
+        .prepare_typed_cached(
+            "UPDATE igame.user
+            SET login_at = $1
+            WHERE id = $2",
+            &[DBType::TIMESTAMPTZ, DBType::INT4],
+        )
+        .await?;
+    client.execute(&s1, &[&Utc::now(), &user_id]).await?;
+    // 冷静期内重新登陆即取消注销
+    if account::cancel_deletion(client, user_id).await? {
+        tracing::info!("[用户ID: {}]登陆后取消了账号注销", user_id);
+    }
+
+    let tokens = create_session(client, user_id, &SessionDevice::from_request(req)).await?;
+    Ok(PostUserLoginOutput {
+        user_id,
+        totp_required: false,
+        challenge_token: None,
+        access_token: Some(tokens.access_token),
+        refresh_token: Some(tokens.refresh_token),
+    })
+}
+
+// 使用refresh_token轮换出一对新的凭证，旧的refresh_token随即失效
+// 如果检测到已使用过的refresh_token被再次使用，则吊销整个会话
+pub async fn rotate_session(
+    client: &Client,
+    refresh_token: &str,
+    device: &SessionDevice,
+) -> Result<(i32, TokenPair), ResponseError> {
+    let claims = jwt::parse_refresh_token(refresh_token)?;
+
+    let s1 = client
+        .prepare_typed_cached(
+            "UPDATE igame.session
+            SET used_at = now()
+            WHERE id = $1 AND user_id = $2
+            AND used_at IS NULL AND revoked_at IS NULL AND expire_at > now()
+            RETURNING family_id, login_at",
+            &[DBType::TEXT, DBType::INT4],
+        )
+        .await?;
+
+    match client.query_one(&s1, &[&claims.jti, &claims.user_id]).await {
+        Ok(r1) => {
+            let family_id: String = r1.get("family_id");
+            let login_at: DateTime<Utc> = r1.get("login_at");
+            let tokens =
+                issue_tokens(client, claims.user_id, &family_id, &login_at, device).await?;
+            Ok((claims.user_id, tokens))
+        }
+        Err(e) => match is_db_zero_line_error(&e) {
+            true => Err(reject_refresh_token(client, claims.user_id, &claims.jti).await?),
+            false => Err(e.into()),
+        },
+    }
+}
+
+// 使用refresh_token吊销其所属的会话
+pub async fn revoke_session_by_refresh_token(
+    client: &Client,
+    refresh_token: &str,
+) -> Result<i32, ResponseError> {
+    let claims = jwt::parse_refresh_token(refresh_token)?;
+
+    let s1 = client
+        .prepare_typed_cached(
+            "SELECT family_id FROM igame.session WHERE id = $1 AND user_id = $2",
+            &[DBType::TEXT, DBType::INT4],
+        )
+        .await?;
+    let r1 = client
+        .query_one(&s1, &[&claims.jti, &claims.user_id])
+        .await
+        .map_err(|e| match is_db_zero_line_error(&e) {
+            true => ResponseError::refresh_token_err(
+                "用户刷新凭证无效，请重新登陆",
+                &format!(
+                    "[用户ID: {}]refresh_token[{}]不存在",
+                    claims.user_id, claims.jti
+                ),
+            ),
+            false => ResponseError::from(e),
+        })?;
+    let family_id: String = r1.get("family_id");
+    revoke_session(client, claims.user_id, &family_id).await?;
+
+    Ok(claims.user_id)
+}
+
+// 吊销用户的某个会话，返回该会话是否存在
+pub async fn revoke_session(
+    client: &Client,
+    user_id: i32,
+    family_id: &str,
+) -> Result<bool, ResponseError> {
+    let s1 = client
+        .prepare_typed_cached(
+            "UPDATE igame.session
+            SET revoked_at = now()
+            WHERE user_id = $1 AND family_id = $2 AND revoked_at IS NULL",
+            &[DBType::INT4, DBType::TEXT],
+        )
+        .await?;
+    let count = client.execute(&s1, &[&user_id, &family_id]).await?;
+    // 只记录确实属于该用户的会话，避免通过不存在或他人的family_id填充吊销列表
+    if count > 0 {
+        jwt::add_revoked_session(family_id, Utc::now().timestamp() as u64);
+    }
+
+    Ok(count > 0)
+}
+
+// 吊销用户的所有会话
+pub async fn revoke_user_sessions(client: &Client, user_id: i32) -> Result<(), ResponseError> {
+    let s1 = client
+        .prepare_typed_cached(
+            "UPDATE igame.session
+            SET revoked_at = now()
+            WHERE user_id = $1 AND revoked_at IS NULL
+            RETURNING family_id",
+            &[DBType::INT4],
+        )
+        .await?;
+    let r1s = client.query(&s1, &[&user_id]).await?;
+    let now = Utc::now().timestamp() as u64;
+    for r1 in r1s {
+        jwt::add_revoked_session(r1.get("family_id"), now);
+    }
+    Ok(())
+}
+
+// 从数据库中加载最近被吊销的会话，返回加载的数量
+// 启动时调用保证重启后吊销列表依然有效，之后定时调用以同步其他实例吊销的会话
+pub async fn load_revoked_sessions(client: &Client) -> Result<usize, ResponseError> {
+    let s1 = client
+        .prepare_typed_cached(
+            "SELECT family_id, max(revoked_at) AS revoked_at
+            FROM igame.session
+            WHERE revoked_at > $1
+            GROUP BY family_id",
+            &[DBType::TIMESTAMPTZ],
+        )
+        .await?;
+    let since = Utc::now() - Duration::seconds(GLOBAL_CONFIG.jwt.access_token_expire as i64);
+    let r1s = client.query(&s1, &[&since]).await?;
+    let count = r1s.len();
+    jwt::add_revoked_sessions(
+        r1s.iter()
+            .map(|r1| {
+                let revoked_at: DateTime<Utc> = r1.get("revoked_at");
+                (r1.get("family_id"), revoked_at.timestamp() as u64)
+            })
+            .collect(),
+    );
+    Ok(count)
+}
+
+async fn issue_tokens(
+    client: &Client,
+    user_id: i32,
+    family_id: &str,
+    login_at: &DateTime<Utc>,
+    device: &SessionDevice,
+) -> Result<TokenPair, ResponseError> {
+    let s1 = client
+        .prepare_typed_cached(
+            "INSERT INTO igame.session (id, family_id, user_id, device, ip, login_at, expire_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            &[
+                DBType::TEXT,
+                DBType::TEXT,
+                DBType::INT4,
+                DBType::TEXT,
+                DBType::TEXT,
+                DBType::TIMESTAMPTZ,
+                DBType::TIMESTAMPTZ,
+            ],
+        )
+        .await?;
+
+    let jti = jwt::generate_token_id();
+    let expire_at = Utc::now() + Duration::seconds(GLOBAL_CONFIG.jwt.refresh_token_expire as i64);
+    client
+        .execute(
+            &s1,
+            &[
+                &jti,
+                &family_id,
+                &user_id,
+                &device.device,
+                &device.ip,
+                login_at,
+                &expire_at,
+            ],
+        )
+        .await?;
+
+    Ok(TokenPair {
+        access_token: jwt::generate_access_token(user_id, family_id)?,
+        refresh_token: jwt::generate_refresh_token(user_id, &jti)?,
+    })
+}
+
+// 根据User-Agent生成简短的设备描述，例如"Chrome on Windows"
+fn device_label(user_agent: &str) -> String {
+    if user_agent.is_empty() {
+        return "未知设备".to_string();
+    }
+    let os = [
+        ("Windows", "Windows"),
+        ("iPhone", "iPhone"),
+        ("iPad", "iPad"),
+        ("Android", "Android"),
+        ("Mac OS X", "macOS"),
+        ("Linux", "Linux"),
+    ]
+    .iter()
+    .find(|(pattern, _)| user_agent.contains(pattern))
+    .map(|(_, name)| *name);
+    // 顺序很重要，Edge跟Chrome的User-Agent中都包含Chrome与Safari
+    let client = [
+        ("Edg/", "Edge"),
+        ("OPR/", "Opera"),
+        ("Firefox/", "Firefox"),
+        ("Chrome/", "Chrome"),
+        ("Safari/", "Safari"),
+    ]
+    .iter()
+    .find(|(pattern, _)| user_agent.contains(pattern))
+    .map(|(_, name)| name.to_string())
+    // 非浏览器客户端（例如启动器），取User-Agent的产品名
+    .unwrap_or_else(|| {
+        user_agent
+            .split_whitespace()
+            .next()
+            .unwrap_or("")
+            .chars()
+            .take(64)
+            .collect()
+    });
+    match os {
+        Some(os) => format!("{} on {}", client, os),
+        None => client,
+    }
+}
+
+// 判断refresh_token无法使用的原因，并在重复使用时吊销整个会话
+async fn reject_refresh_token(
+    client: &Client,
+    user_id: i32,
+    jti: &str,
+) -> Result<ResponseError, ResponseError> {
+    let (s1, s2) = try_join(
+        client.prepare_typed_cached(
+            "SELECT family_id, used_at, revoked_at
+            FROM igame.session
+            WHERE id = $1 AND user_id = $2",
+            &[DBType::TEXT, DBType::INT4],
+        ),
+        client.prepare_typed_cached(
+            "UPDATE igame.session
+            SET revoked_at = now()
+            WHERE family_id = $1 AND revoked_at IS NULL",
+            &[DBType::TEXT],
+        ),
+    )
+    .await?;
+
+    let r1 = match client.query_opt(&s1, &[&jti, &user_id]).await? {
+        Some(v) => v,
+        None => {
+            return Ok(ResponseError::refresh_token_err(
+                "用户刷新凭证无效，请重新登陆",
+                &format!("[用户ID: {}]refresh_token[{}]不存在", user_id, jti),
+            ))
+        }
+    };
+    let family_id: String = r1.get("family_id");
+    let used_at: Option<DateTime<Utc>> = r1.get("used_at");
+    let revoked_at: Option<DateTime<Utc>> = r1.get("revoked_at");
+
+    // 已使用过的refresh_token被再次使用，说明凭证可能已泄露
+    if revoked_at.is_none() && used_at.is_some() {
+        client.execute(&s2, &[&family_id]).await?;
+        jwt::add_revoked_session(&family_id, Utc::now().timestamp() as u64);
+        tracing::warn!(
+            "[用户ID: {}]refresh_token[{}]被重复使用，已吊销会话[{}]",
+            user_id,
+            jti,
+            family_id
+        );
+        return Ok(ResponseError::refresh_token_err(
+            "用户刷新凭证已被使用，请重新登陆",
+            &format!("[用户ID: {}]refresh_token[{}]被重复使用", user_id, jti),
+        ));
+    }
+
+    Ok(ResponseError::refresh_token_err(
+        "用户刷新凭证已失效，请重新登陆",
+        &format!(
+            "[用户ID: {}]refresh_token[{}]已被吊销或已过期",
+            user_id, jti
+        ),
+    ))
+}
@@ -0,0 +1,273 @@
+use data_encoding::BASE32_NOPAD;
+use deadpool_postgres::Client;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha1::Sha1;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::GLOBAL_CONFIG;
+use crate::db::Type as DBType;
+use crate::error::ResponseError;
+use crate::model::role::RoleKey;
+
+// RFC 6238的默认参数，绝大多数验证器应用只支持这一组参数
+const TIME_STEP: u64 = 30;
+const CODE_DIGITS: usize = 6;
+// 允许前后各偏差一个时间步，容忍客户端的时钟误差
+const ALLOWED_SKEW: i64 = 1;
+const SECRET_LEN: usize = 20;
+const RECOVERY_CODE_CHARSET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+const RECOVERY_CODE_LEN: usize = 10;
+
+// 生成新的两步验证密钥，以base32编码保存
+pub fn generate_secret() -> String {
+    BASE32_NOPAD.encode(&rand::thread_rng().gen::<[u8; SECRET_LEN]>())
+}
+
+// 生成供验证器应用扫描的otpauth地址
+pub fn otpauth_uri(account: &str, secret: &str) -> String {
+    let issuer = &GLOBAL_CONFIG.totp.issuer;
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        percent_encode(issuer),
+        percent_encode(account),
+        secret,
+        percent_encode(issuer),
+        CODE_DIGITS,
+        TIME_STEP
+    )
+}
+
+// 校验验证码，成功时返回验证码所属的时间步
+pub fn verify_code(secret: &str, code: &str) -> Option<i64> {
+    if !is_totp_code(code) {
+        return None;
+    }
+    let key = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    let current_step = (SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        / TIME_STEP) as i64;
+    (-ALLOWED_SKEW..=ALLOWED_SKEW)
+        .map(|skew| current_step + skew)
+        .find(|step| constant_time_eq(code_at(&key, *step as u64).as_bytes(), code.as_bytes()))
+}
+
+// 生成一组新的恢复码，格式为xxxxx-xxxxx
+pub fn generate_recovery_codes() -> Vec<String> {
+    let mut rng = rand::thread_rng();
+    (0..GLOBAL_CONFIG.totp.recovery_code_count)
+        .map(|_| {
+            let code: String = (0..RECOVERY_CODE_LEN)
+                .map(|_| {
+                    RECOVERY_CODE_CHARSET[rng.gen_range(0..RECOVERY_CODE_CHARSET.len())] as char
+                })
+                .collect();
+            format!(
+                "{}-{}",
+                &code[..RECOVERY_CODE_LEN / 2],
+                &code[RECOVERY_CODE_LEN / 2..]
+            )
+        })
+        .collect()
+}
+
+// 恢复码本身是高熵的随机值，使用快速哈希保存即可
+pub fn hash_recovery_code(code: &str) -> Vec<u8> {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    blake3::hash(normalized.as_bytes()).as_bytes().to_vec()
+}
+
+// 判断输入是验证器应用生成的验证码还是恢复码
+pub fn is_totp_code(code: &str) -> bool {
+    code.len() == CODE_DIGITS && code.bytes().all(|c| c.is_ascii_digit())
+}
+
+// 查询权限时附加在igame.user_role AS ur上的过滤条件
+// 开启require_for_admin后，未启用两步验证的管理员不具备管理员角色的权限
+pub fn admin_role_filter() -> String {
+    if !GLOBAL_CONFIG.totp.require_for_admin {
+        return String::new();
+    }
+    format!(
+        "AND (ur.role_id NOT IN (SELECT id FROM igame.role WHERE key = '{}') OR EXISTS(
+            SELECT 1 FROM igame.user_totp WHERE user_id = $1 AND enabled = true
+        ))",
+        RoleKey::Admin.as_str()
+    )
+}
+
+// 判断用户是否开启了两步验证
+pub async fn is_enabled(client: &Client, user_id: i32) -> Result<bool, ResponseError> {
+    let s1 = client
+        .prepare_typed_cached(
+            "SELECT EXISTS(SELECT 1 FROM igame.user_totp WHERE user_id = $1 AND enabled = true)",
+            &[DBType::INT4],
+        )
+        .await?;
+    let r1 = client.query_one(&s1, &[&user_id]).await?;
+    Ok(r1.get(0))
+}
+
+// 使用验证码或恢复码完成两步验证
+pub async fn verify_second_factor(
+    client: &Client,
+    user_id: i32,
+    code: &str,
+) -> Result<bool, ResponseError> {
+    let code = code.trim();
+    if is_totp_code(code) {
+        let s1 = client
+            .prepare_typed_cached(
+                "SELECT secret FROM igame.user_totp WHERE user_id = $1 AND enabled = true",
+                &[DBType::INT4],
+            )
+            .await?;
+        let secret: String = match client.query_opt(&s1, &[&user_id]).await? {
+            Some(r1) => r1.get("secret"),
+            None => return Ok(false),
+        };
+        let step = match verify_code(&secret, code) {
+            Some(v) => v,
+            None => return Ok(false),
+        };
+        // 只接受比上次更新的时间步，同一个验证码不能被使用两次
+        let s2 = client
+            .prepare_typed_cached(
+                "UPDATE igame.user_totp
+                SET last_used_step = $2
+                WHERE user_id = $1 AND (last_used_step IS NULL OR last_used_step < $2)",
+                &[DBType::INT4, DBType::INT8],
+            )
+            .await?;
+        return Ok(client.execute(&s2, &[&user_id, &step]).await? > 0);
+    }
+
+    let s1 = client
+        .prepare_typed_cached(
+            "UPDATE igame.user_recovery_code
+            SET used_at = now()
+            WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL",
+            &[DBType::INT4, DBType::BYTEA],
+        )
+        .await?;
+    let count = client
+        .execute(&s1, &[&user_id, &hash_recovery_code(code)])
+        .await?;
+    if count > 0 {
+        tracing::warn!("[用户ID: {}]使用了一个两步验证恢复码", user_id);
+    }
+    Ok(count > 0)
+}
+
+// 生成一组新的恢复码并替换掉旧的恢复码
+pub async fn replace_recovery_codes(
+    client: &Client,
+    user_id: i32,
+) -> Result<Vec<String>, ResponseError> {
+    let s1 = client
+        .prepare_typed_cached(
+            "WITH d AS (
+                DELETE FROM igame.user_recovery_code WHERE user_id = $1
+            )
+            INSERT INTO igame.user_recovery_code (user_id, code_hash)
+            SELECT $1, unnest($2::BYTEA[])",
+            &[DBType::INT4, DBType::BYTEA_ARRAY],
+        )
+        .await?;
+    let codes = generate_recovery_codes();
+    let hashes: Vec<Vec<u8>> = codes.iter().map(|v| hash_recovery_code(v)).collect();
+    client.execute(&s1, &[&user_id, &hashes]).await?;
+    Ok(codes)
+}
+
+fn code_at(key: &[u8], step: u64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).unwrap();
+    mac.update(&step.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+    // RFC 4226中的动态截断
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    format!(
+        "{:0width$}",
+        binary % 10u32.pow(CODE_DIGITS as u32),
+        width = CODE_DIGITS
+    )
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|c| match c {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (c as char).to_string()
+            }
+            _ => format!("%{:02X}", c),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 6238附录B与RFC 4226附录D使用的SHA1密钥
+    const RFC_KEY: &[u8] = b"12345678901234567890";
+
+    // RFC 6238附录B的SHA1测试向量，原文为8位验证码，这里取其后6位
+    #[test]
+    fn rfc6238_test_vectors() {
+        let vectors = [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+            (20000000000, "353130"),
+        ];
+        for (time, code) in vectors {
+            assert_eq!(code_at(RFC_KEY, time / TIME_STEP), code, "T = {}", time);
+        }
+    }
+
+    // RFC 4226附录D的HOTP测试向量，计数器即时间步
+    #[test]
+    fn rfc4226_test_vectors() {
+        let codes = [
+            "755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583",
+            "399871", "520489",
+        ];
+        for (step, code) in codes.iter().enumerate() {
+            assert_eq!(code_at(RFC_KEY, step as u64), *code, "counter = {}", step);
+        }
+    }
+
+    #[test]
+    fn verify_code_accepts_current_step() {
+        let secret = BASE32_NOPAD.encode(RFC_KEY);
+        let step = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            / TIME_STEP;
+        let code = code_at(RFC_KEY, step);
+        let verified = verify_code(&secret, &code).unwrap();
+        // 测试执行时可能恰好跨过时间步
+        assert!((verified - step as i64).abs() <= ALLOWED_SKEW);
+        assert_eq!(verify_code(&secret, "12345a"), None);
+        assert_eq!(verify_code(&secret, "1234567"), None);
+    }
+}